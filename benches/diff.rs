@@ -0,0 +1,179 @@
+//! Throughput of diffing, patching and merging across file sizes and change
+//! densities. Enable the `similar`, `diffy` and `gix` features to compare
+//! against the pure-Rust diff crates on the same inputs.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libxdiff::MMFile;
+
+const LINE_COUNTS: [usize; 3] = [100, 1_000, 10_000];
+/// Changed lines per thousand
+const DENSITIES: [usize; 3] = [1, 10, 100];
+
+/// Deterministic xorshift generator so every run diffs the same inputs
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn base_lines(lines: usize) -> Vec<String> {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    (0..lines)
+        .map(|i| format!("line {} {:016x}\n", i, rng.next()))
+        .collect()
+}
+
+/// Replace, delete or insert roughly `density` lines per thousand, only
+/// touching lines where `select` returns true
+fn edit(lines: &[String], density: usize, seed: u64, select: impl Fn(usize) -> bool) -> String {
+    let mut rng = Rng(seed);
+    let mut out = String::new();
+    for (i, line) in lines.iter().enumerate() {
+        if !select(i) || (rng.next() % 1000) as usize >= density {
+            out.push_str(line);
+            continue;
+        }
+        match rng.next() % 3 {
+            0 => out.push_str(&format!("changed {:x}\n", rng.next())),
+            1 => {}
+            _ => {
+                out.push_str(line);
+                out.push_str(&format!("inserted {:x}\n", rng.next()));
+            }
+        }
+    }
+    out
+}
+
+/// Every size and density as (parameter label, old text, new text)
+fn inputs() -> Vec<(String, String, String)> {
+    let mut inputs = Vec::new();
+    for lines in LINE_COUNTS {
+        let base = base_lines(lines);
+        for density in DENSITIES {
+            let new = edit(&base, density, lines as u64 + density as u64, |_| true);
+            inputs.push((
+                format!("{}lines/{}permille", lines, density),
+                base.concat(),
+                new,
+            ));
+        }
+    }
+    inputs
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    for (label, old, new) in inputs() {
+        group.throughput(Throughput::Bytes(old.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("libxdiff", &label),
+            &(old, new),
+            |b, (old, new)| {
+                let old = MMFile::from_bytes(old.as_bytes());
+                let new = MMFile::from_bytes(new.as_bytes());
+                b.iter(|| black_box(old.compute_patch(&new).unwrap()))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_patch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("patch");
+    for (label, old, new) in inputs() {
+        group.throughput(Throughput::Bytes(old.len() as u64));
+        let mut old = MMFile::from_bytes(old.as_bytes());
+        let mut patch = old
+            .compute_patch(&MMFile::from_bytes(new.as_bytes()))
+            .unwrap();
+        group.bench_function(BenchmarkId::new("libxdiff", &label), |b| {
+            b.iter(|| black_box(old.apply_patch(&mut patch).unwrap()))
+        });
+    }
+    group.finish();
+}
+
+fn bench_merge(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merge");
+    for lines in LINE_COUNTS {
+        let base_text = base_lines(lines);
+        for density in DENSITIES {
+            // each side edits a different half of the file, so the merge is clean
+            let ours = edit(&base_text, density, 1, |i| i < lines / 2);
+            let theirs = edit(&base_text, density, 2, |i| i >= lines / 2);
+            let base_bytes = base_text.concat();
+            group.throughput(Throughput::Bytes(base_bytes.len() as u64));
+            let mut base = MMFile::from_bytes(base_bytes.as_bytes());
+            let mut ours = MMFile::from_bytes(ours.as_bytes());
+            let mut theirs = MMFile::from_bytes(theirs.as_bytes());
+            let label = format!("{}lines/{}permille", lines, density);
+            group.bench_function(BenchmarkId::new("libxdiff", &label), |b| {
+                b.iter(|| black_box(MMFile::merge3(&mut base, &mut ours, &mut theirs).unwrap()))
+            });
+        }
+    }
+    group.finish();
+}
+
+/// The same unified diffs computed by libxdiff and by pure-Rust crates
+fn bench_compare(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compare");
+    for (label, old, new) in inputs() {
+        group.throughput(Throughput::Bytes(old.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::new("libxdiff", &label),
+            &(&old, &new),
+            |b, (old, new)| {
+                let old = MMFile::from_bytes(old.as_bytes());
+                let new = MMFile::from_bytes(new.as_bytes());
+                b.iter(|| black_box(old.compute_patch(&new).unwrap()))
+            },
+        );
+        #[cfg(feature = "similar")]
+        group.bench_with_input(
+            BenchmarkId::new("similar", &label),
+            &(&old, &new),
+            |b, (old, new)| {
+                b.iter(|| {
+                    black_box(
+                        similar::TextDiff::from_lines(old.as_str(), new.as_str())
+                            .unified_diff()
+                            .to_string(),
+                    )
+                })
+            },
+        );
+        #[cfg(feature = "diffy")]
+        group.bench_with_input(
+            BenchmarkId::new("diffy", &label),
+            &(&old, &new),
+            |b, (old, new)| b.iter(|| black_box(diffy::create_patch(old, new).to_string())),
+        );
+        #[cfg(feature = "gix")]
+        group.bench_with_input(
+            BenchmarkId::new("imara-diff", &label),
+            &(&old, &new),
+            |b, (old, new)| {
+                use imara_diff::{intern::InternedInput, Algorithm, UnifiedDiffBuilder};
+                b.iter(|| {
+                    let input = InternedInput::new(old.as_str(), new.as_str());
+                    black_box(imara_diff::diff(
+                        Algorithm::Myers,
+                        &input,
+                        UnifiedDiffBuilder::new(&input),
+                    ))
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff, bench_patch, bench_merge, bench_compare);
+criterion_main!(benches);
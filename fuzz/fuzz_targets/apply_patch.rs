@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libxdiff::{MMFile, Patch};
+
+// arbitrary patch text that passes validation must never crash libxdiff,
+// whether or not it applies
+fuzz_target!(|input: (MMFile, &[u8])| {
+    let (mut file, text) = input;
+    if let Ok(mut patch) = Patch::from_bytes(text) {
+        let _ = file.apply_patch(&mut patch);
+        let _ = file.apply_patch_reverse(&mut patch);
+    }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libxdiff::MMFile;
+
+// a patch computed between two files must turn the first into the second
+fuzz_target!(|files: (MMFile, MMFile)| {
+    let (mut old, new) = files;
+    let mut patch = old.compute_patch(&new).unwrap();
+    let patched = old.apply_patch(&mut patch).unwrap();
+    assert_eq!(patched.as_slice(), new.as_slice());
+});
@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libxdiff::MMFile;
+
+fuzz_target!(|files: (MMFile, MMFile, MMFile)| {
+    let (mut base, mut ours, mut theirs) = files;
+    let _ = MMFile::merge3(&mut base, &mut ours, &mut theirs);
+});
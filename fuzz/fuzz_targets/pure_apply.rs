@@ -0,0 +1,15 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use libxdiff::pure;
+
+// input is a base file and patch text separated by the first NUL; applying
+// arbitrary patch text must not panic, and a diff from the base to the patch
+// text must reproduce it
+fuzz_target!(|data: &[u8]| {
+    let split = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    let (base, text) = (&data[..split], data.get(split + 1..).unwrap_or(&[]));
+    let _ = pure::apply(base, text);
+    let patch = pure::diff(base, text, 3);
+    assert_eq!(pure::apply(base, &patch).unwrap(), text);
+});
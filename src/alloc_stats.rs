@@ -0,0 +1,119 @@
+use core::{
+    ffi::{c_uint, c_void},
+    ptr::null_mut,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use libc::{free, malloc, realloc, size_t};
+
+/// Every allocation is prefixed with its requested size so that `free` can
+/// account for it. 16 bytes keeps the returned pointer maximally aligned.
+const HEADER: usize = 16;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static FREES: AtomicUsize = AtomicUsize::new(0);
+static REALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Snapshot of the memory libxdiff has requested through this crate's allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocStats {
+    /// Bytes currently allocated by libxdiff
+    pub live_bytes: usize,
+    /// Highest value `live_bytes` has reached
+    pub peak_bytes: usize,
+    /// Number of calls to `malloc`
+    pub allocations: usize,
+    /// Number of calls to `free` with a non-null pointer
+    pub frees: usize,
+    /// Number of calls to `realloc`
+    pub reallocations: usize,
+}
+
+/// Get the current allocation statistics for libxdiff's internal allocations.
+/// Counters are process-wide and include allocations made on any thread.
+pub fn alloc_stats() -> AllocStats {
+    AllocStats {
+        live_bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        peak_bytes: PEAK_BYTES.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        frees: FREES.load(Ordering::Relaxed),
+        reallocations: REALLOCATIONS.load(Ordering::Relaxed),
+    }
+}
+
+/// Reset the peak to the current live byte count, so the peak of a single
+/// operation can be measured
+pub fn reset_peak_alloc() {
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+fn grow(size: usize) {
+    let live = LIVE_BYTES.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+fn shrink(size: usize) {
+    LIVE_BYTES.fetch_sub(size, Ordering::Relaxed);
+}
+
+/// Write the size header and return the pointer handed to libxdiff
+unsafe fn tag(base: *mut c_void, size: usize) -> *mut c_void {
+    if base.is_null() {
+        return null_mut();
+    }
+    unsafe {
+        (base as *mut usize).write(size);
+        (base as *mut u8).add(HEADER) as *mut c_void
+    }
+}
+
+/// Recover the base pointer and recorded size of an allocation
+unsafe fn untag(ptr: *mut c_void) -> (*mut c_void, usize) {
+    unsafe {
+        let base = (ptr as *mut u8).sub(HEADER) as *mut c_void;
+        (base, (base as *mut usize).read())
+    }
+}
+
+pub(crate) unsafe extern "C" fn counting_malloc(_obj: *mut c_void, size: c_uint) -> *mut c_void {
+    let size = size as usize;
+    let ptr = unsafe { tag(malloc((size + HEADER) as size_t), size) };
+    if !ptr.is_null() {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        grow(size);
+    }
+    ptr
+}
+
+pub(crate) unsafe extern "C" fn counting_free(_obj: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let (base, size) = unsafe { untag(ptr) };
+    FREES.fetch_add(1, Ordering::Relaxed);
+    shrink(size);
+    unsafe { free(base) }
+}
+
+pub(crate) unsafe extern "C" fn counting_realloc(
+    obj: *mut c_void,
+    ptr: *mut c_void,
+    size: c_uint,
+) -> *mut c_void {
+    if ptr.is_null() {
+        return unsafe { counting_malloc(obj, size) };
+    }
+    let size = size as usize;
+    let (base, old_size) = unsafe { untag(ptr) };
+    let new_base = unsafe { realloc(base, (size + HEADER) as size_t) };
+    if new_base.is_null() {
+        // the original allocation is untouched
+        return null_mut();
+    }
+    REALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    shrink(old_size);
+    grow(size);
+    unsafe { tag(new_base, size) }
+}
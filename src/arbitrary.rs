@@ -0,0 +1,39 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{DiffOptions, HunkNumbering, MMFile, Patch};
+
+impl<'a> Arbitrary<'a> for MMFile {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(MMFile::from_bytes(u.arbitrary()?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for HunkNumbering {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(if u.arbitrary()? {
+            HunkNumbering::Absolute
+        } else {
+            HunkNumbering::Relative
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for DiffOptions {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(DiffOptions::new()
+            .context_len(u.int_in_range(0..=8)?)
+            .minimal(u.arbitrary()?)
+            .numbering(u.arbitrary()?))
+    }
+}
+
+/// Patches are generated by diffing two arbitrary files, so they are always
+/// well-formed
+impl<'a> Arbitrary<'a> for Patch {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let old: MMFile = u.arbitrary()?;
+        let new: MMFile = u.arbitrary()?;
+        old.compute_patch(&new)
+            .map_err(|_| arbitrary::Error::IncorrectFormat)
+    }
+}
@@ -0,0 +1,140 @@
+use core::{
+    ffi::{c_int, c_void},
+    ptr::{addr_of, addr_of_mut},
+};
+
+use libxdiff_sys::{xdemitcb_t, xdl_bdiff, xdl_bpatch, xdl_rabdiff};
+
+use crate::{
+    error::clear_errno, mmfile::write_blocks_cb, BDiffAlgorithm, BDiffOptions, Error, MMBlocks,
+    MMFile,
+};
+
+/// Size of the header libxdiff writes at the start of every binary patch: a
+/// fingerprint of the source file followed by its size
+pub(crate) const BPATCH_HEADER_LEN: usize = 8;
+
+/// A binary delta in libxdiff's format, produced by [`MMFile::bdiff`] or
+/// [`MMFile::rabdiff`] and applied with [`MMFile::apply_binary_patch`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinaryPatch {
+    pub(crate) file: MMFile,
+}
+
+impl BinaryPatch {
+    /// Wrap the raw bytes of a binary patch. Returns `Err` if the bytes are
+    /// too short to contain the patch header.
+    pub fn from_bytes(bytes: &[u8]) -> Result<BinaryPatch, Error> {
+        if bytes.len() < BPATCH_HEADER_LEN {
+            return Err(Error::Invalid(format!(
+                "binary patch is {} bytes, shorter than its {} byte header",
+                bytes.len(),
+                BPATCH_HEADER_LEN
+            )));
+        }
+        Ok(BinaryPatch {
+            file: MMFile::from_bytes(bytes),
+        })
+    }
+
+    /// Get a view of the patch as a slice
+    pub fn as_slice(&self) -> &[u8] {
+        self.file.as_slice()
+    }
+
+    /// Convert this patch into an MMFile containing its bytes
+    pub fn to_mmfile(self) -> MMFile {
+        self.file
+    }
+}
+
+impl MMFile {
+    /// Compute a binary delta to turn self into other using libxdiff's
+    /// block-matching algorithm with default options
+    pub fn bdiff(&self, other: &MMFile) -> Result<BinaryPatch, Error> {
+        self.bdiff_with(other, &BDiffOptions::new())
+    }
+
+    /// Compute a binary delta to turn self into other
+    pub fn bdiff_with(&self, other: &MMFile, opts: &BDiffOptions) -> Result<BinaryPatch, Error> {
+        if opts.algorithm == BDiffAlgorithm::Rabin {
+            return self.rabdiff(other);
+        }
+        if opts.block_size == 0 {
+            return Err(Error::Invalid(
+                "bdiff block size must be positive".to_owned(),
+            ));
+        }
+        let mut output = MMBlocks::new();
+        let mut emit_struct = xdemitcb_t {
+            priv_: addr_of_mut!(output) as *mut c_void,
+            outf: Some(write_blocks_cb),
+        };
+        let params = opts.bdiffparam();
+        span!("xdl_bdiff", block_size = opts.block_size);
+        clear_errno();
+        let err = unsafe {
+            xdl_bdiff(
+                &mut self.view(),
+                &mut other.view(),
+                addr_of!(params),
+                addr_of_mut!(emit_struct),
+            )
+        };
+        if err != 0 {
+            return Err(Error::failed("bdiff", err));
+        }
+        Ok(BinaryPatch {
+            file: output.to_mmfile(),
+        })
+    }
+
+    /// Compute a binary delta to turn self into other using libxdiff's
+    /// Rabin fingerprint algorithm, which finds more matches than
+    /// [`bdiff`](Self::bdiff) at the cost of speed
+    pub fn rabdiff(&self, other: &MMFile) -> Result<BinaryPatch, Error> {
+        let mut output = MMBlocks::new();
+        let mut emit_struct = xdemitcb_t {
+            priv_: addr_of_mut!(output) as *mut c_void,
+            outf: Some(write_blocks_cb),
+        };
+        span!("xdl_rabdiff");
+        clear_errno();
+        let err = unsafe {
+            xdl_rabdiff(
+                &mut self.view(),
+                &mut other.view(),
+                addr_of_mut!(emit_struct),
+            )
+        };
+        if err != 0 {
+            return Err(Error::failed("rabdiff", err));
+        }
+        Ok(BinaryPatch {
+            file: output.to_mmfile(),
+        })
+    }
+
+    /// Apply a binary patch to a file. Fails if the patch was not computed
+    /// against this file's contents.
+    pub fn apply_binary_patch(&mut self, patch: &mut BinaryPatch) -> Result<MMFile, Error> {
+        let mut output = MMBlocks::new();
+        let mut emit_struct = xdemitcb_t {
+            priv_: addr_of_mut!(output) as *mut c_void,
+            outf: Some(write_blocks_cb),
+        };
+        span!("xdl_bpatch");
+        clear_errno();
+        let err: c_int = unsafe {
+            xdl_bpatch(
+                addr_of_mut!(self.inner),
+                addr_of_mut!(patch.file.inner),
+                addr_of_mut!(emit_struct),
+            )
+        };
+        if err != 0 {
+            return Err(Error::failed("bpatch", err));
+        }
+        Ok(output.to_mmfile())
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{lines::line_starts, Error, MMFile, Patch, PatchError};
+
+/// The result of applying a chain of patches, recording which patch
+/// introduced each line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blame {
+    /// The file after every patch has been applied
+    pub file: MMFile,
+    /// For each line of `file`, the index of the patch that introduced it,
+    /// or `None` if it comes from the base file
+    pub origins: Vec<Option<usize>>,
+}
+
+impl Blame {
+    /// Index of the patch that introduced a 0-based line of the final file.
+    /// Returns `None` for lines from the base file or past the end.
+    pub fn origin(&self, line: usize) -> Option<usize> {
+        self.origins.get(line).copied().flatten()
+    }
+}
+
+/// Apply `patches` to `base` in order, tracking which patch introduced each
+/// line of the result. Returns `Err` if any patch fails to apply cleanly.
+pub fn blame(base: &MMFile, patches: &mut [Patch]) -> Result<Blame, Error> {
+    let mut file = base.clone();
+    let mut origins = vec![None; line_starts(file.as_slice()).len() - 1];
+    for (index, patch) in patches.iter_mut().enumerate() {
+        let (patched, map) = match file.apply_patch_mapped(patch) {
+            Ok(applied) => applied,
+            Err(PatchError::Rejected { .. }) => {
+                return Err(Error::Invalid(format!(
+                    "patch {} did not apply cleanly",
+                    index
+                )))
+            }
+            Err(PatchError::Failed(e)) => return Err(e),
+        };
+        let mut next = vec![Some(index); line_starts(patched.as_slice()).len() - 1];
+        for (old, origin) in origins.into_iter().enumerate() {
+            if let Some(new) = map.get(old) {
+                next[new] = origin;
+            }
+        }
+        file = patched;
+        origins = next;
+    }
+    Ok(Blame { file, origins })
+}
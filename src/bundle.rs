@@ -0,0 +1,129 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use crate::{Error, Patch};
+
+/// First line of every serialized bundle
+const MAGIC: &[u8] = b"libxdiff-bundle 1\n";
+
+/// A collection of named patches, such as one per file or per version, that
+/// can be written to and read from a single artifact.
+///
+/// The format is the line `libxdiff-bundle 1` followed by one record per
+/// patch: a line `<name length> <patch length>` giving both lengths in
+/// bytes, then the name and the patch text.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PatchBundle {
+    entries: Vec<(String, Patch)>,
+}
+
+impl PatchBundle {
+    /// Create an empty bundle
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a named patch. Names need not be unique.
+    pub fn push(&mut self, name: impl Into<String>, patch: Patch) {
+        self.entries.push((name.into(), patch));
+    }
+
+    /// Number of patches in the bundle
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the bundle holds no patches
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The first patch with the given name
+    pub fn get(&self, name: &str) -> Option<&Patch> {
+        self.entries.iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+
+    /// Iterate over the names and patches in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Patch)> {
+        self.entries.iter().map(|(n, p)| (n.as_str(), p))
+    }
+
+    /// Write the bundle in its serialized form
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        for (name, patch) in self.entries.iter() {
+            writeln!(writer, "{} {}", name.len(), patch.as_slice().len())?;
+            writer.write_all(name.as_bytes())?;
+            writer.write_all(patch.as_slice())?;
+        }
+        Ok(())
+    }
+
+    /// Serialize the bundle to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        // writing to a Vec can't fail
+        let _ = self.write_to(&mut out);
+        out
+    }
+
+    /// Read a serialized bundle. Returns `Err` if the data is truncated or
+    /// malformed or a patch is invalid.
+    pub fn read_from<R: Read>(reader: R) -> Result<PatchBundle, Error> {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        read_line(&mut reader, &mut line)?;
+        if line != MAGIC {
+            return Err(Error::Invalid("not a patch bundle".to_owned()));
+        }
+        let mut bundle = PatchBundle::new();
+        loop {
+            line.clear();
+            if read_line(&mut reader, &mut line)? == 0 {
+                return Ok(bundle);
+            }
+            let (name_len, patch_len) = parse_lengths(&line).ok_or_else(|| {
+                Error::Invalid(format!(
+                    "malformed bundle record: {:?}",
+                    String::from_utf8_lossy(&line)
+                ))
+            })?;
+            let name = read_exact(&mut reader, name_len)?;
+            let name = String::from_utf8(name)
+                .map_err(|_| Error::Invalid("bundle name is not UTF-8".to_owned()))?;
+            let patch = Patch::from_bytes(&read_exact(&mut reader, patch_len)?).map_err(|e| {
+                Error::Invalid(format!("invalid patch {:?} in bundle: {}", name, e))
+            })?;
+            bundle.push(name, patch);
+        }
+    }
+
+    /// Deserialize a bundle from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<PatchBundle, Error> {
+        Self::read_from(bytes)
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> Result<usize, Error> {
+    reader
+        .read_until(b'\n', line)
+        .map_err(|e| Error::io("bundle", e))
+}
+
+fn read_exact<R: Read>(reader: &mut R, len: usize) -> Result<Vec<u8>, Error> {
+    // don't trust the length enough to allocate it all up front
+    let mut buf = Vec::new();
+    reader
+        .take(len as u64)
+        .read_to_end(&mut buf)
+        .map_err(|e| Error::io("bundle", e))?;
+    if buf.len() < len {
+        return Err(Error::Invalid("bundle is truncated".to_owned()));
+    }
+    Ok(buf)
+}
+
+fn parse_lengths(line: &[u8]) -> Option<(usize, usize)> {
+    let line = core::str::from_utf8(line).ok()?.strip_suffix('\n')?;
+    let (name, patch) = line.split_once(' ')?;
+    Some((name.parse().ok()?, patch.parse().ok()?))
+}
@@ -0,0 +1,374 @@
+use crate::{DiffOptions, Error, MMFile};
+
+/// One step of a character-level diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CharOp {
+    /// Text present in both versions
+    Equal(String),
+    /// Text only present in the old version
+    Delete(String),
+    /// Text only present in the new version
+    Insert(String),
+}
+
+/// Options controlling how a [`CharPatch`] is made and applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct CharPatchOptions {
+    pub(crate) context_len: usize,
+    pub(crate) match_distance: usize,
+    pub(crate) match_threshold: f64,
+}
+
+impl Default for CharPatchOptions {
+    fn default() -> Self {
+        Self {
+            context_len: 4,
+            match_distance: 1000,
+            match_threshold: 0.5,
+        }
+    }
+}
+
+impl CharPatchOptions {
+    /// Create options with diff-match-patch's defaults
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of unchanged characters kept around each change to
+    /// locate it when applying
+    pub fn context_len(mut self, chars: usize) -> Self {
+        self.context_len = chars;
+        self
+    }
+
+    /// Set how many characters away from its expected position a hunk is
+    /// searched for
+    pub fn match_distance(mut self, chars: usize) -> Self {
+        self.match_distance = chars;
+        self
+    }
+
+    /// Set the largest fraction of a hunk's old text that may differ from
+    /// the text it is applied to, between 0.0 (exact) and 1.0
+    pub fn match_threshold(mut self, threshold: f64) -> Self {
+        self.match_threshold = threshold;
+        self
+    }
+}
+
+/// A change to one region of a text, with positions counted in characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharHunk {
+    /// Where the hunk starts in the old text
+    pub old_start: usize,
+    /// Where the hunk starts in the new text
+    pub new_start: usize,
+    /// The hunk's context and changes in order
+    pub ops: Vec<CharOp>,
+}
+
+impl CharHunk {
+    fn old_text(&self) -> Vec<char> {
+        self.side(|op| !matches!(op, CharOp::Insert(_)))
+    }
+
+    fn new_text(&self) -> Vec<char> {
+        self.side(|op| !matches!(op, CharOp::Delete(_)))
+    }
+
+    fn side<F: Fn(&CharOp) -> bool>(&self, keep: F) -> Vec<char> {
+        self.ops
+            .iter()
+            .filter(|op| keep(op))
+            .flat_map(|op| op_text(op).chars())
+            .collect()
+    }
+}
+
+/// A character-level patch that applies fuzzily, tolerating moved text and
+/// small differences in context, in the manner of diff-match-patch. Suited
+/// to collaborative editing, where line-based patches with exact context are
+/// too brittle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharPatch {
+    hunks: Vec<CharHunk>,
+}
+
+impl CharPatch {
+    /// Compute the patch turning `old` into `new` with default options
+    pub fn new(old: &str, new: &str) -> Result<CharPatch, Error> {
+        CharPatch::with_options(old, new, &CharPatchOptions::new())
+    }
+
+    /// Compute the patch turning `old` into `new`
+    pub fn with_options(old: &str, new: &str, opts: &CharPatchOptions) -> Result<CharPatch, Error> {
+        let old: Vec<char> = old.chars().collect();
+        let new: Vec<char> = new.chars().collect();
+        let ops = char_diff(&old, &new)?;
+        Ok(CharPatch {
+            hunks: make_hunks(&ops, opts.context_len),
+        })
+    }
+
+    /// The hunks of this patch
+    pub fn hunks(&self) -> &[CharHunk] {
+        &self.hunks
+    }
+
+    /// Apply this patch to `text` with default options. See
+    /// [`apply_with`](Self::apply_with).
+    pub fn apply(&self, text: &str) -> Result<(String, Vec<bool>), Error> {
+        self.apply_with(text, &CharPatchOptions::new())
+    }
+
+    /// Apply this patch to `text`, finding each hunk near where earlier
+    /// hunks suggest it should be, and tolerating differences up to
+    /// [`CharPatchOptions::match_threshold`]. Returns the patched text and
+    /// whether each hunk was applied. Returns `Err` only if diffing a fuzzy
+    /// match fails.
+    pub fn apply_with(
+        &self,
+        text: &str,
+        opts: &CharPatchOptions,
+    ) -> Result<(String, Vec<bool>), Error> {
+        let mut text: Vec<char> = text.chars().collect();
+        let mut applied = Vec::with_capacity(self.hunks.len());
+        // how far text has moved from where the patch expects it
+        let mut delta = 0isize;
+        for hunk in self.hunks.iter() {
+            let old = hunk.old_text();
+            let new = hunk.new_text();
+            let expected = (hunk.new_start as isize + delta).clamp(0, text.len() as isize) as usize;
+            let Some((start, exact)) = locate(&text, &old, expected, opts) else {
+                delta -= new.len() as isize - old.len() as isize;
+                applied.push(false);
+                continue;
+            };
+            delta += start as isize - expected as isize;
+            if exact {
+                text.splice(start..start + old.len(), new);
+            } else {
+                let end = (start + old.len()).min(text.len());
+                let found = text[start..end].to_vec();
+                let diffs = char_diff(&old, &found)?;
+                let patched = apply_mapped(&hunk.ops, &found, &diffs);
+                text.splice(start..end, patched);
+            }
+            applied.push(true);
+        }
+        Ok((text.into_iter().collect(), applied))
+    }
+}
+
+fn op_text(op: &CharOp) -> &str {
+    match op {
+        CharOp::Equal(s) | CharOp::Delete(s) | CharOp::Insert(s) => s,
+    }
+}
+
+/// Diff two strings character by character with libxdiff, by giving it each
+/// character on a line of its own
+fn char_diff(old: &[char], new: &[char]) -> Result<Vec<CharOp>, Error> {
+    let encode = |chars: &[char]| {
+        let mut out = Vec::with_capacity(chars.len() * 2);
+        let mut buf = [0; 4];
+        for c in chars {
+            match c {
+                '\n' => out.extend_from_slice(b"\\n"),
+                '\\' => out.extend_from_slice(b"\\\\"),
+                c => out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes()),
+            }
+            out.push(b'\n');
+        }
+        MMFile::from_bytes(&out)
+    };
+    let changes = encode(old).changed_lines(&encode(new), &DiffOptions::new())?;
+    let text = |chars: &[char]| chars.iter().collect::<String>();
+    let mut ops = Vec::new();
+    let mut next = 0;
+    for change in changes {
+        if change.old.start > next {
+            ops.push(CharOp::Equal(text(&old[next..change.old.start])));
+        }
+        if !change.old.is_empty() {
+            ops.push(CharOp::Delete(text(&old[change.old.clone()])));
+        }
+        if !change.new.is_empty() {
+            ops.push(CharOp::Insert(text(&new[change.new])));
+        }
+        next = change.old.end;
+    }
+    if next < old.len() {
+        ops.push(CharOp::Equal(text(&old[next..])));
+    }
+    Ok(ops)
+}
+
+/// Group a diff's changes into hunks with `context_len` characters of
+/// context, splitting where changes are further apart than twice that
+fn make_hunks(ops: &[CharOp], context_len: usize) -> Vec<CharHunk> {
+    let mut hunks = Vec::new();
+    let mut current: Option<CharHunk> = None;
+    let (mut old_pos, mut new_pos) = (0, 0);
+    let mut prev_equal: &str = "";
+    for (i, op) in ops.iter().enumerate() {
+        let len = op_text(op).chars().count();
+        match op {
+            CharOp::Equal(s) => {
+                if let Some(mut hunk) = current.take() {
+                    if len <= 2 * context_len && i + 1 < ops.len() {
+                        hunk.ops.push(op.clone());
+                        current = Some(hunk);
+                    } else {
+                        let tail: String = s.chars().take(context_len).collect();
+                        hunk.ops.push(CharOp::Equal(tail));
+                        hunks.push(hunk);
+                    }
+                }
+                prev_equal = s;
+                old_pos += len;
+                new_pos += len;
+            }
+            CharOp::Delete(_) | CharOp::Insert(_) => {
+                let hunk = current.get_or_insert_with(|| {
+                    let lead_len = prev_equal.chars().count().min(context_len);
+                    let skip = prev_equal.chars().count() - lead_len;
+                    let lead: String = prev_equal.chars().skip(skip).collect();
+                    CharHunk {
+                        old_start: old_pos - lead_len,
+                        new_start: new_pos - lead_len,
+                        ops: if lead.is_empty() {
+                            Vec::new()
+                        } else {
+                            vec![CharOp::Equal(lead)]
+                        },
+                    }
+                });
+                hunk.ops.push(op.clone());
+                prev_equal = "";
+                if matches!(op, CharOp::Delete(_)) {
+                    old_pos += len;
+                } else {
+                    new_pos += len;
+                }
+            }
+        }
+    }
+    hunks.extend(current);
+    hunks
+}
+
+/// Longest prefix of a pattern scored at every candidate position of a fuzzy
+/// search, as diff-match-patch limits its bitap search to 32 characters
+const FUZZY_PREFIX_LEN: usize = 32;
+
+/// Find where `pattern` best matches `text` near `expected`: an exact match
+/// if there is one within the match distance, otherwise the position where
+/// the pattern's first [`FUZZY_PREFIX_LEN`] characters have the fewest
+/// differences, weighted by distance. The rest of the pattern is then
+/// compared character by character at that position, so the search costs
+/// time linear in the pattern and distance. Returns the position and whether
+/// the match was exact.
+fn locate(
+    text: &[char],
+    pattern: &[char],
+    expected: usize,
+    opts: &CharPatchOptions,
+) -> Option<(usize, bool)> {
+    if pattern.is_empty() {
+        return Some((expected, true));
+    }
+    let first = expected.saturating_sub(opts.match_distance);
+    let last = (expected + opts.match_distance).min(text.len().saturating_sub(pattern.len()));
+    if pattern.len() > text.len() || first > last {
+        return None;
+    }
+    let exact = (first..=last)
+        .filter(|&pos| text[pos..pos + pattern.len()] == *pattern)
+        .min_by_key(|&pos| pos.abs_diff(expected));
+    if let Some(pos) = exact {
+        return Some((pos, true));
+    }
+    let max_errors = (pattern.len() as f64 * opts.match_threshold) as usize;
+    let distance = opts.match_distance.max(1) as f64;
+    let (prefix, rest) = pattern.split_at(pattern.len().min(FUZZY_PREFIX_LEN));
+    let (pos, prefix_errors, _) = (first..=last)
+        .map(|pos| {
+            let errors = edit_distance(prefix, &text[pos..pos + prefix.len()]);
+            let score =
+                errors as f64 / prefix.len() as f64 + pos.abs_diff(expected) as f64 / distance;
+            (pos, errors, score)
+        })
+        .min_by(|a, b| a.2.total_cmp(&b.2))?;
+    let rest_errors = rest
+        .iter()
+        .zip(&text[pos + prefix.len()..])
+        .filter(|(a, b)| a != b)
+        .count();
+    (prefix_errors + rest_errors <= max_errors).then_some((pos, false))
+}
+
+/// Levenshtein distance between two character sequences
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = diagonal + (ca != cb) as usize;
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Map a position in the first text of `diffs` to the second
+fn map_position(diffs: &[CharOp], pos: usize) -> usize {
+    let (mut old, mut new) = (0, 0);
+    for op in diffs {
+        let len = op_text(op).chars().count();
+        match op {
+            CharOp::Equal(_) if old + len > pos => return new + (pos - old),
+            CharOp::Delete(_) if old + len > pos => return new,
+            CharOp::Equal(_) => {
+                old += len;
+                new += len;
+            }
+            CharOp::Delete(_) => old += len,
+            CharOp::Insert(_) => new += len,
+        }
+    }
+    new + pos.saturating_sub(old)
+}
+
+/// Apply a hunk's changes to `found`, text that approximately matches the
+/// hunk's old text, mapping each change's position through `diffs` from the
+/// old text to `found`
+fn apply_mapped(ops: &[CharOp], found: &[char], diffs: &[CharOp]) -> Vec<char> {
+    let mut out = Vec::with_capacity(found.len());
+    let mut copied = 0;
+    let mut old_pos = 0;
+    for op in ops {
+        let len = op_text(op).chars().count();
+        match op {
+            CharOp::Equal(_) => old_pos += len,
+            CharOp::Insert(s) => {
+                let at = map_position(diffs, old_pos).clamp(copied, found.len());
+                out.extend_from_slice(&found[copied..at]);
+                out.extend(s.chars());
+                copied = at;
+            }
+            CharOp::Delete(_) => {
+                let start = map_position(diffs, old_pos).clamp(copied, found.len());
+                let end = map_position(diffs, old_pos + len).clamp(start, found.len());
+                out.extend_from_slice(&found[copied..start]);
+                copied = end;
+                old_pos += len;
+            }
+        }
+    }
+    out.extend_from_slice(&found[copied..]);
+    out
+}
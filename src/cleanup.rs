@@ -0,0 +1,35 @@
+use crate::LineChange;
+
+/// Merge changes separated only by short runs of unchanged lines, as
+/// diff-match-patch's semantic cleanup does: a run is absorbed into the
+/// changes around it when it is no longer, in bytes, than the larger side of
+/// the change before it and of the change after it. Changes grown this way
+/// keep absorbing the runs that follow.
+pub(crate) fn cleanup_semantic(a: &[u8], b: &[u8], changes: Vec<LineChange>) -> Vec<LineChange> {
+    let a_lines: Vec<usize> = a
+        .split_inclusive(|b| *b == b'\n')
+        .map(<[u8]>::len)
+        .collect();
+    let b_lines: Vec<usize> = b
+        .split_inclusive(|b| *b == b'\n')
+        .map(<[u8]>::len)
+        .collect();
+    let size = |c: &LineChange| {
+        let old: usize = a_lines[c.old.clone()].iter().sum();
+        let new: usize = b_lines[c.new.clone()].iter().sum();
+        old.max(new)
+    };
+    let mut merged: Vec<LineChange> = Vec::with_capacity(changes.len());
+    for change in changes {
+        if let Some(prev) = merged.last_mut() {
+            let equal: usize = a_lines[prev.old.end..change.old.start].iter().sum();
+            if equal <= size(prev) && equal <= size(&change) {
+                prev.old.end = change.old.end;
+                prev.new.end = change.new.end;
+                continue;
+            }
+        }
+        merged.push(change);
+    }
+    merged
+}
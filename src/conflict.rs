@@ -0,0 +1,216 @@
+use core::ops::Range;
+
+use crate::{Error, LineEnding, MergeDriver, Resolution};
+
+/// A conflict found in a buffer containing conflict markers, such as
+///
+/// ```text
+/// <<<<<<< ours
+/// our lines
+/// ||||||| base
+/// base lines
+/// =======
+/// their lines
+/// >>>>>>> theirs
+/// ```
+///
+/// The `|||||||` section is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkedConflict {
+    /// Text following the `<<<<<<<` marker, if any
+    pub ours_label: String,
+    /// Text following the `|||||||` marker, if that section is present
+    pub base_label: Option<String>,
+    /// Text following the `>>>>>>>` marker, if any
+    pub theirs_label: String,
+    /// Lines between the `<<<<<<<` marker and the next marker
+    pub ours: Vec<u8>,
+    /// Lines of the `|||||||` section, if present
+    pub base: Option<Vec<u8>>,
+    /// Lines between the `=======` and `>>>>>>>` markers
+    pub theirs: Vec<u8>,
+    /// 0-based, half-open range of lines covered, including the markers
+    pub lines: Range<usize>,
+    /// Range of bytes covered, including the markers
+    pub bytes: Range<usize>,
+    /// Line ending used by the marker lines
+    pub line_ending: LineEnding,
+}
+
+impl MarkedConflict {
+    /// Render this conflict back into marker form
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        let eol = self.line_ending;
+        push_marker(&mut out, b'<', &self.ours_label, eol);
+        out.extend_from_slice(&self.ours);
+        if let Some(base) = &self.base {
+            let label = self.base_label.as_deref().unwrap_or("");
+            push_marker(&mut out, b'|', label, eol);
+            out.extend_from_slice(base);
+        }
+        push_marker(&mut out, b'=', "", eol);
+        out.extend_from_slice(&self.theirs);
+        push_marker(&mut out, b'>', &self.theirs_label, eol);
+        out
+    }
+}
+
+/// A [`MergeDriver`] that keeps both sides of every conflict between
+/// git-style conflict markers, which [`parse_conflict_markers`] can read back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictMarkers {
+    ours_label: String,
+    base_label: Option<String>,
+    theirs_label: String,
+}
+
+impl Default for ConflictMarkers {
+    fn default() -> Self {
+        Self::new("ours", "theirs")
+    }
+}
+
+impl ConflictMarkers {
+    /// Mark conflicts with the given labels after the `<<<<<<<` and
+    /// `>>>>>>>` markers
+    pub fn new(ours_label: &str, theirs_label: &str) -> Self {
+        Self {
+            ours_label: ours_label.to_owned(),
+            base_label: None,
+            theirs_label: theirs_label.to_owned(),
+        }
+    }
+
+    /// Also write the base lines in a `|||||||` section with this label, as
+    /// git's diff3 conflict style does
+    pub fn base_label(mut self, label: &str) -> Self {
+        self.base_label = Some(label.to_owned());
+        self
+    }
+}
+
+impl MergeDriver for ConflictMarkers {
+    fn resolve(&mut self, base: &[u8], ours: &[u8], theirs: &[u8]) -> Resolution {
+        let eol = LineEnding::detect(&[ours, theirs].concat());
+        let mut out = Vec::new();
+        let push_lines = |out: &mut Vec<u8>, lines: &[u8]| {
+            out.extend_from_slice(lines);
+            if !lines.is_empty() && !lines.ends_with(b"\n") {
+                out.extend_from_slice(eol.as_bytes());
+            }
+        };
+        push_marker(&mut out, b'<', &self.ours_label, eol);
+        push_lines(&mut out, ours);
+        if let Some(label) = &self.base_label {
+            push_marker(&mut out, b'|', label, eol);
+            push_lines(&mut out, base);
+        }
+        push_marker(&mut out, b'=', "", eol);
+        push_lines(&mut out, theirs);
+        push_marker(&mut out, b'>', &self.theirs_label, eol);
+        Resolution::Replace(out)
+    }
+}
+
+pub(crate) fn push_marker(out: &mut Vec<u8>, marker: u8, label: &str, eol: LineEnding) {
+    out.extend_from_slice(&[marker; 7]);
+    if !label.is_empty() {
+        out.push(b' ');
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.extend_from_slice(eol.as_bytes());
+}
+
+/// If `line` is a conflict marker made of `marker` characters, return its
+/// label
+fn marker_label(line: &[u8], marker: u8) -> Option<String> {
+    if line.len() < 7 || line[..7].iter().any(|b| *b != marker) {
+        return None;
+    }
+    let rest = &line[7..];
+    let rest = rest.strip_suffix(b"\n").unwrap_or(rest);
+    let rest = rest.strip_suffix(b"\r").unwrap_or(rest);
+    match rest.first() {
+        None => Some(String::new()),
+        Some(b' ') => Some(String::from_utf8_lossy(&rest[1..]).into_owned()),
+        Some(_) => None,
+    }
+}
+
+enum Section {
+    Ours,
+    Base,
+    Theirs,
+}
+
+/// Find every conflict marked in `buf`. Returns `Err` if the markers are
+/// unbalanced or out of order.
+pub fn parse_conflict_markers(buf: &[u8]) -> Result<Vec<MarkedConflict>, Error> {
+    let mut conflicts = Vec::new();
+    let mut current: Option<(MarkedConflict, Section)> = None;
+    let mut offset = 0;
+    for (index, line) in buf.split_inclusive(|b| *b == b'\n').enumerate() {
+        let line_start = offset;
+        offset += line.len();
+        let Some((conflict, section)) = current.as_mut() else {
+            if let Some(label) = marker_label(line, b'<') {
+                let conflict = MarkedConflict {
+                    ours_label: label,
+                    base_label: None,
+                    theirs_label: String::new(),
+                    ours: Vec::new(),
+                    base: None,
+                    theirs: Vec::new(),
+                    lines: index..index,
+                    bytes: line_start..line_start,
+                    line_ending: LineEnding::detect(line),
+                };
+                current = Some((conflict, Section::Ours));
+            } else if marker_label(line, b'>').is_some() {
+                return Err(Error::Invalid(format!(
+                    "unmatched '>>>>>>>' marker on line {}",
+                    index + 1
+                )));
+            }
+            continue;
+        };
+        match section {
+            Section::Ours | Section::Base if marker_label(line, b'=').is_some() => {
+                *section = Section::Theirs;
+            }
+            Section::Ours if marker_label(line, b'|').is_some() => {
+                conflict.base_label = marker_label(line, b'|');
+                conflict.base = Some(Vec::new());
+                *section = Section::Base;
+            }
+            Section::Theirs if marker_label(line, b'>').is_some() => {
+                let (mut conflict, _) = current.take().unwrap();
+                conflict.theirs_label = marker_label(line, b'>').unwrap();
+                conflict.lines.end = index + 1;
+                conflict.bytes.end = offset;
+                conflicts.push(conflict);
+            }
+            _ if marker_label(line, b'<').is_some() => {
+                return Err(Error::Invalid(format!(
+                    "nested '<<<<<<<' marker on line {}",
+                    index + 1
+                )));
+            }
+            Section::Ours => conflict.ours.extend_from_slice(line),
+            Section::Base => {
+                if let Some(base) = conflict.base.as_mut() {
+                    base.extend_from_slice(line);
+                }
+            }
+            Section::Theirs => conflict.theirs.extend_from_slice(line),
+        }
+    }
+    match current {
+        Some((conflict, _)) => Err(Error::Invalid(format!(
+            "conflict starting on line {} is not terminated",
+            conflict.lines.start + 1
+        ))),
+        None => Ok(conflicts),
+    }
+}
@@ -0,0 +1,90 @@
+use core::ffi::c_int;
+
+use crate::{
+    hunk::NO_NEWLINE,
+    lines::{line_starts, trim_common_lines},
+    Error, HunkHeader,
+};
+
+/// Size of the edit graph left once common leading and trailing lines are
+/// removed: the product of the remaining line counts of both files. Myers'
+/// algorithm never does more work than this, and pathological inputs
+/// approach it.
+pub(crate) fn edit_cost(a: &[u8], b: &[u8]) -> usize {
+    let (a_range, b_range, _) = trim_common_lines(a, b, 0);
+    let a_lines = line_starts(&a[a_range]).len() - 1;
+    let b_lines = line_starts(&b[b_range]).len() - 1;
+    a_lines.saturating_mul(b_lines)
+}
+
+/// Emit a single hunk replacing everything between the common leading and
+/// trailing lines of `a` and `b`, in the same record format as libxdiff
+pub(crate) fn coarse_records<CB>(
+    a: &[u8],
+    b: &[u8],
+    context_len: usize,
+    mut callback: CB,
+) -> Result<(), Error>
+where
+    CB: FnMut(&[&[u8]]) -> c_int,
+{
+    let (a_range, b_range, prefix) = trim_common_lines(a, b, 0);
+    if a_range.is_empty() && b_range.is_empty() {
+        return Ok(());
+    }
+    let a_starts = line_starts(a);
+    let b_starts = line_starts(b);
+    let a_total = a_starts.len() - 1;
+    let a_changed = line_starts(&a[a_range]).len() - 1;
+    let b_changed = line_starts(&b[b_range]).len() - 1;
+    let suffix = a_total - prefix - a_changed;
+
+    let lead = prefix.min(context_len);
+    let trail = suffix.min(context_len);
+    let first = prefix - lead;
+    let header = HunkHeader {
+        old_start: first + 1,
+        old_len: lead + a_changed + trail,
+        new_start: first + 1,
+        new_len: lead + b_changed + trail,
+    };
+    let mut emit = |record: &[&[u8]]| match callback(record) {
+        0 => Ok(()),
+        code => Err(Error::Failed { op: "emit", code }),
+    };
+    emit(&[&normalize_empty(header).to_bytes()])?;
+
+    let mut emit_line = |prefix: &[u8], data: &[u8]| {
+        if data.ends_with(b"\n") {
+            emit(&[prefix, data])
+        } else {
+            emit(&[prefix, data, NO_NEWLINE])
+        }
+    };
+    let a_line = |i: usize| &a[a_starts[i]..a_starts[i + 1]];
+    let b_line = |i: usize| &b[b_starts[i]..b_starts[i + 1]];
+    for i in first..prefix {
+        emit_line(b" ", a_line(i))?;
+    }
+    for i in prefix..prefix + a_changed {
+        emit_line(b"-", a_line(i))?;
+    }
+    for i in prefix..prefix + b_changed {
+        emit_line(b"+", b_line(i))?;
+    }
+    for i in prefix + a_changed..prefix + a_changed + trail {
+        emit_line(b" ", a_line(i))?;
+    }
+    Ok(())
+}
+
+/// Number an empty side by the line it follows, as git does
+pub(crate) fn normalize_empty(mut header: HunkHeader) -> HunkHeader {
+    if header.old_len == 0 {
+        header.old_start -= 1;
+    }
+    if header.new_len == 0 {
+        header.new_start -= 1;
+    }
+    header
+}
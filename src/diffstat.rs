@@ -0,0 +1,115 @@
+use core::fmt::Write;
+
+use crate::{hunk::parse_hunks, ChangeTag, Patch};
+
+impl Patch {
+    /// Count the lines this patch inserts and deletes
+    pub fn line_changes(&self) -> (usize, usize) {
+        let hunks = parse_hunks(self.as_slice()).unwrap_or_default();
+        hunks
+            .iter()
+            .flat_map(|h| h.changes.iter())
+            .fold((0, 0), |(ins, del), c| match c.tag {
+                ChangeTag::Insert => (ins + 1, del),
+                ChangeTag::Delete => (ins, del + 1),
+                ChangeTag::Equal => (ins, del),
+            })
+    }
+
+    /// Render a one-file diffstat, e.g. ` name | 3 ++-`, followed by the
+    /// summary line. See [`diffstat`].
+    pub fn diffstat(&self, name: &str, bar_width: usize) -> String {
+        diffstat(&[(name, self)], bar_width)
+    }
+
+    /// Describe this patch in one line for confirmation prompts, e.g.
+    /// `3 hunks: +12/−4 lines, affects lines 10–18, 40–44, 90`. Line numbers
+    /// are 1-based lines of the source file, excluding context; a hunk that
+    /// only inserts is listed by the line it follows, as `after 12`.
+    pub fn summary(&self) -> String {
+        if self.is_empty() {
+            return "no changes".to_owned();
+        }
+        let (ins, del) = self.line_changes();
+        let hunks = parse_hunks(self.as_slice()).unwrap_or_default();
+        let plural = |n: usize| if n == 1 { "" } else { "s" };
+        let mut out = format!(
+            "{} hunk{}: +{}/\u{2212}{} line{}",
+            hunks.len(),
+            plural(hunks.len()),
+            ins,
+            del,
+            plural(ins.max(del))
+        );
+        let ranges: Vec<String> = hunks
+            .iter()
+            .map(|h| {
+                let lines = h.old_changed_lines();
+                match lines.len() {
+                    0 if lines.start == 0 => "at start".to_owned(),
+                    0 => format!("after {}", lines.start),
+                    1 => lines.end.to_string(),
+                    _ => format!("{}\u{2013}{}", lines.start + 1, lines.end),
+                }
+            })
+            .collect();
+        let _ = write!(out, ", affects lines {}", ranges.join(", "));
+        out
+    }
+}
+
+/// Render a `git diff --stat` style summary of several patches, one line per
+/// `(name, patch)` pair followed by a totals line:
+///
+/// ```text
+///  src/lib.rs | 12 ++++++++----
+///  README.md  |  1 +
+///  2 files changed, 9 insertions(+), 4 deletions(-)
+/// ```
+///
+/// Histogram bars are scaled down proportionally so that none is wider than
+/// `bar_width` characters.
+pub fn diffstat<S: AsRef<str>>(files: &[(S, &Patch)], bar_width: usize) -> String {
+    let counts: Vec<(usize, usize)> = files.iter().map(|(_, p)| p.line_changes()).collect();
+    let name_width = files
+        .iter()
+        .map(|(name, _)| name.as_ref().chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_changes = counts.iter().map(|(i, d)| i + d).max().unwrap_or(0);
+    let count_width = max_changes.to_string().len();
+    let scale = |n: usize| {
+        if max_changes <= bar_width || n == 0 {
+            n
+        } else {
+            (n * bar_width / max_changes).max(1)
+        }
+    };
+
+    let mut out = String::new();
+    for ((name, _), (ins, del)) in files.iter().zip(counts.iter()) {
+        let bar = "+".repeat(scale(*ins)) + &"-".repeat(scale(*del));
+        let line = format!(
+            " {:<name_width$} | {:>count_width$} {}",
+            name.as_ref(),
+            ins + del,
+            bar
+        );
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+
+    let (insertions, deletions) = counts
+        .iter()
+        .fold((0, 0), |(ti, td), (i, d)| (ti + i, td + d));
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let _ = write!(out, " {} file{} changed", files.len(), plural(files.len()));
+    if insertions > 0 || deletions == 0 {
+        let _ = write!(out, ", {} insertion{}(+)", insertions, plural(insertions));
+    }
+    if deletions > 0 || insertions == 0 {
+        let _ = write!(out, ", {} deletion{}(-)", deletions, plural(deletions));
+    }
+    out.push('\n');
+    out
+}
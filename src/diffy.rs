@@ -0,0 +1,17 @@
+use crate::{Error, Patch};
+
+impl Patch {
+    /// View this patch as a [`diffy::Patch`]. Returns `Err` if diffy cannot
+    /// parse the patch text.
+    pub fn to_diffy(&self) -> Result<diffy::Patch<'_, [u8]>, Error> {
+        diffy::Patch::from_bytes(self.as_slice()).map_err(|e| Error::Invalid(e.to_string()))
+    }
+
+    /// Create a patch from a [`diffy::Patch`]
+    pub fn from_diffy<T>(patch: &diffy::Patch<'_, T>) -> Result<Patch, Error>
+    where
+        T: AsRef<[u8]> + ToOwned + ?Sized,
+    {
+        Patch::from_bytes(&patch.to_bytes())
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{Error, MMFile};
+
+/// A text encoding that inputs can be transcoded from so they diff as UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, where every byte is the code point of the same value
+    Latin1,
+}
+
+impl TextEncoding {
+    /// Guess the encoding of `bytes`: UTF-16 if it starts with a UTF-16 byte
+    /// order mark, UTF-8 if it is valid UTF-8, and Latin-1 otherwise.
+    pub fn detect(bytes: &[u8]) -> TextEncoding {
+        if bytes.starts_with(&[0xFF, 0xFE]) {
+            TextEncoding::Utf16Le
+        } else if bytes.starts_with(&[0xFE, 0xFF]) {
+            TextEncoding::Utf16Be
+        } else if core::str::from_utf8(bytes).is_ok() {
+            TextEncoding::Utf8
+        } else {
+            TextEncoding::Latin1
+        }
+    }
+
+    /// Convert `bytes` in this encoding to UTF-8. Invalid sequences are
+    /// replaced with U+FFFD. A byte order mark is kept as U+FEFF so that
+    /// [`TextEncoding::encode`] restores it.
+    pub fn decode(&self, bytes: &[u8]) -> Vec<u8> {
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes).into_owned().into_bytes(),
+            TextEncoding::Utf16Le => decode_utf16(bytes, u16::from_le_bytes),
+            TextEncoding::Utf16Be => decode_utf16(bytes, u16::from_be_bytes),
+            TextEncoding::Latin1 => bytes
+                .iter()
+                .map(|b| char::from(*b))
+                .collect::<String>()
+                .into_bytes(),
+        }
+    }
+
+    /// Convert UTF-8 `text` back to this encoding. Invalid UTF-8 is replaced
+    /// with U+FFFD, and characters Latin-1 can't represent become `?`.
+    pub fn encode(&self, text: &[u8]) -> Vec<u8> {
+        let text = String::from_utf8_lossy(text);
+        match self {
+            TextEncoding::Utf8 => text.into_owned().into_bytes(),
+            TextEncoding::Utf16Le => text.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            TextEncoding::Utf16Be => text.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            TextEncoding::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(u32::from(c)).unwrap_or(b'?'))
+                .collect(),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], to_unit: fn([u8; 2]) -> u16) -> Vec<u8> {
+    let units = bytes.chunks(2).map(|pair| match pair {
+        [a, b] => to_unit([*a, *b]),
+        // odd trailing byte
+        _ => 0xFFFD,
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect::<String>()
+        .into_bytes()
+}
+
+impl MMFile {
+    /// Create a new MMFile holding `bytes` transcoded from `encoding` to
+    /// UTF-8
+    pub fn from_encoded(bytes: &[u8], encoding: TextEncoding) -> MMFile {
+        MMFile::from_bytes(&encoding.decode(bytes))
+    }
+
+    /// Get the contents of this file transcoded from UTF-8 to `encoding`
+    pub fn to_encoded(&self, encoding: TextEncoding) -> Vec<u8> {
+        encoding.encode(self.as_slice())
+    }
+
+    #[cfg(feature = "std")]
+    /// Compute the diff to turn self into other, where both files hold UTF-8
+    /// decoded from `encoding`. Each line is transcoded back to `encoding`
+    /// before being passed to the callback. Returns `Err` if callback panics.
+    pub fn diff_raw_encoded<CB>(
+        &self,
+        other: &MMFile,
+        encoding: TextEncoding,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]),
+    {
+        self.diff_raw(other, |line| callback(&encoding.encode(line)))
+    }
+}
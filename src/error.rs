@@ -0,0 +1,204 @@
+use core::{
+    ffi::c_int,
+    fmt::{self, Display, Formatter},
+};
+
+/// Errors returned by libxdiff operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Error {
+    /// A libxdiff function returned a failure code
+    Failed { op: &'static str, code: c_int },
+    /// A libxdiff function returned a failure code and set `errno`
+    Os {
+        op: &'static str,
+        code: c_int,
+        errno: c_int,
+    },
+    /// A callback passed the diff's output returned a failure code or
+    /// panicked. `hunk` counts hunks from 0, and `line` counts lines within
+    /// the hunk from 0, or is `None` if the callback failed on the hunk's
+    /// header.
+    Callback {
+        op: &'static str,
+        hunk: usize,
+        line: Option<usize>,
+        panic: Option<String>,
+    },
+    /// The diff's estimated cost exceeded [`DiffOptions::max_cost`](crate::DiffOptions::max_cost)
+    TooExpensive { cost: usize, limit: usize },
+    /// An argument or input was malformed, such as patch text with a bad
+    /// hunk header
+    Invalid(String),
+    /// Reading input failed
+    #[cfg(feature = "std")]
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+    /// Any other failure, described by a message
+    Other(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Failed { op, code } => write!(f, "{} failed with err: {}", op, code),
+            Error::Os { op, code, errno } => {
+                write!(f, "{} failed with err: {} (errno {})", op, code, errno)
+            }
+            Error::Callback {
+                op,
+                hunk,
+                line,
+                panic,
+            } => {
+                let verb = if panic.is_some() {
+                    "panicked"
+                } else {
+                    "failed"
+                };
+                match line {
+                    Some(line) => write!(
+                        f,
+                        "{} callback {} on line {} of hunk {}",
+                        op, verb, line, hunk
+                    )?,
+                    None => write!(f, "{} callback {} on header of hunk {}", op, verb, hunk)?,
+                }
+                match panic {
+                    Some(msg) => write!(f, ": {}", msg),
+                    None => Ok(()),
+                }
+            }
+            Error::TooExpensive { cost, limit } => {
+                write!(f, "diff cost {} exceeds limit {}", cost, limit)
+            }
+            #[cfg(feature = "std")]
+            Error::Io { message, .. } => f.write_str(message),
+            Error::Invalid(msg) | Error::Other(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// Broad categories of [`Error`], for deciding how to react to a failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// libxdiff ran out of memory (`ENOMEM`)
+    OutOfMemory,
+    /// The input was malformed, or libxdiff rejected it (`EINVAL`)
+    InvalidInput,
+    /// The diff's estimated cost exceeded its limit
+    TooExpensive,
+    /// Reading input failed
+    Io,
+    /// Anything else, including failures reported by callbacks
+    Other,
+}
+
+impl Error {
+    /// Build the error for a failed libxdiff call, picking up `errno` if the
+    /// call set it. Call [`clear_errno`] before the call.
+    pub(crate) fn failed(op: &'static str, code: c_int) -> Error {
+        match last_errno() {
+            0 => Error::Failed { op, code },
+            errno => Error::Os { op, code, errno },
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Build the error for a failed read of `what`
+    pub(crate) fn io(what: &str, e: std::io::Error) -> Error {
+        Error::Io {
+            kind: e.kind(),
+            message: format!("failed to read {}: {}", what, e),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Attach the message of a panic to a callback error
+    pub(crate) fn with_panic(self, message: Option<String>) -> Error {
+        match self {
+            Error::Callback { op, hunk, line, .. } if message.is_some() => Error::Callback {
+                op,
+                hunk,
+                line,
+                panic: message,
+            },
+            err => err,
+        }
+    }
+
+    /// The category of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Os {
+                errno: libc::ENOMEM,
+                ..
+            } => ErrorKind::OutOfMemory,
+            Error::Os {
+                errno: libc::EINVAL,
+                ..
+            } => ErrorKind::InvalidInput,
+            Error::Invalid(_) => ErrorKind::InvalidInput,
+            Error::TooExpensive { .. } => ErrorKind::TooExpensive,
+            #[cfg(feature = "std")]
+            Error::Io { .. } => ErrorKind::Io,
+            _ => ErrorKind::Other,
+        }
+    }
+
+    /// The `errno` set by the failing libxdiff call, if any
+    pub fn errno(&self) -> Option<c_int> {
+        match self {
+            Error::Os { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "emscripten",
+    target_os = "dragonfly"
+))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__errno_location() })
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+fn errno_location() -> Option<*mut c_int> {
+    Some(unsafe { libc::__error() })
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "emscripten",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "dragonfly"
+)))]
+fn errno_location() -> Option<*mut c_int> {
+    None
+}
+
+/// Reset `errno` so a failure can be attributed to the next libxdiff call
+pub(crate) fn clear_errno() {
+    if let Some(errno) = errno_location() {
+        // SAFETY: errno is thread-local, so nothing else can be accessing it
+        unsafe { *errno = 0 };
+    }
+}
+
+/// The current thread's `errno`, or 0 where it can't be read
+fn last_errno() -> c_int {
+    // SAFETY: as in clear_errno
+    errno_location().map_or(0, |errno| unsafe { *errno })
+}
@@ -0,0 +1,49 @@
+//! Adapter letting gitoxide-style blob diffing use libxdiff. gitoxide's
+//! `gix_diff::blob` module re-exports `imara-diff`, so the [`Sink`] and
+//! [`InternedInput`] types here are the ones gix tools already use.
+
+use imara_diff::{
+    intern::{InternedInput, Token},
+    Sink,
+};
+
+use crate::{DiffOptions, Error, MMFile};
+
+/// Compute the diff between the interned `before` and `after` token
+/// sequences with libxdiff, passing each change to `sink` in order. This is a
+/// drop-in replacement for `gix_diff::blob::diff(algorithm, input, sink)`.
+pub fn diff<S: Sink, T>(input: &InternedInput<T>, sink: S) -> Result<S::Out, Error> {
+    diff_with_tokens(&input.before, &input.after, sink)
+}
+
+/// Compute the diff between two token sequences with libxdiff, passing each
+/// change to `sink` in order
+pub fn diff_with_tokens<S: Sink>(
+    before: &[Token],
+    after: &[Token],
+    mut sink: S,
+) -> Result<S::Out, Error> {
+    // each token becomes one line holding its id, so libxdiff compares
+    // exactly the token sequences
+    let old = tokens_to_file(before);
+    let new = tokens_to_file(after);
+    let opts = DiffOptions::new().context_len(0);
+    for hunk in old.diff_hunks(&new, &opts)? {
+        let before = hunk.old_lines();
+        let after = hunk.new_lines();
+        sink.process_change(
+            before.start as u32..before.end as u32,
+            after.start as u32..after.end as u32,
+        );
+    }
+    Ok(sink.finish())
+}
+
+fn tokens_to_file(tokens: &[Token]) -> MMFile {
+    let mut data = Vec::with_capacity(tokens.len() * 4);
+    for token in tokens {
+        data.extend_from_slice(u32::from(*token).to_string().as_bytes());
+        data.push(b'\n');
+    }
+    MMFile::from_bytes(&data)
+}
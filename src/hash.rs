@@ -0,0 +1,97 @@
+use core::slice;
+
+use crate::{MMBlocks, MMFile};
+
+/// Streaming 64-bit hasher: xxh3 with the `xxhash` feature, FNV-1a otherwise
+struct ContentHasher {
+    #[cfg(feature = "xxhash")]
+    inner: xxhash_rust::xxh3::Xxh3,
+    #[cfg(not(feature = "xxhash"))]
+    state: u64,
+}
+
+impl ContentHasher {
+    fn new() -> Self {
+        Self {
+            #[cfg(feature = "xxhash")]
+            inner: xxhash_rust::xxh3::Xxh3::new(),
+            #[cfg(not(feature = "xxhash"))]
+            state: 0xcbf2_9ce4_8422_2325,
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        #[cfg(feature = "xxhash")]
+        self.inner.update(bytes);
+        #[cfg(not(feature = "xxhash"))]
+        for b in bytes {
+            self.state = (self.state ^ *b as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        #[cfg(feature = "xxhash")]
+        return self.inner.digest();
+        #[cfg(not(feature = "xxhash"))]
+        return self.state;
+    }
+}
+
+/// Identifies which hasher [`hash_bytes`] uses, for data that persists hashes
+#[cfg(all(feature = "std", feature = "xxhash"))]
+pub(crate) const HASH_KIND: u8 = 1;
+#[cfg(all(feature = "std", not(feature = "xxhash")))]
+pub(crate) const HASH_KIND: u8 = 0;
+
+/// Hash a byte slice the same way as [`MMFile::content_hash`]
+pub(crate) fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = ContentHasher::new();
+    hasher.update(bytes);
+    hasher.finish()
+}
+
+impl MMFile {
+    /// Hash of the file's contents, for cheaply detecting duplicates. Equal
+    /// contents always hash equally, but the value depends on whether the
+    /// `xxhash` feature is enabled and should not be persisted. The hash is
+    /// cached until the file is mutated.
+    pub fn content_hash(&self) -> u64 {
+        *self.hash.get_or_init(|| hash_bytes(self.as_slice()))
+    }
+}
+
+impl MMBlocks {
+    /// Hash of the contents, equal to [`MMFile::content_hash`] of the same
+    /// bytes regardless of how they are split into blocks.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = ContentHasher::new();
+        let mut block = self.inner.head;
+        while !block.is_null() {
+            // SAFETY: the block chain is owned by this file and each block
+            // holds `size` initialized bytes
+            let b = unsafe { &*block };
+            if b.size > 0 {
+                hasher
+                    .update(unsafe { slice::from_raw_parts(b.ptr as *const u8, b.size as usize) });
+            }
+            block = b.next;
+        }
+        hasher.finish()
+    }
+}
+
+/// The id git gives a blob holding `bytes`: the SHA-1 of a `blob <len>\0`
+/// header followed by the bytes, in hex
+#[cfg(feature = "git-header")]
+pub(crate) fn git_blob_id(bytes: &[u8]) -> String {
+    use sha1::{Digest, Sha1};
+
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", bytes.len()));
+    hasher.update(bytes);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
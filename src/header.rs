@@ -0,0 +1,215 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "git-header")]
+use crate::hash::git_blob_id;
+use crate::{rewrite::preamble, DiffOptions, Patch};
+
+#[cfg(feature = "git-header")]
+/// Mode git gives regular, non-executable files
+const REGULAR_MODE: u32 = 0o100644;
+
+/// Describes the file a diff belongs to, for emitting git's extended
+/// headers. Paths are written as given, without git's quoting of unusual
+/// characters.
+#[cfg(feature = "git-header")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHeader {
+    pub(crate) old_path: Option<String>,
+    pub(crate) new_path: Option<String>,
+    pub(crate) old_mode: u32,
+    pub(crate) new_mode: u32,
+}
+
+#[cfg(feature = "git-header")]
+impl GitHeader {
+    /// A regular file changed from `old_path` to `new_path`. Differing paths
+    /// are written as a rename.
+    pub fn new(old_path: impl Into<String>, new_path: impl Into<String>) -> Self {
+        GitHeader {
+            old_path: Some(old_path.into()),
+            new_path: Some(new_path.into()),
+            old_mode: REGULAR_MODE,
+            new_mode: REGULAR_MODE,
+        }
+    }
+
+    /// A regular file created at `path`
+    pub fn created(path: impl Into<String>) -> Self {
+        GitHeader {
+            old_path: None,
+            ..GitHeader::new("", path)
+        }
+    }
+
+    /// A regular file deleted from `path`
+    pub fn deleted(path: impl Into<String>) -> Self {
+        GitHeader {
+            new_path: None,
+            ..GitHeader::new(path, "")
+        }
+    }
+
+    /// Set the file's mode before and after the change, such as `0o100755`
+    /// for an executable
+    pub fn modes(mut self, old_mode: u32, new_mode: u32) -> Self {
+        self.old_mode = old_mode;
+        self.new_mode = new_mode;
+        self
+    }
+
+    /// The `---`/`+++` labels git gives the two sides
+    fn labels(&self) -> (String, String) {
+        let label = |prefix: &str, path: &Option<String>| match path {
+            Some(path) => format!("{}/{}", prefix, path),
+            None => "/dev/null".to_owned(),
+        };
+        (label("a", &self.old_path), label("b", &self.new_path))
+    }
+
+    /// Write the extended header lines for a diff from `old` to `new`, or
+    /// nothing if the diff has no hunks and the file keeps its path and mode
+    fn write(&self, out: &mut Vec<u8>, old: &[u8], new: &[u8], has_hunks: bool) {
+        let old_path = self.old_path.as_deref();
+        let new_path = self.new_path.as_deref();
+        let renamed = matches!((old_path, new_path), (Some(a), Some(b)) if a != b);
+        let mode_changed =
+            old_path.is_some() && new_path.is_some() && self.old_mode != self.new_mode;
+        if !has_hunks && !renamed && !mode_changed && old_path.is_some() && new_path.is_some() {
+            return;
+        }
+        let (a, b) = (old_path.or(new_path), new_path.or(old_path));
+        let a = a.unwrap_or_default();
+        let b = b.unwrap_or_default();
+        out.extend_from_slice(format!("diff --git a/{} b/{}\n", a, b).as_bytes());
+        let null_id = "0".repeat(40);
+        let old_id = old_path.map_or(null_id.clone(), |_| git_blob_id(old));
+        let new_id = new_path.map_or(null_id, |_| git_blob_id(new));
+        let mut index_mode = String::new();
+        match (old_path, new_path) {
+            (None, _) => {
+                out.extend_from_slice(format!("new file mode {:o}\n", self.new_mode).as_bytes())
+            }
+            (_, None) => {
+                out.extend_from_slice(format!("deleted file mode {:o}\n", self.old_mode).as_bytes())
+            }
+            _ if mode_changed => out.extend_from_slice(
+                format!(
+                    "old mode {:o}\nnew mode {:o}\n",
+                    self.old_mode, self.new_mode
+                )
+                .as_bytes(),
+            ),
+            _ => index_mode = format!(" {:o}", self.old_mode),
+        }
+        if renamed {
+            out.extend_from_slice(format!("rename from {}\nrename to {}\n", a, b).as_bytes());
+        }
+        if old_id != new_id {
+            out.extend_from_slice(
+                format!("index {}..{}{}\n", old_id, new_id, index_mode).as_bytes(),
+            );
+        }
+    }
+}
+
+/// The file header lines `opts` asks for ahead of the hunks of a diff from
+/// `old` to `new`
+#[cfg_attr(not(feature = "git-header"), allow(unused_variables))]
+pub(crate) fn file_header(opts: &DiffOptions, old: &[u8], new: &[u8], has_hunks: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    #[cfg(feature = "git-header")]
+    if let Some(git) = &opts.git_header {
+        git.write(&mut out, old, new, has_hunks);
+    }
+    let labels = opts.labels.clone();
+    #[cfg(feature = "git-header")]
+    let labels = labels.or_else(|| opts.git_header.as_ref().map(GitHeader::labels));
+    if let (true, Some((old_label, new_label))) = (has_hunks, labels) {
+        let (old_time, new_time) = match opts.timestamps {
+            Some((old, new)) => (
+                format!("\t{}", format_timestamp(old)),
+                format!("\t{}", format_timestamp(new)),
+            ),
+            None => Default::default(),
+        };
+        out.extend_from_slice(
+            format!(
+                "--- {}{}\n+++ {}{}\n",
+                old_label, old_time, new_label, new_time
+            )
+            .as_bytes(),
+        );
+    }
+    out
+}
+
+/// Format a time as diff -u does, in UTC: `2002-02-21 23:30:39.942229878 +0000`.
+/// Times before 1970 are written as the epoch.
+fn format_timestamp(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // civil date from days since the epoch, after Howard Hinnant
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09} +0000",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60,
+        since.subsec_nanos()
+    )
+}
+
+/// One side's `---` or `+++` file header line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileLabel {
+    /// The label, usually a path such as `a/file`
+    pub name: String,
+    /// The text after the tab following the label, usually a timestamp,
+    /// left unparsed since tools write it in different formats
+    pub timestamp: Option<String>,
+}
+
+impl FileLabel {
+    fn parse(line: &[u8], prefix: &[u8]) -> Option<FileLabel> {
+        let rest = line.strip_prefix(prefix)?;
+        let rest = String::from_utf8_lossy(rest);
+        let rest = rest.trim_end_matches(['\n', '\r']);
+        Some(match rest.split_once('\t') {
+            Some((name, timestamp)) => FileLabel {
+                name: name.to_owned(),
+                timestamp: Some(timestamp.to_owned()),
+            },
+            None => FileLabel {
+                name: rest.to_owned(),
+                timestamp: None,
+            },
+        })
+    }
+}
+
+impl Patch {
+    /// The `---` and `+++` file header lines before the first hunk, if the
+    /// patch has both
+    pub fn file_labels(&self) -> Option<(FileLabel, FileLabel)> {
+        let mut lines = preamble(self.as_slice()).split_inclusive(|b| *b == b'\n');
+        while let Some(line) = lines.next() {
+            if let Some(old) = FileLabel::parse(line, b"--- ") {
+                let new = FileLabel::parse(lines.next()?, b"+++ ")?;
+                return Some((old, new));
+            }
+        }
+        None
+    }
+}
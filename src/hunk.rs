@@ -0,0 +1,526 @@
+#[cfg(feature = "ffi")]
+use core::ffi::c_int;
+use core::{ops::Range, str::from_utf8};
+
+use crate::{
+    lines::{byte_range, line_starts},
+    Error,
+};
+#[cfg(feature = "ffi")]
+use crate::{DiffOptions, MMFile};
+
+pub(crate) const NO_NEWLINE: &[u8] = b"\n\\ No newline at end of file\n";
+
+/// Line ranges described by a unified diff hunk header such as
+/// `@@ -1,3 +1,4 @@`. Starts are 1-based, as they appear in the header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HunkHeader {
+    pub old_start: usize,
+    pub old_len: usize,
+    pub new_start: usize,
+    pub new_len: usize,
+}
+
+impl HunkHeader {
+    /// Parse a hunk header line. Returns `None` if `line` is not a header.
+    pub fn parse(line: &[u8]) -> Option<HunkHeader> {
+        let line = from_utf8(line).ok()?;
+        let rest = line.strip_prefix("@@ -")?;
+        let (ranges, _) = rest.split_once(" @@")?;
+        let (old, new) = ranges.split_once(" +")?;
+        let (old_start, old_len) = parse_range(old)?;
+        let (new_start, new_len) = parse_range(new)?;
+        Some(HunkHeader {
+            old_start,
+            old_len,
+            new_start,
+            new_len,
+        })
+    }
+
+    /// Render this header in the same format libxdiff emits
+    pub fn to_bytes(&self) -> Vec<u8> {
+        format!(
+            "@@ -{},{} +{},{} @@\n",
+            self.old_start, self.old_len, self.new_start, self.new_len
+        )
+        .into_bytes()
+    }
+
+    /// Shift both sides of this header down by the given number of lines
+    pub(crate) fn offset(&self, old_offset: usize, new_offset: usize) -> HunkHeader {
+        HunkHeader {
+            old_start: self.old_start + old_offset,
+            old_len: self.old_len,
+            new_start: self.new_start + new_offset,
+            new_len: self.new_len,
+        }
+    }
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    match range.split_once(',') {
+        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+        None => Some((range.parse().ok()?, 1)),
+    }
+}
+
+/// Whether a line of a hunk is shared, removed, or added
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeTag {
+    /// Context line present in both files
+    Equal,
+    /// Line only present in the old file
+    Delete,
+    /// Line only present in the new file
+    Insert,
+}
+
+impl ChangeTag {
+    /// The prefix character used for this kind of line in unified diffs
+    pub fn prefix(&self) -> u8 {
+        match self {
+            ChangeTag::Equal => b' ',
+            ChangeTag::Delete => b'-',
+            ChangeTag::Insert => b'+',
+        }
+    }
+
+    pub(crate) fn from_prefix(prefix: u8) -> Option<ChangeTag> {
+        match prefix {
+            b' ' => Some(ChangeTag::Equal),
+            b'-' => Some(ChangeTag::Delete),
+            b'+' => Some(ChangeTag::Insert),
+            _ => None,
+        }
+    }
+}
+
+/// One self-describing step of a diff, as passed to
+/// [`MMFile::diff_events`](crate::MMFile::diff_events)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffEvent<'a> {
+    /// A new hunk starts, with its header as libxdiff wrote it
+    HunkStart(HunkHeader),
+    /// A line present in both files. Lines lack a trailing newline only
+    /// where the file does.
+    Context(&'a [u8]),
+    /// A line only present in the old file
+    Removed(&'a [u8]),
+    /// A line only present in the new file
+    Added(&'a [u8]),
+}
+
+/// What a buffer passed to [`MMFile::diff_raw_tagged`](crate::MMFile::diff_raw_tagged)
+/// holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BufferKind {
+    /// A hunk header line, such as `@@ -1,3 +1,3 @@\n`
+    HunkHeader,
+    /// The `" "`, `"-"` or `"+"` prefix of a line
+    Prefix(ChangeTag),
+    /// The contents of a line
+    Content,
+    /// The `\ No newline at end of file` line following content that lacks
+    /// a newline
+    NoNewlineMarker,
+}
+
+/// A single line of a [`Hunk`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub tag: ChangeTag,
+    /// Contents of the line, including its newline if it has one
+    pub line: Vec<u8>,
+    /// 0-based index of the line in the old file, if it appears there
+    pub old_index: Option<usize>,
+    /// 0-based index of the line in the new file, if it appears there
+    pub new_index: Option<usize>,
+    /// Byte offset of the line in the old file, if known and it appears there
+    pub old_offset: Option<usize>,
+    /// Byte offset of the line in the new file, if known and it appears there
+    pub new_offset: Option<usize>,
+}
+
+/// A structured diff hunk: its header and the lines it contains
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub header: HunkHeader,
+    pub changes: Vec<Change>,
+    /// Bytes of the old file covered by this hunk, if known
+    pub old_bytes: Option<Range<usize>>,
+    /// Bytes of the new file covered by this hunk, if known
+    pub new_bytes: Option<Range<usize>>,
+}
+
+impl Hunk {
+    /// 0-based, half-open range of old file lines covered by this hunk
+    pub fn old_lines(&self) -> Range<usize> {
+        line_range(self.header.old_start, self.header.old_len)
+    }
+
+    /// 0-based, half-open range of new file lines covered by this hunk
+    pub fn new_lines(&self) -> Range<usize> {
+        line_range(self.header.new_start, self.header.new_len)
+    }
+
+    /// The range of old lines actually changed by this hunk, excluding
+    /// leading and trailing context
+    pub fn old_changed_lines(&self) -> Range<usize> {
+        let (lead, trail) = self.context_lens();
+        let lines = self.old_lines();
+        lines.start + lead..lines.end - trail
+    }
+
+    /// The range of new lines actually changed by this hunk, excluding
+    /// leading and trailing context
+    pub fn new_changed_lines(&self) -> Range<usize> {
+        let (lead, trail) = self.context_lens();
+        let lines = self.new_lines();
+        lines.start + lead..lines.end - trail
+    }
+
+    /// Number of context lines at the start and end of this hunk
+    fn context_lens(&self) -> (usize, usize) {
+        let is_context = |c: &&Change| c.tag == ChangeTag::Equal;
+        let lead = self.changes.iter().take_while(is_context).count();
+        if lead == self.changes.len() {
+            return (lead, 0);
+        }
+        let trail = self.changes.iter().rev().take_while(is_context).count();
+        (lead, trail)
+    }
+}
+
+// In headers, an empty side's start is the line after which lines are
+// inserted, so it is already the 0-based index of the next line
+fn line_range(start: usize, len: usize) -> Range<usize> {
+    match start.checked_sub(1) {
+        _ if len == 0 => start..start,
+        Some(first) => first..first + len,
+        // a non-empty side can't start at line 0; parsing rejects it
+        None => 0..len,
+    }
+}
+
+/// Lines changed by one contiguous edit, as 0-based line ranges. An empty
+/// range marks the position where lines were inserted or deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineChange {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Assembles [`Hunk`]s from a stream of headers and prefixed lines
+#[derive(Default)]
+pub(crate) struct HunkBuilder {
+    pub(crate) hunks: Vec<Hunk>,
+    old_next: usize,
+    new_next: usize,
+    // net change in line count from all hunks before the current one
+    delta: isize,
+}
+
+impl HunkBuilder {
+    pub(crate) fn header(&mut self, header: HunkHeader) {
+        if let Some(prev) = self.hunks.last() {
+            self.delta += prev.header.new_len as isize - prev.header.old_len as isize;
+        }
+        // an empty side may be numbered 0, so derive its position from the
+        // other side instead of trusting the header
+        self.old_next = if header.old_len > 0 {
+            header.old_start.saturating_sub(1)
+        } else {
+            (header.new_start.saturating_sub(1) as isize - self.delta).max(0) as usize
+        };
+        self.new_next = if header.new_len > 0 {
+            header.new_start.saturating_sub(1)
+        } else {
+            (header.old_start.saturating_sub(1) as isize + self.delta).max(0) as usize
+        };
+        let mut header = header;
+        if header.old_len == 0 {
+            header.old_start = self.old_next;
+        }
+        if header.new_len == 0 {
+            header.new_start = self.new_next;
+        }
+        self.hunks.push(Hunk {
+            header,
+            changes: Vec::new(),
+            old_bytes: None,
+            new_bytes: None,
+        });
+    }
+
+    /// Add a line to the current hunk. Returns `false` if there is no hunk.
+    pub(crate) fn line(&mut self, tag: ChangeTag, line: &[u8]) -> bool {
+        let Some(hunk) = self.hunks.last_mut() else {
+            return false;
+        };
+        let old_index = (tag != ChangeTag::Insert).then_some(self.old_next);
+        let new_index = (tag != ChangeTag::Delete).then_some(self.new_next);
+        self.old_next += old_index.is_some() as usize;
+        self.new_next += new_index.is_some() as usize;
+        hunk.changes.push(Change {
+            tag,
+            line: line.to_owned(),
+            old_index,
+            new_index,
+            old_offset: None,
+            new_offset: None,
+        });
+        true
+    }
+
+    /// Add a record as emitted by libxdiff: either a header, or a prefix, a
+    /// line, and possibly a missing-newline marker
+    pub(crate) fn record(&mut self, record: &[&[u8]]) -> bool {
+        match record {
+            [header] => match HunkHeader::parse(header) {
+                Some(h) => {
+                    self.header(h);
+                    true
+                }
+                None => false,
+            },
+            [prefix, line, ..] if prefix.len() == 1 => match ChangeTag::from_prefix(prefix[0]) {
+                Some(tag) => self.line(tag, line),
+                None => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Fill in byte offsets of every hunk and change given the contents of
+    /// both files
+    pub(crate) fn locate(&mut self, old: &[u8], new: &[u8]) {
+        locate_hunks(&mut self.hunks, old, new);
+    }
+}
+
+/// Fill in byte offsets of every hunk and change given the contents of both
+/// files and the line indices of each change
+pub(crate) fn locate_hunks(hunks: &mut [Hunk], old: &[u8], new: &[u8]) {
+    let old_starts = line_starts(old);
+    let new_starts = line_starts(new);
+    for hunk in hunks.iter_mut() {
+        for change in hunk.changes.iter_mut() {
+            change.old_offset = change.old_index.map(|i| old_starts[i]);
+            change.new_offset = change.new_index.map(|i| new_starts[i]);
+        }
+        // a side with no lines is positioned by its header
+        let old_first = first_index(hunk, |c| c.old_index).unwrap_or(hunk.old_lines().start);
+        let new_first = first_index(hunk, |c| c.new_index).unwrap_or(hunk.new_lines().start);
+        let old_end = old_first + hunk.header.old_len;
+        let new_end = new_first + hunk.header.new_len;
+        hunk.old_bytes = Some(byte_range(&old_starts, old_first..old_end));
+        hunk.new_bytes = Some(byte_range(&new_starts, new_first..new_end));
+    }
+}
+
+/// Parse the hunks of a unified diff, such as a patch produced by
+/// [`MMFile::compute_patch`]. Lines before the first hunk header are ignored.
+pub(crate) fn parse_hunks(patch: &[u8]) -> Result<Vec<Hunk>, Error> {
+    let mut builder = HunkBuilder::default();
+    for line in patch.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(b"@@") {
+            match HunkHeader::parse(line) {
+                Some(h)
+                    if (h.old_start == 0 && h.old_len > 0)
+                        || (h.new_start == 0 && h.new_len > 0) =>
+                {
+                    return Err(Error::Invalid(format!(
+                        "hunk header starts a non-empty side at line 0: {:?}",
+                        from_utf8(line)
+                    )))
+                }
+                Some(h) => builder.header(h),
+                None => {
+                    return Err(Error::Invalid(format!(
+                        "malformed hunk header: {:?}",
+                        from_utf8(line)
+                    )))
+                }
+            }
+        } else if line.starts_with(b"\\") {
+            // "\ No newline at end of file" applies to the previous line
+            let last = builder.hunks.last_mut().and_then(|h| h.changes.last_mut());
+            if let Some(change) = last {
+                if change.line.ends_with(b"\n") {
+                    change.line.pop();
+                }
+            }
+        } else if builder.hunks.is_empty() {
+            // file headers such as `---`/`+++` look like changed lines, but
+            // nothing before the first hunk header belongs to a hunk
+            continue;
+        } else if let Some(tag) = line.first().copied().and_then(ChangeTag::from_prefix) {
+            builder.line(tag, &line[1..]);
+        } else {
+            return Err(Error::Invalid(format!(
+                "unexpected line in hunk: {:?}",
+                from_utf8(line)
+            )));
+        }
+    }
+    for hunk in builder.hunks.iter() {
+        let old_len = hunk
+            .changes
+            .iter()
+            .filter(|c| c.old_index.is_some())
+            .count();
+        let new_len = hunk
+            .changes
+            .iter()
+            .filter(|c| c.new_index.is_some())
+            .count();
+        if old_len != hunk.header.old_len || new_len != hunk.header.new_len {
+            return Err(Error::Invalid(format!(
+                "hunk line counts do not match header: {:?}",
+                from_utf8(&hunk.header.to_bytes())
+            )));
+        }
+    }
+    Ok(builder.hunks)
+}
+
+/// Append a hunk to `out` in unified diff format
+pub(crate) fn write_hunk(out: &mut Vec<u8>, hunk: &Hunk) {
+    out.extend_from_slice(&hunk.header.to_bytes());
+    for change in hunk.changes.iter() {
+        out.push(change.tag.prefix());
+        out.extend_from_slice(&change.line);
+        if !change.line.ends_with(b"\n") {
+            out.extend_from_slice(NO_NEWLINE);
+        }
+    }
+}
+
+fn first_index<F: Fn(&Change) -> Option<usize>>(hunk: &Hunk, index: F) -> Option<usize> {
+    hunk.changes.iter().find_map(index)
+}
+
+#[cfg(feature = "ffi")]
+impl MMFile {
+    /// Compute the diff to turn self into other as a list of structured
+    /// hunks, including line numbers and byte offsets into both files.
+    pub fn diff_hunks(&self, other: &MMFile, opts: &DiffOptions) -> Result<Vec<Hunk>, Error> {
+        let mut builder = HunkBuilder::default();
+        // SAFETY: the callback only pushes to a Vec and doesn't panic
+        unsafe {
+            self.diff_records(other, opts, |record| {
+                if builder.record(record) {
+                    0
+                } else {
+                    -1 as c_int
+                }
+            })?
+        };
+        builder.locate(self.as_slice(), other.as_slice());
+        Ok(builder.hunks)
+    }
+
+    /// Compute the diff like [`diff_hunks`](Self::diff_hunks), also
+    /// returning whether any hunks were left out because of
+    /// [`DiffOptions::max_hunks`]
+    pub fn diff_hunks_limited(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+    ) -> Result<(Vec<Hunk>, bool), Error> {
+        let mut builder = HunkBuilder::default();
+        // SAFETY: the callback only pushes to a Vec and doesn't panic
+        let truncated = unsafe {
+            self.diff_records_limited(
+                other,
+                opts,
+                opts.max_hunks.unwrap_or(usize::MAX),
+                |record| {
+                    if builder.record(record) {
+                        0
+                    } else {
+                        -1 as c_int
+                    }
+                },
+            )?
+        };
+        builder.locate(self.as_slice(), other.as_slice());
+        Ok((builder.hunks, truncated))
+    }
+
+    /// Compute the diff like [`diff_hunks`](Self::diff_hunks), but only
+    /// build the hunks that overlap `lines` of this file (0-based, half-open
+    /// ranges), context included, for showing one section of a large file.
+    /// The lines of other hunks are skipped without being copied.
+    pub fn diff_hunks_in(
+        &self,
+        other: &MMFile,
+        lines: &[Range<usize>],
+        opts: &DiffOptions,
+    ) -> Result<Vec<Hunk>, Error> {
+        let wanted = |hunk: &Hunk| {
+            let old = hunk.old_lines();
+            lines.iter().any(|r| {
+                (old.start < r.end && r.start < old.end)
+                    || (old.is_empty() && r.start <= old.start && old.start <= r.end)
+            })
+        };
+        let mut builder = HunkBuilder::default();
+        let mut keep = Vec::new();
+        // SAFETY: the callback only pushes to Vecs and doesn't panic
+        unsafe {
+            self.diff_records(other, opts, |record| {
+                let ok = match record {
+                    [_] => {
+                        let ok = builder.record(record);
+                        keep.push(ok && builder.hunks.last().is_some_and(wanted));
+                        ok
+                    }
+                    _ if keep.last() == Some(&true) => builder.record(record),
+                    _ => true,
+                };
+                if ok {
+                    0
+                } else {
+                    -1 as c_int
+                }
+            })?
+        };
+        let mut keep = keep.into_iter();
+        builder.hunks.retain(|_| keep.next().unwrap_or(false));
+        builder.locate(self.as_slice(), other.as_slice());
+        Ok(builder.hunks)
+    }
+
+    /// Compute only the line ranges changed in each file, without any line
+    /// contents. Context settings in `opts` are ignored.
+    pub fn changed_lines(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+    ) -> Result<Vec<LineChange>, Error> {
+        let opts = opts.clone().context_len(0);
+        let mut builder = HunkBuilder::default();
+        // SAFETY: the callback only pushes to a Vec and doesn't panic
+        unsafe {
+            self.diff_records(other, &opts, |record| {
+                if let [header] = record {
+                    match HunkHeader::parse(header) {
+                        Some(h) => builder.header(h),
+                        None => return -1 as c_int,
+                    }
+                }
+                0
+            })?
+        };
+        Ok(builder
+            .hunks
+            .iter()
+            .map(|h| LineChange {
+                old: line_range(h.header.old_start, h.header.old_len),
+                new: line_range(h.header.new_start, h.header.new_len),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,94 @@
+use core::{ffi::c_int, marker::PhantomData};
+use std::{
+    sync::mpsc::{sync_channel, Receiver},
+    thread,
+};
+
+use crate::{hunk::HunkBuilder, DiffOptions, Error, Hunk, MMFile};
+
+/// Hunks of a diff running on a worker thread, yielded as the diff emits
+/// them. Created by [`MMFile::diff_hunks_stream`].
+#[derive(Debug)]
+pub struct HunkStream<'scope> {
+    receiver: Receiver<Hunk>,
+    // keeps the stream from outliving the worker feeding it
+    _scope: PhantomData<&'scope ()>,
+}
+
+impl Iterator for HunkStream<'_> {
+    type Item = Hunk;
+
+    fn next(&mut self) -> Option<Hunk> {
+        self.receiver.recv().ok()
+    }
+}
+
+impl MMFile {
+    /// Compute the diff like [`diff_hunks`](Self::diff_hunks), but run it on
+    /// a scoped worker thread and pass `consume` an iterator yielding each
+    /// hunk as soon as it is complete. At most `bound` finished hunks are
+    /// buffered, so memory stays bounded however large the diff. Hunks carry
+    /// line numbers but not byte offsets.
+    ///
+    /// If `consume` returns without exhausting the stream, the diff is
+    /// stopped early. Returns what `consume` returns, or `Err` if the diff
+    /// failed before `consume` stopped reading.
+    pub fn diff_hunks_stream<R, F>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        bound: usize,
+        consume: F,
+    ) -> Result<R, Error>
+    where
+        F: for<'scope> FnOnce(HunkStream<'scope>) -> R,
+    {
+        let (sender, receiver) = sync_channel(bound);
+        // clones share the buffers, and unlike references can be sent
+        let (old, new) = (self.clone(), other.clone());
+        thread::scope(|scope| {
+            let worker = scope.spawn(move || {
+                let mut builder = HunkBuilder::default();
+                let mut disconnected = false;
+                // SAFETY: the callback only builds hunks and sends them,
+                // neither of which panics
+                let result = unsafe {
+                    old.diff_records(&new, opts, |record| {
+                        let is_header = record.len() == 1;
+                        if !builder.record(record) {
+                            return -1 as c_int;
+                        }
+                        // the builder numbers a hunk from the one before it,
+                        // so keep the newest and send the rest
+                        if is_header && builder.hunks.len() > 1 {
+                            let hunk = builder.hunks.remove(0);
+                            if sender.send(hunk).is_err() {
+                                disconnected = true;
+                                return -1;
+                            }
+                        }
+                        0
+                    })
+                };
+                for hunk in builder.hunks.drain(..) {
+                    if disconnected || sender.send(hunk).is_err() {
+                        disconnected = true;
+                        break;
+                    }
+                }
+                match result {
+                    Err(_) if disconnected => Ok(()),
+                    result => result,
+                }
+            });
+            let out = consume(HunkStream {
+                receiver,
+                _scope: PhantomData,
+            });
+            match worker.join() {
+                Ok(result) => result.map(|()| out),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        })
+    }
+}
@@ -0,0 +1,127 @@
+use core::ops::Range;
+
+use crate::{
+    hunk::locate_hunks,
+    lines::{byte_range, line_starts},
+    DiffOptions, Error, Hunk, MMFile,
+};
+
+/// A diff that is kept up to date as the new file is edited, re-diffing only
+/// the lines around each edit. Hunks are kept without context lines so that
+/// everything outside them is known to be unchanged.
+#[derive(Debug, Clone)]
+pub struct IncrementalDiff {
+    old: MMFile,
+    new: MMFile,
+    opts: DiffOptions,
+    hunks: Vec<Hunk>,
+}
+
+impl IncrementalDiff {
+    /// Diff two files, ignoring the context length in `opts`
+    pub fn new(old: MMFile, new: MMFile, opts: &DiffOptions) -> Result<Self, Error> {
+        let opts = opts.clone().context_len(0);
+        let hunks = old.diff_hunks(&new, &opts)?;
+        Ok(IncrementalDiff {
+            old,
+            new,
+            opts,
+            hunks,
+        })
+    }
+
+    /// The current hunks, without context lines
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    /// The file being diffed against
+    pub fn old_file(&self) -> &MMFile {
+        &self.old
+    }
+
+    /// The edited file
+    pub fn new_file(&self) -> &MMFile {
+        &self.new
+    }
+
+    /// Replace bytes `range` of the new file with `replacement` and update
+    /// the hunks that the edit touches. Returns `Err` if the range is out of
+    /// bounds or the diff fails.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &[u8]) -> Result<(), Error> {
+        let new_bytes = self.new.as_slice();
+        if range.start > range.end || range.end > new_bytes.len() {
+            return Err(Error::Invalid(format!(
+                "edit range {:?} out of bounds for {} bytes",
+                range,
+                new_bytes.len()
+            )));
+        }
+        let starts = line_starts(new_bytes);
+        let line_count = starts.len() - 1;
+        let line_of = |byte: usize| starts.partition_point(|s| *s <= byte).saturating_sub(1);
+
+        // lines of the new file touched by the edit, grown to cover any hunk
+        // overlapping or adjacent to them
+        let mut first = line_of(range.start).min(line_count);
+        let mut last = (line_of(range.end) + 1).min(line_count);
+        let before = self.hunks.partition_point(|h| h.new_lines().end < first);
+        let mut after = before;
+        while let Some(h) = self.hunks.get(after) {
+            let lines = h.new_lines();
+            if lines.start > last {
+                break;
+            }
+            first = first.min(lines.start);
+            last = last.max(lines.end);
+            after += 1;
+        }
+
+        // everything outside hunks is unchanged, so the region maps back to
+        // the old file by the line count difference of earlier hunks
+        let delta_before: isize = self.hunks[..before].iter().map(line_delta).sum();
+        let delta_within: isize = self.hunks[before..after].iter().map(line_delta).sum();
+        let old_first = (first as isize - delta_before) as usize;
+        let old_last = (last as isize - delta_before - delta_within) as usize;
+
+        let region = byte_range(&starts, first..last);
+        let mut edited = Vec::with_capacity(new_bytes.len() + replacement.len());
+        edited.extend_from_slice(&new_bytes[..range.start]);
+        edited.extend_from_slice(replacement);
+        edited.extend_from_slice(&new_bytes[range.end..]);
+        let byte_shift = replacement.len() as isize - range.len() as isize;
+        let new_region = region.start..(region.end as isize + byte_shift) as usize;
+        let region_lines = line_starts(&edited[new_region.clone()]).len() - 1;
+        let line_shift = (first + region_lines) as isize - last as isize;
+
+        let (old_slice, _) = self.old.slice_lines(old_first..old_last);
+        let new_slice = MMFile::from_bytes(&edited[new_region]);
+        let mut replaced = old_slice.diff_hunks(&new_slice, &self.opts)?;
+        for hunk in replaced.iter_mut() {
+            shift_hunk(hunk, old_first as isize, first as isize);
+        }
+        for hunk in self.hunks[after..].iter_mut() {
+            shift_hunk(hunk, 0, line_shift);
+        }
+        self.hunks.splice(before..after, replaced);
+
+        self.new = MMFile::from_bytes(&edited);
+        locate_hunks(&mut self.hunks, self.old.as_slice(), self.new.as_slice());
+        Ok(())
+    }
+}
+
+fn line_delta(hunk: &Hunk) -> isize {
+    hunk.header.new_len as isize - hunk.header.old_len as isize
+}
+
+/// Move a hunk down by the given number of lines in each file
+fn shift_hunk(hunk: &mut Hunk, old_shift: isize, new_shift: isize) {
+    let shift = |n: usize, by: isize| (n as isize + by) as usize;
+    hunk.header.old_start = shift(hunk.header.old_start, old_shift);
+    hunk.header.new_start = shift(hunk.header.new_start, new_shift);
+    for change in hunk.changes.iter_mut() {
+        change.old_index = change.old_index.map(|i| shift(i, old_shift));
+        change.new_index = change.new_index.map(|i| shift(i, new_shift));
+    }
+}
@@ -0,0 +1,113 @@
+use core::{
+    ffi::{c_long, c_uint, c_ulong, c_void},
+    mem::MaybeUninit,
+    ptr::{addr_of, null_mut},
+};
+
+#[cfg(not(feature = "std"))]
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(feature = "std")]
+use std::sync::Once;
+
+use libc::{free, malloc, realloc, size_t};
+use libxdiff_sys::{memallocator_t, mmfile_t, xdl_init_mmfile, xdl_set_allocator, XDL_MMF_ATOMIC};
+
+#[cfg(feature = "alloc-stats")]
+use crate::alloc_stats;
+
+#[cfg_attr(feature = "alloc-stats", allow(dead_code))]
+unsafe extern "C" fn wrap_malloc(_obj: *mut c_void, size: c_uint) -> *mut c_void {
+    malloc(size as size_t)
+}
+
+#[cfg_attr(feature = "alloc-stats", allow(dead_code))]
+unsafe extern "C" fn wrap_free(_obj: *mut c_void, ptr: *mut c_void) {
+    free(ptr)
+}
+
+#[cfg_attr(feature = "alloc-stats", allow(dead_code))]
+unsafe extern "C" fn wrap_realloc(
+    _obj: *mut c_void,
+    ptr: *mut c_void,
+    size: c_uint,
+) -> *mut c_void {
+    realloc(ptr, size as size_t)
+}
+
+// must call before using any xdl functions and must only call once
+unsafe fn init() {
+    #[cfg(not(feature = "alloc-stats"))]
+    let alloc_struct = memallocator_t {
+        priv_: null_mut(),
+        malloc: Some(wrap_malloc),
+        free: Some(wrap_free),
+        realloc: Some(wrap_realloc),
+    };
+    #[cfg(feature = "alloc-stats")]
+    let alloc_struct = memallocator_t {
+        priv_: null_mut(),
+        malloc: Some(alloc_stats::counting_malloc),
+        free: Some(alloc_stats::counting_free),
+        realloc: Some(alloc_stats::counting_realloc),
+    };
+    unsafe { xdl_set_allocator(addr_of!(alloc_struct)) };
+}
+
+#[cfg(feature = "std")]
+static INIT: Once = Once::new();
+
+/// Safely ensure libxdiff has been initialized before proceeding.
+/// This is called automatically when [`MMFile`]s are created. From
+/// that point on, the existence of the object means the library has been
+/// initialized already. Threads racing to initialize wait until the
+/// allocator is installed.
+#[cfg(feature = "std")]
+pub(crate) fn ensure_init() {
+    INIT.call_once(|| unsafe { init() });
+}
+
+#[cfg(not(feature = "std"))]
+const UNINITIALIZED: u8 = 0;
+#[cfg(not(feature = "std"))]
+const INITIALIZING: u8 = 1;
+#[cfg(not(feature = "std"))]
+const INITIALIZED: u8 = 2;
+#[cfg(not(feature = "std"))]
+static INIT_STATE: AtomicU8 = AtomicU8::new(UNINITIALIZED);
+
+/// Safely ensure libxdiff has been initialized before proceeding.
+/// This is called automatically when [`MMFile`]s are created. From
+/// that point on, the existence of the object means the library has been
+/// initialized already. Without `std`, threads racing to initialize spin
+/// until the allocator is installed.
+#[cfg(not(feature = "std"))]
+pub(crate) fn ensure_init() {
+    match INIT_STATE.compare_exchange(
+        UNINITIALIZED,
+        INITIALIZING,
+        Ordering::Acquire,
+        Ordering::Acquire,
+    ) {
+        Ok(_) => {
+            unsafe { init() };
+            INIT_STATE.store(INITIALIZED, Ordering::Release);
+        }
+        Err(_) => {
+            while INIT_STATE.load(Ordering::Acquire) != INITIALIZED {
+                core::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Initialize a new mmfile_t
+pub(crate) fn init_mmfile(len: usize) -> mmfile_t {
+    ensure_init();
+    let mut inner: MaybeUninit<mmfile_t> = MaybeUninit::uninit();
+    let inner_ptr = inner.as_mut_ptr();
+    let err = unsafe { xdl_init_mmfile(inner_ptr, len as c_long, XDL_MMF_ATOMIC as c_ulong) };
+    if err != 0 {
+        panic!("mmfile initialization failed");
+    }
+    unsafe { inner.assume_init() }
+}
@@ -6,9 +6,10 @@
 //! This wrapper structures the API by defining all [`MMFile`]s to be compact
 //! (backed by a single buffer). The non-compact form is [`MMBlocks`].
 //!
-//! libxdiff tracks iteration over buffers internally, so some operations that
-//! conceptually are read-only end up requiring `&mut` arguments in order to be
-//! safe.
+//! libxdiff tracks iteration over buffers internally. Diffing hands it a copy
+//! of each file's descriptor, so it only needs `&` arguments, but some other
+//! operations that conceptually are read-only still require `&mut` arguments
+//! in order to be safe.
 //!
 //! # Example
 //!
@@ -16,10 +17,10 @@
 //! use core::str::from_utf8;
 //! use libxdiff::MMFile;
 //!
-//! let mut f1 = MMFile::from_bytes(b"hello world\n");
-//! let mut f2 = MMFile::from_bytes(b"hello world!\n");
+//! let f1 = MMFile::from_bytes(b"hello world\n");
+//! let f2 = MMFile::from_bytes(b"hello world!\n");
 //! let mut diff_lines = Vec::<String>::new();
-//! f1.diff_raw(&mut f2, |line: &[u8]| {
+//! f1.diff_raw(&f2, |line: &[u8]| {
 //!     diff_lines.push(from_utf8(line).unwrap().to_owned());
 //! })
 //! .unwrap();
@@ -36,73 +37,208 @@
 //!
 //! [1]: http://www.xmailserver.org/xdiff-lib.html
 
-#[cfg_attr(not(feature = "std"), no_std)]
-use core::{
-    ffi::{c_long, c_uint, c_ulong, c_void},
-    mem::MaybeUninit,
-    ptr::{addr_of, null_mut},
-    sync::atomic::{AtomicBool, Ordering},
-};
+// helpers shared with the libxdiff-backed APIs go unused without them
+#![cfg_attr(not(feature = "ffi"), allow(dead_code))]
 
-use libc::{free, malloc, realloc, size_t};
-use libxdiff_sys::{memallocator_t, mmfile_t, xdl_init_mmfile, xdl_set_allocator, XDL_MMF_ATOMIC};
+extern crate alloc;
 
+#[cfg_attr(not(feature = "ffi"), allow(unused_macros))]
+/// Enter a tracing span lasting until the end of the enclosing block
+macro_rules! span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($arg)*).entered();
+    };
+}
+
+#[cfg_attr(not(feature = "ffi"), allow(unused_macros))]
+/// Record a tracing event
+macro_rules! event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
+mod error;
+pub use error::{Error, ErrorKind};
+
+#[cfg(feature = "ffi")]
+mod init;
+#[cfg(feature = "ffi")]
+pub(crate) use init::{ensure_init, init_mmfile};
+
+mod version;
+pub use version::{version, Version};
+
+#[cfg(feature = "ffi")]
+mod cost;
+
+#[cfg(feature = "ffi")]
 mod mmfile;
+#[cfg(feature = "ffi")]
 pub use mmfile::*;
 
+#[cfg(feature = "ffi")]
 mod mmblocks;
+#[cfg(feature = "ffi")]
 pub use mmblocks::*;
 
-#[cfg(test)]
-mod tests;
+#[cfg(feature = "ffi")]
+mod shared;
+#[cfg(feature = "ffi")]
+pub use shared::SharedMMFile;
 
-unsafe extern "C" fn wrap_malloc(_obj: *mut c_void, size: c_uint) -> *mut c_void {
-    malloc(size as size_t)
-}
+#[cfg(feature = "ffi")]
+mod options;
+#[cfg(feature = "ffi")]
+pub use options::*;
 
-unsafe extern "C" fn wrap_free(_obj: *mut c_void, ptr: *mut c_void) {
-    free(ptr)
-}
+#[cfg(feature = "ffi")]
+mod header;
+#[cfg(feature = "ffi")]
+pub use header::FileLabel;
+#[cfg(feature = "git-header")]
+pub use header::GitHeader;
 
-unsafe extern "C" fn wrap_realloc(
-    _obj: *mut c_void,
-    ptr: *mut c_void,
-    size: c_uint,
-) -> *mut c_void {
-    realloc(ptr, size as size_t)
-}
+mod hunk;
+pub use hunk::*;
 
-// must call before using any xdl functions and must only call once
-unsafe fn init() {
-    let alloc_struct = memallocator_t {
-        priv_: null_mut(),
-        malloc: Some(wrap_malloc),
-        free: Some(wrap_free),
-        realloc: Some(wrap_realloc),
-    };
-    unsafe { xdl_set_allocator(addr_of!(alloc_struct)) };
-}
+#[cfg(feature = "ffi")]
+mod merge;
+#[cfg(feature = "ffi")]
+pub use merge::*;
 
-static INITIALIZED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "ffi")]
+mod conflict;
+#[cfg(feature = "ffi")]
+pub use conflict::*;
 
-/// Safely ensure libxdiff has been initialized before proceeding. U
-/// This is called automatically when [`MMFile`]s are created. From
-/// that point on, the existence of the object means the library has been
-/// initialized already.
-pub(crate) fn ensure_init() {
-    if let Ok(_) = INITIALIZED.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed) {
-        unsafe { init() }
-    }
-}
+#[cfg(feature = "ffi")]
+mod patch;
+#[cfg(feature = "ffi")]
+pub use patch::*;
 
-/// Initialize a new mmfile_t
-pub(crate) fn init_mmfile(len: usize) -> mmfile_t {
-    ensure_init();
-    let mut inner: MaybeUninit<mmfile_t> = MaybeUninit::uninit();
-    let inner_ptr = inner.as_mut_ptr();
-    let err = unsafe { xdl_init_mmfile(inner_ptr, len as c_long, XDL_MMF_ATOMIC as c_ulong) };
-    if err != 0 {
-        panic!("mmfile initialization failed");
-    }
-    unsafe { inner.assume_init() }
-}
+#[cfg(feature = "ffi")]
+mod prepared;
+#[cfg(feature = "ffi")]
+pub use prepared::PreparedPatch;
+
+#[cfg(feature = "ffi")]
+mod hash;
+
+#[cfg(feature = "ffi")]
+mod binary;
+#[cfg(feature = "ffi")]
+pub use binary::BinaryPatch;
+
+#[cfg(all(feature = "ffi", feature = "std"))]
+pub mod signature;
+
+#[cfg(feature = "ffi")]
+pub mod store;
+
+#[cfg(all(feature = "ffi", feature = "std"))]
+mod bundle;
+#[cfg(all(feature = "ffi", feature = "std"))]
+pub use bundle::PatchBundle;
+
+#[cfg(all(feature = "ffi", feature = "std"))]
+mod stream;
+#[cfg(all(feature = "ffi", feature = "std"))]
+pub use stream::apply_patch_from_reader;
+
+#[cfg(all(feature = "ffi", feature = "std"))]
+mod hunkstream;
+#[cfg(all(feature = "ffi", feature = "std"))]
+pub use hunkstream::HunkStream;
+
+#[cfg(feature = "ffi")]
+mod incremental;
+#[cfg(feature = "ffi")]
+pub use incremental::IncrementalDiff;
+
+#[cfg(feature = "ffi")]
+mod linemap;
+#[cfg(feature = "ffi")]
+pub use linemap::LineMap;
+
+#[cfg(feature = "ffi")]
+mod blame;
+#[cfg(feature = "ffi")]
+pub use blame::{blame, Blame};
+
+#[cfg(feature = "ffi")]
+mod text;
+#[cfg(feature = "ffi")]
+pub use text::NonUtf8;
+
+#[cfg(feature = "ffi")]
+mod rewrite;
+
+#[cfg(feature = "ffi")]
+mod slide;
+
+#[cfg(feature = "ffi")]
+mod cleanup;
+
+#[cfg(feature = "pure-rust")]
+pub mod pure;
+
+#[cfg(feature = "ffi")]
+mod diffstat;
+#[cfg(feature = "ffi")]
+pub use diffstat::diffstat;
+
+#[cfg(feature = "ffi")]
+mod slices;
+#[cfg(feature = "ffi")]
+pub use slices::{apply, merge, ApplyError, MergeOutput};
+
+#[cfg(feature = "ffi")]
+mod charpatch;
+#[cfg(feature = "ffi")]
+pub use charpatch::{CharHunk, CharOp, CharPatch, CharPatchOptions};
+
+#[cfg(feature = "ffi")]
+mod tokenize;
+#[cfg(feature = "ffi")]
+pub use tokenize::{diff_tokens, Separated, TokenChange, Tokenizer, Words};
+
+#[cfg(feature = "ffi")]
+mod syntax;
+#[cfg(feature = "ffi")]
+pub use syntax::{diff_syntax, Lexeme, Lexer};
+
+#[cfg(feature = "arbitrary")]
+mod arbitrary;
+
+#[cfg(feature = "encoding")]
+mod encoding;
+#[cfg(feature = "encoding")]
+pub use encoding::*;
+
+#[cfg(feature = "diffy")]
+mod diffy;
+
+#[cfg(feature = "gix")]
+pub mod gix;
+
+#[cfg(feature = "similar")]
+mod similar;
+#[cfg(feature = "similar")]
+pub use crate::similar::hunks_from_diff_ops;
+
+mod lines;
+pub use lines::LineEnding;
+
+#[cfg(feature = "ffi")]
+mod range;
+
+#[cfg(feature = "alloc-stats")]
+mod alloc_stats;
+#[cfg(feature = "alloc-stats")]
+pub use alloc_stats::{alloc_stats, reset_peak_alloc, AllocStats};
+
+#[cfg(all(test, feature = "ffi", feature = "std"))]
+mod tests;
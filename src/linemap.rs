@@ -0,0 +1,75 @@
+use crate::{lines::line_starts, DiffOptions, LineChange, MMFile, Patch, PatchError};
+
+/// Maps line numbers of a file to where they ended up after a change, for
+/// remapping cursors, bookmarks and diagnostics. All lines are 0-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMap {
+    changes: Vec<LineChange>,
+    old_len: usize,
+}
+
+impl LineMap {
+    pub(crate) fn new(changes: Vec<LineChange>, old_len: usize) -> LineMap {
+        LineMap { changes, old_len }
+    }
+
+    /// New position of an old line, or `None` if it was removed or replaced
+    /// or is past the end of the old file
+    pub fn get(&self, old_line: usize) -> Option<usize> {
+        if old_line >= self.old_len {
+            return None;
+        }
+        let next = self.changes.partition_point(|c| c.old.end <= old_line);
+        match self.changes.get(next) {
+            Some(c) if c.old.start <= old_line => None,
+            _ => Some(self.shift(next, old_line)),
+        }
+    }
+
+    /// New position of an old line, mapping removed or replaced lines to the
+    /// start of whatever replaced them. Lines past the end of the old file
+    /// map past the end of the new one.
+    pub fn nearest(&self, old_line: usize) -> usize {
+        let next = self.changes.partition_point(|c| c.old.end <= old_line);
+        match self.changes.get(next) {
+            Some(c) if c.old.start <= old_line => c.new.start,
+            _ => self.shift(next, old_line),
+        }
+    }
+
+    /// The new position of every old line, as returned by [`get`](Self::get)
+    pub fn to_vec(&self) -> Vec<Option<usize>> {
+        (0..self.old_len).map(|l| self.get(l)).collect()
+    }
+
+    /// The changes this map was built from
+    pub fn changes(&self) -> &[LineChange] {
+        &self.changes
+    }
+
+    // position of an unchanged line, given the number of changes before it
+    fn shift(&self, changes_before: usize, old_line: usize) -> usize {
+        match changes_before.checked_sub(1).map(|i| &self.changes[i]) {
+            Some(prev) => prev.new.end + (old_line - prev.old.end),
+            None => old_line,
+        }
+    }
+}
+
+impl MMFile {
+    /// Apply a patch like [`apply_patch`](Self::apply_patch), also returning
+    /// where each line of this file ended up in the result
+    #[allow(clippy::result_large_err)]
+    pub fn apply_patch_mapped(
+        &mut self,
+        patch: &mut Patch,
+    ) -> Result<(MMFile, LineMap), PatchError> {
+        let patched = self.apply_patch(patch)?;
+        // hunks may apply at an offset, so map against the actual result
+        let changes = self
+            .changed_lines(&patched, &DiffOptions::new())
+            .map_err(PatchError::Failed)?;
+        let old_len = line_starts(self.as_slice()).len() - 1;
+        Ok((patched, LineMap::new(changes, old_len)))
+    }
+}
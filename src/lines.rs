@@ -0,0 +1,146 @@
+use core::ops::Range;
+
+/// Byte offsets of the start of every line in `bytes`, followed by
+/// `bytes.len()` as a sentinel. A trailing line without a newline still counts
+/// as a line.
+pub(crate) fn line_starts(bytes: &[u8]) -> Vec<usize> {
+    let mut starts = vec![0];
+    for (i, b) in bytes.iter().enumerate() {
+        if *b == b'\n' && i + 1 < bytes.len() {
+            starts.push(i + 1);
+        }
+    }
+    if bytes.is_empty() {
+        starts.clear();
+    }
+    starts.push(bytes.len());
+    starts
+}
+
+/// Count the lines in a file split into `chunks`, without building an
+/// index. A trailing line without a newline still counts as a line.
+pub(crate) fn count_lines<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> usize {
+    let mut newlines = 0;
+    let mut last = None;
+    for chunk in chunks {
+        newlines += chunk.iter().filter(|b| **b == b'\n').count();
+        last = chunk.last().copied().or(last);
+    }
+    match last {
+        Some(b'\n') | None => newlines,
+        Some(_) => newlines + 1,
+    }
+}
+
+/// Byte range covering lines `lines` (0-based, half-open) of a buffer with
+/// the given line starts. Out-of-range lines are clamped to the buffer.
+pub(crate) fn byte_range(starts: &[usize], lines: Range<usize>) -> Range<usize> {
+    let last = starts.len() - 1;
+    let start = lines.start.min(last);
+    let end = lines.end.clamp(start, last);
+    starts[start]..starts[end]
+}
+
+/// Count the lines shared at the start and at the end of `a` and `b`, then
+/// give back `margin` lines of each so context around the changes survives.
+/// Returns the byte ranges of `a` and `b` left between the shared regions
+/// and the number of shared leading lines removed.
+pub(crate) fn trim_common_lines(
+    a: &[u8],
+    b: &[u8],
+    margin: usize,
+) -> (Range<usize>, Range<usize>, usize) {
+    let a_starts = line_starts(a);
+    let b_starts = line_starts(b);
+    let a_len = a_starts.len() - 1;
+    let b_len = b_starts.len() - 1;
+    let a_line = |i: usize| &a[a_starts[i]..a_starts[i + 1]];
+    let b_line = |i: usize| &b[b_starts[i]..b_starts[i + 1]];
+
+    let mut prefix = 0;
+    while prefix < a_len.min(b_len) && a_line(prefix) == b_line(prefix) {
+        prefix += 1;
+    }
+    let mut suffix = 0;
+    while suffix < (a_len - prefix).min(b_len - prefix)
+        && a_line(a_len - 1 - suffix) == b_line(b_len - 1 - suffix)
+    {
+        suffix += 1;
+    }
+    let prefix = prefix.saturating_sub(margin);
+    let suffix = suffix.saturating_sub(margin);
+    (
+        a_starts[prefix]..a_starts[a_len - suffix],
+        b_starts[prefix]..b_starts[b_len - suffix],
+        prefix,
+    )
+}
+
+/// Split a leading UTF-8 or UTF-16 byte order mark off of `bytes`. The BOM
+/// is empty if there isn't one.
+pub(crate) fn split_bom(bytes: &[u8]) -> (&[u8], &[u8]) {
+    let len = if bytes.starts_with(b"\xEF\xBB\xBF") {
+        3
+    } else if bytes.starts_with(b"\xFF\xFE") || bytes.starts_with(b"\xFE\xFF") {
+        2
+    } else {
+        0
+    };
+    bytes.split_at(len)
+}
+
+/// A line terminator style
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub enum LineEnding {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    /// The bytes of this line terminator
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineEnding::Lf => b"\n",
+            LineEnding::CrLf => b"\r\n",
+        }
+    }
+
+    /// Detect the line ending used by most lines in `bytes`. Returns
+    /// [`LineEnding::Lf`] if there are no line endings or it's a tie.
+    pub fn detect(bytes: &[u8]) -> LineEnding {
+        let mut lf = 0;
+        let mut crlf = 0;
+        for (i, b) in bytes.iter().enumerate() {
+            if *b == b'\n' {
+                if i > 0 && bytes[i - 1] == b'\r' {
+                    crlf += 1;
+                } else {
+                    lf += 1;
+                }
+            }
+        }
+        if crlf > lf {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        }
+    }
+
+    /// Replace every line ending in `bytes` with this one
+    pub fn apply(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(bytes.len());
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            match line.strip_suffix(b"\n") {
+                Some(content) => {
+                    out.extend_from_slice(content.strip_suffix(b"\r").unwrap_or(content));
+                    out.extend_from_slice(self.as_bytes());
+                }
+                None => out.extend_from_slice(line),
+            }
+        }
+        out
+    }
+}
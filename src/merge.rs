@@ -0,0 +1,491 @@
+use core::ops::Range;
+
+use crate::{
+    hunk::parse_hunks,
+    lines::{byte_range, line_starts, split_bom},
+    ChangeTag, ConflictMarkers, DiffOptions, Error, Hunk, LineEnding, MMBlocks, MMFile, Resolution,
+};
+
+/// Options controlling how a three-way merge is performed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MergeOptions {
+    pub(crate) preserve_line_endings: bool,
+    pub(crate) ignore_bom: bool,
+}
+
+impl MergeOptions {
+    /// Create options matching the behavior of [`MMFile::merge3`]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compare lines regardless of `\n` vs `\r\n` endings, and write the
+    /// merged file using the line ending most common in "our" file. Without
+    /// this, a file whose endings differ between sides conflicts on every
+    /// line.
+    pub fn preserve_line_endings(mut self, preserve: bool) -> Self {
+        self.preserve_line_endings = preserve;
+        self
+    }
+
+    /// Strip any byte order mark from all three files before merging, then
+    /// restore "our" file's BOM on the merged result. Without this, a BOM
+    /// added or removed on one side conflicts with edits to the first line.
+    pub fn ignore_bom(mut self, ignore: bool) -> Self {
+        self.ignore_bom = ignore;
+        self
+    }
+}
+
+/// A region where both sides of a three-way merge changed the same lines.
+/// Ranges are 0-based, half-open line ranges of the changed lines,
+/// excluding any surrounding context.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictRegion {
+    /// Lines of the base file changed by both sides
+    pub base: Range<usize>,
+    /// Lines of "our" file (the one whose changes were kept) in this region
+    pub ours: Range<usize>,
+    /// Lines of "their" file (the one whose changes were rejected) in this
+    /// region
+    pub theirs: Range<usize>,
+}
+
+/// Result of a three-way merge
+#[derive(Debug)]
+pub struct MergeOutcome {
+    /// The merged file. Where the two sides conflict, "our" side is kept.
+    pub merged: MMFile,
+    /// Every region where "their" changes could not be applied
+    pub conflicts: Vec<ConflictRegion>,
+}
+
+impl MergeOutcome {
+    /// Whether the merge completed without any conflicts
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Where a region of a merged file came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MergeOrigin {
+    /// Lines neither side changed
+    Base,
+    /// Lines only "our" side changed, including conflicting lines where
+    /// "ours" was kept
+    Ours,
+    /// Lines only "their" side changed
+    Theirs,
+    /// Lines both sides changed in the same way
+    Both,
+}
+
+/// A run of lines of a merged file that share an origin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeRegion {
+    pub origin: MergeOrigin,
+    /// 0-based, half-open range of lines of the merged file
+    pub lines: Range<usize>,
+}
+
+impl MMFile {
+    /// Merge like [`MMFile::merge3`], also splitting the merged file into
+    /// regions labeled with the side each one came from. Regions cover every
+    /// line of the merged file in order.
+    pub fn merge3_labeled(
+        base: &mut MMFile,
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+    ) -> Result<(MergeOutcome, Vec<MergeRegion>), Error> {
+        let outcome = MMFile::merge3(base, ours, theirs)?;
+        let opts = DiffOptions::new().context_len(0);
+        let ours_hunks = base.diff_hunks(ours, &opts)?;
+        let theirs_hunks = base.diff_hunks(theirs, &opts)?;
+        let merged_hunks = ours.diff_hunks(&outcome.merged, &opts)?;
+
+        // origin of each line of "our" file
+        let mut ours_origins = vec![MergeOrigin::Base; line_starts(ours.as_slice()).len() - 1];
+        for hunk in ours_hunks.iter() {
+            let same = theirs_hunks
+                .iter()
+                .any(|t| t.old_lines() == hunk.old_lines() && inserted(t).eq(inserted(hunk)));
+            let origin = if same {
+                MergeOrigin::Both
+            } else {
+                MergeOrigin::Ours
+            };
+            ours_origins[hunk.new_lines()].fill(origin);
+        }
+
+        // lines of the merged file missing from "our" file came from "theirs"
+        let mut origins = Vec::new();
+        let mut next = 0;
+        for hunk in merged_hunks.iter() {
+            let old = hunk.old_lines();
+            origins.extend_from_slice(&ours_origins[next..old.start]);
+            origins.extend(hunk.new_lines().map(|_| MergeOrigin::Theirs));
+            next = old.end;
+        }
+        origins.extend_from_slice(&ours_origins[next..]);
+
+        let mut regions: Vec<MergeRegion> = Vec::new();
+        for (line, origin) in origins.into_iter().enumerate() {
+            match regions.last_mut() {
+                Some(region) if region.origin == origin => region.lines.end = line + 1,
+                _ => regions.push(MergeRegion {
+                    origin,
+                    lines: line..line + 1,
+                }),
+            }
+        }
+        Ok((outcome, regions))
+    }
+
+    /// Compute the file that results from merging the changes in `ours` and
+    /// `theirs` to the `base` file. Where the changes conflict, `ours` wins
+    /// and the conflict is recorded in [`MergeOutcome::conflicts`].
+    pub fn merge3(
+        base: &mut MMFile,
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+    ) -> Result<MergeOutcome, Error> {
+        let mut merged = MMBlocks::new();
+        let mut rejected = Vec::new();
+        // SAFETY: neither callback panics
+        unsafe {
+            MMFile::merge3_nopanic(
+                base,
+                ours,
+                theirs,
+                |buf| merged.write_buf(buf),
+                |buf| {
+                    rejected.extend_from_slice(buf);
+                    0
+                },
+            )?
+        };
+
+        let rejected_hunks = parse_hunks(&rejected)?;
+        let conflicts = if rejected_hunks.is_empty() {
+            Vec::new()
+        } else {
+            let opts = DiffOptions::new().context_len(0);
+            let ours_hunks = base.diff_hunks(ours, &opts)?;
+            rejected_hunks
+                .iter()
+                .map(|hunk| {
+                    let base_lines = hunk.old_changed_lines();
+                    ConflictRegion {
+                        ours: map_lines(&ours_hunks, base_lines.clone()),
+                        base: base_lines,
+                        theirs: hunk.new_changed_lines(),
+                    }
+                })
+                .collect()
+        };
+        if !conflicts.is_empty() {
+            event!(conflicts = conflicts.len(), "merge produced conflicts");
+        }
+        Ok(MergeOutcome {
+            merged: merged.to_mmfile(),
+            conflicts,
+        })
+    }
+}
+
+impl MMFile {
+    /// Like [`MMFile::merge3`], but configured by `opts`
+    pub fn merge3_with(
+        base: &mut MMFile,
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+        opts: &MergeOptions,
+    ) -> Result<MergeOutcome, Error> {
+        if !opts.preserve_line_endings && !opts.ignore_bom {
+            return MMFile::merge3(base, ours, theirs);
+        }
+        let ending = LineEnding::detect(ours.as_slice());
+        let (bom, _) = split_bom(ours.as_slice());
+        let bom = if opts.ignore_bom {
+            bom.to_vec()
+        } else {
+            Vec::new()
+        };
+        let prepare = |f: &MMFile| {
+            let mut data = f.as_slice();
+            if opts.ignore_bom {
+                data = split_bom(data).1;
+            }
+            if opts.preserve_line_endings {
+                MMFile::from_bytes(&LineEnding::Lf.apply(data))
+            } else {
+                MMFile::from_bytes(data)
+            }
+        };
+        let mut outcome =
+            MMFile::merge3(&mut prepare(base), &mut prepare(ours), &mut prepare(theirs))?;
+        if !bom.is_empty() || (opts.preserve_line_endings && ending != LineEnding::Lf) {
+            let mut merged = bom;
+            if opts.preserve_line_endings {
+                merged.extend_from_slice(&ending.apply(outcome.merged.as_slice()));
+            } else {
+                merged.extend_from_slice(outcome.merged.as_slice());
+            }
+            outcome.merged = MMFile::from_bytes(&merged);
+        }
+        Ok(outcome)
+    }
+}
+
+/// The lines a hunk inserts
+fn inserted(hunk: &Hunk) -> impl Iterator<Item = &[u8]> {
+    hunk.changes
+        .iter()
+        .filter(|c| c.tag == ChangeTag::Insert)
+        .map(|c| &c.line[..])
+}
+
+impl MMFile {
+    /// Merge with several merge bases, as happens after criss-cross merges,
+    /// the way git's recursive strategy does: the bases are merged into one
+    /// virtual base, which is then used to merge `ours` and `theirs`. Each
+    /// pair of bases is merged using the lines they share as their ancestor,
+    /// with the earlier base winning any conflicts. Returns `Err` if `bases`
+    /// is empty.
+    pub fn merge3_recursive(
+        bases: &mut [MMFile],
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+        opts: &MergeOptions,
+    ) -> Result<MergeOutcome, Error> {
+        let Some((first, rest)) = bases.split_first_mut() else {
+            return Err(Error::Invalid(
+                "recursive merge needs at least one base".to_owned(),
+            ));
+        };
+        let mut virtual_base = first.clone();
+        for base in rest.iter_mut() {
+            let mut ancestor = common_lines(&mut virtual_base, base)?;
+            virtual_base =
+                MMFile::merge3_with(&mut ancestor, &mut virtual_base, base, opts)?.merged;
+        }
+        MMFile::merge3_with(&mut virtual_base, ours, theirs, opts)
+    }
+}
+
+/// A conflict found by [`MMFile::merge_n`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContributorConflict {
+    /// Index of the contributor whose changes were rejected
+    pub contributor: usize,
+    /// Where the conflict is. `ours` lines refer to the merge of all earlier
+    /// contributors and `theirs` lines to this contributor's file.
+    pub region: ConflictRegion,
+}
+
+/// Result of merging many contributors' changes with [`MMFile::merge_n`]
+#[derive(Debug)]
+pub struct MultiMergeOutcome {
+    /// The merged file. Where contributors conflict, the earliest one wins.
+    pub merged: MMFile,
+    /// Every region where a contributor's changes could not be applied
+    pub conflicts: Vec<ContributorConflict>,
+}
+
+impl MultiMergeOutcome {
+    /// Whether every contributor's changes were merged without conflicts
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+impl MMFile {
+    /// Merge the changes several contributors made to `base`, folding
+    /// [`MMFile::merge3_with`] over them in order: each contributor is merged
+    /// into the result of merging the ones before it.
+    pub fn merge_n(
+        base: &mut MMFile,
+        others: &mut [MMFile],
+        opts: &MergeOptions,
+    ) -> Result<MultiMergeOutcome, Error> {
+        let Some((first, rest)) = others.split_first_mut() else {
+            return Ok(MultiMergeOutcome {
+                merged: base.clone(),
+                conflicts: Vec::new(),
+            });
+        };
+        let mut merged = first.clone();
+        let mut conflicts = Vec::new();
+        for (i, other) in rest.iter_mut().enumerate() {
+            let outcome = MMFile::merge3_with(base, &mut merged, other, opts)?;
+            conflicts.extend(
+                outcome
+                    .conflicts
+                    .into_iter()
+                    .map(|region| ContributorConflict {
+                        contributor: i + 1,
+                        region,
+                    }),
+            );
+            merged = outcome.merged;
+        }
+        Ok(MultiMergeOutcome { merged, conflicts })
+    }
+}
+
+/// The lines `a` and `b` have in common, in order, for use as an ancestor
+/// when no real one is known
+pub(crate) fn common_lines(a: &mut MMFile, b: &mut MMFile) -> Result<MMFile, Error> {
+    let hunks = a.diff_hunks(b, &DiffOptions::new().context_len(0))?;
+    let bytes = a.as_slice();
+    let starts = line_starts(bytes);
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut next = 0;
+    for hunk in hunks.iter() {
+        let old = hunk.old_lines();
+        out.extend_from_slice(&bytes[byte_range(&starts, next..old.start)]);
+        next = old.end;
+    }
+    out.extend_from_slice(&bytes[byte_range(&starts, next..usize::MAX)]);
+    Ok(MMFile::from_bytes(&out))
+}
+
+/// Decides how to resolve each region where both sides of a three-way merge
+/// changed the same lines, for domain-specific merges. Closures taking the
+/// base, "our" and "their" lines implement this trait.
+pub trait MergeDriver {
+    /// Resolve one conflict, given the lines of each file it covers
+    fn resolve(&mut self, base: &[u8], ours: &[u8], theirs: &[u8]) -> Resolution;
+}
+
+impl<F> MergeDriver for F
+where
+    F: FnMut(&[u8], &[u8], &[u8]) -> Resolution,
+{
+    fn resolve(&mut self, base: &[u8], ours: &[u8], theirs: &[u8]) -> Resolution {
+        self(base, ours, theirs)
+    }
+}
+
+impl MMFile {
+    /// Merge like [`MMFile::merge3_with`], consulting `driver` for every
+    /// conflicting region. Conflicts the driver replaces are removed from
+    /// [`MergeOutcome::conflicts`]; those it skips keep "our" lines and stay
+    /// recorded. Returns `Err` if the driver aborts.
+    pub fn merge3_with_driver<D: MergeDriver + ?Sized>(
+        base: &mut MMFile,
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+        opts: &MergeOptions,
+        driver: &mut D,
+    ) -> Result<MergeOutcome, Error> {
+        let (merged, kept, _) = drive_merge(base, ours, theirs, opts, driver)?;
+        Ok(MergeOutcome {
+            merged,
+            conflicts: kept,
+        })
+    }
+
+    /// Merge two files that have no common ancestor, using the lines a diff
+    /// between them leaves unchanged as the base. Relative to that base each
+    /// side only inserts lines, so lines only one side has are merged in
+    /// without markers, and a file merged with a copy of itself missing some
+    /// lines comes out whole. Only where both sides insert lines at the same
+    /// place are both kept between conflict markers labeled `ours` and
+    /// `theirs`, with the region recorded in [`MergeOutcome::conflicts`],
+    /// relative to that base.
+    pub fn merge2(
+        ours: &mut MMFile,
+        theirs: &mut MMFile,
+        opts: &MergeOptions,
+    ) -> Result<MergeOutcome, Error> {
+        let mut base = common_lines(ours, theirs)?;
+        let mut markers = ConflictMarkers::default();
+        let (merged, _, conflicts) = drive_merge(&mut base, ours, theirs, opts, &mut markers)?;
+        Ok(MergeOutcome { merged, conflicts })
+    }
+}
+
+/// Merge, letting `driver` resolve each conflict. Returns the merged file,
+/// the conflicts the driver skipped and the ones it replaced.
+fn drive_merge<D: MergeDriver + ?Sized>(
+    base: &mut MMFile,
+    ours: &mut MMFile,
+    theirs: &mut MMFile,
+    opts: &MergeOptions,
+    driver: &mut D,
+) -> Result<(MMFile, Vec<ConflictRegion>, Vec<ConflictRegion>), Error> {
+    let outcome = MMFile::merge3_with(base, ours, theirs, opts)?;
+    if outcome.is_clean() {
+        return Ok((outcome.merged, Vec::new(), Vec::new()));
+    }
+    let ctx0 = DiffOptions::new().context_len(0);
+    let merged_hunks = ours.diff_hunks(&outcome.merged, &ctx0)?;
+    let lines_of = |f: &MMFile| line_starts(f.as_slice());
+    let (base_starts, ours_starts) = (lines_of(base), lines_of(ours));
+    let (theirs_starts, merged_starts) = (lines_of(theirs), lines_of(&outcome.merged));
+
+    let merged_bytes = outcome.merged.as_slice();
+    let mut out = Vec::with_capacity(merged_bytes.len());
+    let (mut kept, mut replaced) = (Vec::new(), Vec::new());
+    let mut next = 0;
+    for (index, conflict) in outcome.conflicts.into_iter().enumerate() {
+        let resolution = driver.resolve(
+            &base.as_slice()[byte_range(&base_starts, conflict.base.clone())],
+            &ours.as_slice()[byte_range(&ours_starts, conflict.ours.clone())],
+            &theirs.as_slice()[byte_range(&theirs_starts, conflict.theirs.clone())],
+        );
+        match resolution {
+            Resolution::Replace(content) => {
+                let lines = map_lines(&merged_hunks, conflict.ours.clone());
+                let bytes = byte_range(&merged_starts, lines);
+                let start = bytes.start.max(next);
+                out.extend_from_slice(&merged_bytes[next..start]);
+                out.extend_from_slice(&content);
+                next = bytes.end.max(start);
+                replaced.push(conflict);
+            }
+            Resolution::Skip => kept.push(conflict),
+            Resolution::Abort => {
+                return Err(Error::Other(format!(
+                    "merge driver aborted at conflict {}",
+                    index
+                )))
+            }
+        }
+    }
+    out.extend_from_slice(&merged_bytes[next..]);
+    Ok((MMFile::from_bytes(&out), kept, replaced))
+}
+
+/// Map a range of old lines onto the new file through context-free `hunks`,
+/// widening it to cover any hunk it overlaps or touches.
+pub(crate) fn map_lines(hunks: &[Hunk], lines: Range<usize>) -> Range<usize> {
+    let mut start = None;
+    let mut start_delta = 0;
+    let mut end_delta = 0;
+    for hunk in hunks {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        let delta = new.end as isize - old.end as isize;
+        if start.is_none() {
+            if old.end < lines.start || (old.end == lines.start && !old.is_empty()) {
+                start_delta = delta;
+            } else if old.start <= lines.start {
+                start = Some(new.start);
+            }
+        }
+        if old.end < lines.end || (old.end == lines.end && !old.is_empty()) {
+            end_delta = delta;
+        } else if old.start < lines.end || (old.is_empty() && old.start == lines.end) {
+            let start = start.unwrap_or((lines.start as isize + start_delta) as usize);
+            return start..new.end.max(start);
+        } else {
+            break;
+        }
+    }
+    let start = start.unwrap_or((lines.start as isize + start_delta) as usize);
+    let end = (lines.end as isize + end_delta) as usize;
+    start..end.max(start)
+}
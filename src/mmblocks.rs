@@ -1,22 +1,112 @@
 use core::{
     ffi::{c_int, c_long, c_ulong, c_void},
-    mem::{forget, swap, MaybeUninit},
-    ptr::{addr_of, addr_of_mut},
+    fmt::{self, Debug, Formatter},
+    mem::{forget, size_of, swap, ManuallyDrop, MaybeUninit},
+    ptr::{self, addr_of, addr_of_mut},
+    slice::from_raw_parts,
 };
 
 use libxdiff_sys::{
-    mmfile_t, xdl_free_mmfile, xdl_mmfile_cmp, xdl_mmfile_compact, xdl_mmfile_iscompact,
-    xdl_mmfile_size, xdl_write_mmfile, XDL_MMF_ATOMIC,
+    mmblock_t, mmbuffer_t, mmfile_t, xdl_free_mmfile, xdl_mmfile_cmp, xdl_mmfile_compact,
+    xdl_mmfile_iscompact, xdl_mmfile_size, xdl_write_mmfile, xdl_writem_mmfile, XDL_MMF_ATOMIC,
 };
 
-use crate::{ensure_init, init_mmfile, MMFile};
+use crate::{ensure_init, init_mmfile, lines::count_lines, text::preview, MMFile};
+
+/// Rule deciding when writes to an [`MMBlocks`], such as
+/// [`MMBlocks::write_buf`] or output appended by libxdiff, compact the file
+/// automatically
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CompactionPolicy {
+    /// Only compact when explicitly asked to
+    #[default]
+    Never,
+    /// Compact once the file is spread over more than this many blocks
+    MaxBlocks(usize),
+    /// Compact once the fraction of bytes outside the largest block exceeds
+    /// this ratio (between 0.0 and 1.0)
+    MaxFragmentation(f64),
+}
 
 /// An MMFile that does not have compactness as an invariant
-#[derive(Debug)]
 pub struct MMBlocks {
     pub(crate) inner: mmfile_t,
+    pub(crate) policy: CompactionPolicy,
+}
+
+/// Iterate over the data of each block of `file` in order
+pub(crate) fn block_slices(file: &mmfile_t) -> impl Iterator<Item = &[u8]> {
+    let mut block = file.head;
+    core::iter::from_fn(move || {
+        if block.is_null() {
+            return None;
+        }
+        // SAFETY: the block chain is owned by the file and can't be modified
+        // while it is borrowed
+        let b = unsafe { &*block };
+        block = b.next;
+        if b.size <= 0 || b.ptr.is_null() {
+            return Some(&[][..]);
+        }
+        Some(unsafe { from_raw_parts(b.ptr as *const u8, b.size as usize) })
+    })
 }
 
+/// Memory held by a file, as reported by [`MMFile::memory_usage`] and
+/// [`MMBlocks::memory_usage`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryUsage {
+    /// Bytes allocated for the file's blocks, including block headers
+    pub allocated: usize,
+    /// Bytes of data stored in the blocks
+    pub stored: usize,
+    /// Number of blocks the data is spread over
+    pub blocks: usize,
+}
+
+impl MemoryUsage {
+    /// Bytes allocated but not holding data
+    pub fn overhead(&self) -> usize {
+        self.allocated.saturating_sub(self.stored)
+    }
+}
+
+/// Walk the block chain of `file`, totalling what each block holds. libxdiff
+/// allocates every block's header and buffer together.
+pub(crate) fn memory_usage(file: &mmfile_t) -> MemoryUsage {
+    let mut usage = MemoryUsage::default();
+    let mut block = file.head;
+    while !block.is_null() {
+        // SAFETY: as in `block_slices`, the chain can't change while borrowed
+        let b = unsafe { &*block };
+        usage.allocated += size_of::<mmblock_t>() + b.bsize.max(0) as usize;
+        usage.stored += b.size.max(0) as usize;
+        usage.blocks += 1;
+        block = b.next;
+    }
+    usage
+}
+
+impl Debug for MMBlocks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let size: usize = block_slices(&self.inner).map(<[u8]>::len).sum();
+        f.debug_struct("MMBlocks")
+            .field("size", &size)
+            .field("blocks", &self.block_count())
+            .field("preview", &preview(block_slices(&self.inner)))
+            .finish()
+    }
+}
+
+impl Default for MMBlocks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// SAFETY: as for MMFile, the blocks are exclusively owned
+unsafe impl Send for MMBlocks {}
+
 impl Drop for MMBlocks {
     fn drop(&mut self) {
         unsafe { xdl_free_mmfile(addr_of_mut!(self.inner)) };
@@ -29,6 +119,7 @@ impl MMBlocks {
         ensure_init();
         Self {
             inner: init_mmfile(0),
+            policy: CompactionPolicy::default(),
         }
     }
 
@@ -50,7 +141,60 @@ impl MMBlocks {
                 bytes.len()
             );
         }
-        Self { inner }
+        Self {
+            inner,
+            policy: CompactionPolicy::default(),
+        }
+    }
+
+    /// Create a new MMBlocks from a sequence of fragments
+    pub fn from_chunks(chunks: &[&[u8]]) -> Self {
+        chunks.iter().copied().collect()
+    }
+
+    /// Get a pointer to the underlying `mmfile_t`, for calling libxdiff-sys
+    /// functions that aren't wrapped
+    pub fn as_raw(&mut self) -> *mut mmfile_t {
+        addr_of_mut!(self.inner)
+    }
+
+    /// Take ownership of the underlying `mmfile_t`. The caller becomes
+    /// responsible for freeing it with `xdl_free_mmfile`.
+    pub fn into_raw(self) -> mmfile_t {
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped, so the file is only freed by the caller
+        unsafe { ptr::read(&this.inner) }
+    }
+
+    /// Wrap an `mmfile_t`, taking ownership of it
+    ///
+    /// # Safety
+    /// `inner` must be an initialized file whose blocks were allocated by
+    /// libxdiff after this crate initialized it, such as one returned by
+    /// [`into_raw`](Self::into_raw), and must not be freed or used elsewhere
+    /// afterwards.
+    pub unsafe fn from_raw(inner: mmfile_t) -> Self {
+        ensure_init();
+        Self {
+            inner,
+            policy: CompactionPolicy::default(),
+        }
+    }
+
+    /// Set the policy consulted by [`write_buf`](Self::write_buf)
+    pub fn with_compaction_policy(mut self, policy: CompactionPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Change the policy consulted by [`write_buf`](Self::write_buf)
+    pub fn set_compaction_policy(&mut self, policy: CompactionPolicy) {
+        self.policy = policy;
+    }
+
+    /// Get the current compaction policy
+    pub fn compaction_policy(&self) -> CompactionPolicy {
+        self.policy
     }
 
     /// Checks if the entire file is a single allocation.
@@ -82,6 +226,7 @@ impl MMBlocks {
         }
         let mut new_blocks = MMBlocks {
             inner: unsafe { compacted.assume_init() },
+            policy: self.policy,
         };
         swap(self, &mut new_blocks); // swap new one in, old one is dropped
     }
@@ -97,9 +242,7 @@ impl MMBlocks {
         let inner_mmfile = self.inner;
         // forget the original blocks so inner obj is not freed
         forget(self);
-        MMFile {
-            inner: inner_mmfile,
-        }
+        MMFile::from_inner(inner_mmfile)
     }
 
     /// Write a buffer of data to the end of this file
@@ -111,10 +254,202 @@ impl MMBlocks {
                 buf.len() as c_long,
             )
         };
-        if write_result == buf.len() as c_long {
-            0
-        } else {
-            -1
+        if write_result != buf.len() as c_long {
+            return -1;
+        }
+        if self.should_compact() {
+            self.to_compact();
+        }
+        0
+    }
+
+    /// Write a group of buffers emitted by libxdiff to the end of this file,
+    /// compacting it like [`write_buf`](Self::write_buf)
+    pub(crate) fn write_buffers(&mut self, buffers: &mut [mmbuffer_t]) -> c_int {
+        let len: c_long = buffers.iter().map(|b| b.size).sum();
+        let written = unsafe {
+            xdl_writem_mmfile(
+                addr_of_mut!(self.inner),
+                buffers.as_mut_ptr(),
+                buffers.len() as c_int,
+            )
+        };
+        if written != len {
+            return -1;
+        }
+        if self.should_compact() {
+            self.to_compact();
+        }
+        0
+    }
+
+    /// Append the contents of `file` by linking its allocation onto the end
+    /// of this file's blocks, without copying
+    pub fn push_file(&mut self, file: MMFile) {
+        let mut other = file.into_raw();
+        if other.head.is_null() || other.fsize == 0 {
+            unsafe { xdl_free_mmfile(addr_of_mut!(other)) };
+            return;
+        }
+        // SAFETY: both block chains were allocated by libxdiff with the same
+        // allocator, and `other` gave up ownership of its chain
+        unsafe {
+            match self.inner.tail.as_mut() {
+                Some(tail) => tail.next = other.head,
+                None => self.inner.head = other.head,
+            }
+        }
+        self.inner.tail = other.tail;
+        self.inner.wcur = other.tail;
+        self.inner.fsize += other.fsize;
+        if self.should_compact() {
+            self.to_compact();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Append everything `reader` produces, reading up to `chunk_size` bytes
+    /// at a time straight into space allocated at the end of this file, so
+    /// no intermediate buffer is needed. Returns the number of bytes read.
+    pub fn extend_from_reader<R: std::io::Read>(
+        &mut self,
+        mut reader: R,
+        chunk_size: usize,
+    ) -> std::io::Result<usize> {
+        use core::slice;
+        use std::io::{Error, ErrorKind};
+
+        use libxdiff_sys::xdl_mmfile_writeallocate;
+
+        if chunk_size == 0 || chunk_size > c_long::MAX as usize {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "chunk size must be positive and fit in a C long",
+            ));
+        }
+        let mut total = 0;
+        loop {
+            let previous_block = self.inner.wcur;
+            let ptr =
+                unsafe { xdl_mmfile_writeallocate(addr_of_mut!(self.inner), chunk_size as c_long) };
+            if ptr.is_null() {
+                return Err(Error::new(
+                    ErrorKind::OutOfMemory,
+                    "mmfile allocation failed",
+                ));
+            }
+            // SAFETY: libxdiff reserved `chunk_size` bytes at `ptr` at the end
+            // of the block being written, which are zeroed before use
+            let buf = unsafe {
+                ptr::write_bytes(ptr as *mut u8, 0, chunk_size);
+                slice::from_raw_parts_mut(ptr as *mut u8, chunk_size)
+            };
+            let read = loop {
+                match reader.read(buf) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    result => break result,
+                }
+            };
+            // `Read` is safe to implement, so don't trust a count larger than
+            // the buffer
+            let filled = match read {
+                Ok(n) if n <= chunk_size => n,
+                _ => 0,
+            };
+            // give back the part of the reservation that wasn't filled
+            let unused = (chunk_size - filled) as c_long;
+            // SAFETY: the reservation was made in the current write block
+            unsafe {
+                (*self.inner.wcur).size -= unused;
+            }
+            self.inner.fsize -= unused;
+            if self.inner.wcur != previous_block {
+                self.free_empty_tail();
+            }
+            match read {
+                Ok(n) if n > chunk_size => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "reader reported more bytes than the buffer holds",
+                    ))
+                }
+                Ok(0) => return Ok(total),
+                Ok(n) => total += n,
+                Err(e) => return Err(e),
+            }
+            if self.should_compact() {
+                self.to_compact();
+            }
+        }
+    }
+
+    /// Unlink and free the last block if it holds nothing, as left behind by
+    /// a reservation that ended up unused
+    #[cfg(feature = "std")]
+    fn free_empty_tail(&mut self) {
+        let tail = self.inner.tail;
+        if tail.is_null() || unsafe { (*tail).size } != 0 {
+            return;
+        }
+        let mut prev = ptr::null_mut::<mmblock_t>();
+        let mut cur = self.inner.head;
+        // SAFETY: the tail is reachable from the head of the chain
+        unsafe {
+            while cur != tail {
+                prev = cur;
+                cur = (*cur).next;
+            }
+            if prev.is_null() {
+                self.inner.head = ptr::null_mut();
+            } else {
+                (*prev).next = ptr::null_mut();
+            }
+        }
+        self.inner.tail = prev;
+        self.inner.wcur = prev;
+        // free it through libxdiff, so the allocator it was made with is used
+        let mut single: mmfile_t = unsafe { MaybeUninit::zeroed().assume_init() };
+        single.head = tail;
+        unsafe { xdl_free_mmfile(addr_of_mut!(single)) };
+    }
+
+    /// Report the bytes allocated for this file against the bytes it stores,
+    /// for deciding when [`to_compact`](Self::to_compact) is worthwhile
+    pub fn memory_usage(&self) -> MemoryUsage {
+        memory_usage(&self.inner)
+    }
+
+    /// Count the lines stored across all blocks, without compacting. A
+    /// trailing line without a newline still counts as a line.
+    pub fn line_count(&self) -> usize {
+        count_lines(block_slices(&self.inner))
+    }
+
+    /// Number of blocks the data is currently spread over. A compact file has
+    /// at most one.
+    pub fn block_count(&self) -> usize {
+        block_slices(&self.inner).count()
+    }
+
+    /// Number of blocks and size of the largest one
+    pub(crate) fn block_stats(&self) -> (usize, usize) {
+        block_slices(&self.inner).fold((0, 0), |(count, largest), b| {
+            (count + 1, largest.max(b.len()))
+        })
+    }
+
+    fn should_compact(&mut self) -> bool {
+        match self.policy {
+            CompactionPolicy::Never => false,
+            CompactionPolicy::MaxBlocks(max) => self.block_count() > max,
+            CompactionPolicy::MaxFragmentation(ratio) => {
+                let size = self.size();
+                if size == 0 {
+                    return false;
+                }
+                let (_, largest) = self.block_stats();
+                (size - largest) as f64 / size as f64 > ratio
+            }
         }
     }
 
@@ -137,12 +472,62 @@ impl MMBlocks {
         }
         return MMBlocks {
             inner: unsafe { compacted.assume_init() },
+            policy: self.policy,
         };
     }
 
     /// Compare contents of 2 files for equality. The underlying structs track
     /// their own iterator state, so comparison requires mutable access.
+    /// Files of different sizes are rejected without reading their contents.
     pub fn eq(&mut self, other: &mut Self) -> bool {
+        if self.size() != other.size() {
+            return false;
+        }
         unsafe { xdl_mmfile_cmp(addr_of_mut!(self.inner), addr_of_mut!(other.inner)) == 0 }
     }
 }
+
+impl<'a> Extend<&'a [u8]> for MMBlocks {
+    /// Append each chunk with [`write_buf`](MMBlocks::write_buf).
+    ///
+    /// # Panics
+    ///
+    /// Panics if libxdiff fails to write a chunk, since `Extend` has no way
+    /// to report errors. Use `write_buf` directly to handle failures.
+    fn extend<I: IntoIterator<Item = &'a [u8]>>(&mut self, iter: I) {
+        for chunk in iter {
+            if self.write_buf(chunk) != 0 {
+                panic!("mmfile write failed for {} byte chunk", chunk.len());
+            }
+        }
+    }
+}
+
+impl Extend<Vec<u8>> for MMBlocks {
+    /// Append each chunk with [`write_buf`](MMBlocks::write_buf).
+    ///
+    /// # Panics
+    ///
+    /// Panics if libxdiff fails to write a chunk.
+    fn extend<I: IntoIterator<Item = Vec<u8>>>(&mut self, iter: I) {
+        for chunk in iter {
+            self.extend([chunk.as_slice()]);
+        }
+    }
+}
+
+impl<'a> FromIterator<&'a [u8]> for MMBlocks {
+    fn from_iter<I: IntoIterator<Item = &'a [u8]>>(iter: I) -> Self {
+        let mut blocks = MMBlocks::new();
+        blocks.extend(iter);
+        blocks
+    }
+}
+
+impl FromIterator<Vec<u8>> for MMBlocks {
+    fn from_iter<I: IntoIterator<Item = Vec<u8>>>(iter: I) -> Self {
+        let mut blocks = MMBlocks::new();
+        blocks.extend(iter);
+        blocks
+    }
+}
@@ -1,33 +1,184 @@
 use core::{
+    borrow::Borrow,
     ffi::{c_int, c_long, c_void},
     fmt::Debug,
-    ptr::{addr_of, addr_of_mut},
-    slice::from_raw_parts,
-    str::from_utf8,
+    mem::{forget, ManuallyDrop},
+    ops::Range,
+    ptr::{self, addr_of, addr_of_mut},
+    slice::{from_raw_parts, from_raw_parts_mut},
 };
+#[cfg(feature = "std")]
+use core::{cell::Cell, ops::ControlFlow};
 
 #[cfg(feature = "std")]
 use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, OnceLock};
 
 use libxdiff_sys::{
-    mmbuffer_t, mmfile_t, xdemitcb_t, xdemitconf_t, xdl_diff, xdl_free_mmfile, xdl_merge3,
-    xdl_mmfile_iscompact, xdl_mmfile_size, xdl_patch, xdl_write_mmfile, xdl_writem_mmfile,
-    xpparam_t, XDL_PATCH_NORMAL,
+    mmbuffer_t, mmfile_t, xdemitcb_t, xdl_diff, xdl_free_mmfile, xdl_merge3, xdl_mmfile_iscompact,
+    xdl_mmfile_size, xdl_patch, xdl_write_mmfile, XDL_PATCH_NORMAL, XDL_PATCH_REVERSE,
+};
+
+use crate::{
+    cleanup::cleanup_semantic,
+    cost::{coarse_records, edit_cost},
+    ensure_init,
+    error::clear_errno,
+    header::file_header,
+    init_mmfile,
+    lines::{count_lines, line_starts, split_bom, trim_common_lines},
+    mmblocks::{block_slices, memory_usage},
+    patch::PatchStats,
+    slide::{change_records, slide_changes},
+    text::preview,
+    tokenize::keyed_changes,
+    ApplyOptions, ChangeTag, CostFallback, DiffOptions, Error, HunkHeader, LineEnding, MMBlocks,
+    MemoryUsage, Patch, PatchError,
 };
+#[cfg(feature = "std")]
+use crate::{BufferKind, DiffEvent};
+
+/// Callback receiving one emitted record as a group of buffers
+pub(crate) type RecordCallback<'a> = dyn FnMut(&[&[u8]]) -> c_int + 'a;
+
+/// Callback receiving one emitted buffer at a time
+pub(crate) type BufCallback<'a> = dyn FnMut(&[u8]) -> c_int + 'a;
+
+/// Emit callback that appends every buffer to the `MMBlocks` pointed to by
+/// `blocks_ptr`, following its compaction policy
+pub(crate) extern "C" fn write_blocks_cb(
+    blocks_ptr: *mut c_void,
+    buffers: *mut mmbuffer_t,
+    num: c_int,
+) -> c_int {
+    let blocks = unsafe { &mut *(blocks_ptr as *mut MMBlocks) };
+    let buffers = unsafe { from_raw_parts_mut(buffers, num as usize) };
+    blocks.write_buffers(buffers)
+}
+
+/// Position of the record most recently passed to an emit callback
+#[derive(Debug, Clone, Copy, Default)]
+struct EmitPosition {
+    hunks: usize,
+    // lines seen in the current hunk, counting the one just emitted
+    lines: usize,
+    header: bool,
+}
+
+impl EmitPosition {
+    fn advance(&mut self, record: &[&[u8]]) {
+        if let [_header] = record {
+            self.hunks += 1;
+            self.lines = 0;
+            self.header = true;
+        } else {
+            self.lines += 1;
+            self.header = false;
+        }
+    }
 
-use crate::{ensure_init, init_mmfile, MMBlocks};
+    fn error(&self, op: &'static str) -> Error {
+        Error::Callback {
+            op,
+            hunk: self.hunks.saturating_sub(1),
+            line: (!self.header).then(|| self.lines.saturating_sub(1)),
+            panic: None,
+        }
+    }
+}
 
-pub type MMPatch = MMBlocks;
+#[cfg(feature = "std")]
+/// The message a panic was started with, if it was a string
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(msg) => (*msg).to_owned(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "non-string panic payload".to_owned()),
+    }
+}
 
-/// Type representing an owned, compact file in libxdiff
+#[cfg(feature = "std")]
+/// Return code for a callback result: keeps going on `Continue`, and on
+/// `Break` or a panic stops libxdiff, saving the value it broke with
+fn break_code<B>(stopped: &Cell<Option<B>>, result: std::thread::Result<ControlFlow<B>>) -> c_int {
+    match result {
+        Ok(ControlFlow::Continue(())) => 0,
+        Ok(ControlFlow::Break(value)) => {
+            stopped.set(Some(value));
+            -1
+        }
+        Err(_) => -1,
+    }
+}
+
+/// Rebuild `patched`, the result of patching `normalized`, so lines kept
+/// from `original` keep their own endings and inserted lines end with
+/// `ending`. `normalized` is `original` with every line ending set to `\n`.
+fn restore_line_endings(
+    original: &[u8],
+    normalized: &MMFile,
+    patched: MMFile,
+    ending: LineEnding,
+) -> Result<MMFile, Error> {
+    if ending == LineEnding::Lf && original == normalized.as_slice() {
+        return Ok(patched);
+    }
+    let starts = line_starts(original);
+    let lines = |range: Range<usize>| &original[starts[range.start]..starts[range.end]];
+    let hunks = normalized.diff_hunks(&patched, &DiffOptions::new().context_len(0))?;
+    let mut out = Vec::with_capacity(patched.as_slice().len());
+    let mut next = 0;
+    for hunk in hunks.iter() {
+        let removed = hunk.old_lines();
+        out.extend_from_slice(lines(next..removed.start));
+        for change in hunk.changes.iter().filter(|c| c.tag == ChangeTag::Insert) {
+            out.extend_from_slice(&ending.apply(&change.line));
+        }
+        next = removed.end;
+    }
+    out.extend_from_slice(lines(next..starts.len() - 1));
+    Ok(MMFile::from_bytes(&out))
+}
+
+/// Aborts the process if dropped while a panic is unwinding, by panicking
+/// again. Forget it once the guarded code has returned normally.
+struct AbortOnUnwind;
+
+impl Drop for AbortOnUnwind {
+    fn drop(&mut self) {
+        panic!("callback panicked inside libxdiff, aborting");
+    }
+}
+
+/// Type representing an owned, compact file in libxdiff. Clones share the
+/// file's buffer, which is copied the first time a shared file is mutated.
 pub struct MMFile {
     // this mmfile is always compact
     pub(crate) inner: mmfile_t,
+    // set once the file is cloned, counting the files sharing its blocks
+    pub(crate) shares: OnceLock<Arc<()>>,
+    // content hash, computed on first use and forgotten before any mutation.
+    // A `OnceLock` since shared files fill it from several threads.
+    pub(crate) hash: OnceLock<u64>,
 }
 
+// SAFETY: shared blocks are only read, since a file copies them before
+// mutating, and libxdiff keeps no thread-local or shared state besides the
+// allocator, which is set once
+unsafe impl Send for MMFile {}
+
 impl Drop for MMFile {
     fn drop(&mut self) {
-        unsafe { xdl_free_mmfile(addr_of_mut!(self.inner)) };
+        // only the last file sharing the blocks frees them
+        let last = match self.shares.take() {
+            Some(shares) => Arc::into_inner(shares).is_some(),
+            None => true,
+        };
+        if last {
+            unsafe { xdl_free_mmfile(addr_of_mut!(self.inner)) };
+        }
     }
 }
 
@@ -35,14 +186,22 @@ impl MMFile {
     /// Create a new empty MMFile
     pub fn new() -> MMFile {
         ensure_init();
-        MMFile {
-            inner: init_mmfile(0),
-        }
+        MMFile::from_inner(init_mmfile(0))
     }
     /// Create a new MMFile initialized with contents
     pub fn from_bytes(bytes: &[u8]) -> MMFile {
+        Self::from_bytes_with_capacity(bytes, bytes.len())
+    }
+
+    /// Create a builder for an MMFile
+    pub fn builder() -> MMFileBuilder {
+        MMFileBuilder::new()
+    }
+
+    fn from_bytes_with_capacity(bytes: &[u8], capacity: usize) -> MMFile {
         ensure_init();
-        let mut inner = init_mmfile(bytes.len());
+        // the file must stay a single block, so never allocate less than the contents
+        let mut inner = init_mmfile(capacity.max(bytes.len()));
         let bytes_written = unsafe {
             xdl_write_mmfile(
                 addr_of_mut!(inner),
@@ -57,7 +216,81 @@ impl MMFile {
                 bytes.len()
             );
         }
-        MMFile { inner }
+        MMFile::from_inner(inner)
+    }
+
+    /// Wrap an `mmfile_t` that no other file shares
+    pub(crate) fn from_inner(inner: mmfile_t) -> MMFile {
+        MMFile {
+            inner,
+            shares: OnceLock::new(),
+            hash: OnceLock::new(),
+        }
+    }
+
+    /// Give this file its own copy of its blocks if a clone shares them, so
+    /// they can be mutated, and forget its content hash
+    fn make_unique(&mut self) {
+        self.hash.take();
+        // `get_mut` synchronizes with clones dropped on other threads, so
+        // their reads happen before any writes here
+        if self
+            .shares
+            .get_mut()
+            .is_some_and(|s| Arc::get_mut(s).is_none())
+        {
+            *self = MMFile::from_bytes(self.as_slice());
+        }
+    }
+
+    /// Get a pointer to the underlying `mmfile_t`, for calling libxdiff-sys
+    /// functions that aren't wrapped. Anything done through the pointer
+    /// must leave the file compact. A file sharing its buffer with a clone
+    /// is given its own copy first.
+    pub fn as_raw(&mut self) -> *mut mmfile_t {
+        self.make_unique();
+        addr_of_mut!(self.inner)
+    }
+
+    /// Convert this file into an [`MMBlocks`] that further writes can be
+    /// appended to, the inverse of [`MMBlocks::to_mmfile`]. The existing
+    /// allocation is handed over without copying.
+    pub fn into_blocks(self) -> MMBlocks {
+        // SAFETY: the file's blocks were allocated by libxdiff after init,
+        // and `into_raw` gives up ownership of them
+        unsafe { MMBlocks::from_raw(self.into_raw()) }
+    }
+
+    /// Take ownership of the underlying `mmfile_t`. The caller becomes
+    /// responsible for freeing it with `xdl_free_mmfile`. A file sharing its
+    /// buffer with a clone is copied first.
+    pub fn into_raw(mut self) -> mmfile_t {
+        self.make_unique();
+        let mut this = ManuallyDrop::new(self);
+        this.shares.take();
+        // SAFETY: `this` is never dropped, so the file is only freed by the caller
+        unsafe { ptr::read(&this.inner) }
+    }
+
+    /// Wrap an `mmfile_t`, taking ownership of it
+    ///
+    /// # Safety
+    /// `inner` must be an initialized, compact file whose blocks were
+    /// allocated by libxdiff after this crate initialized it, such as one
+    /// returned by [`into_raw`](Self::into_raw), and must not be freed or used
+    /// elsewhere afterwards.
+    pub unsafe fn from_raw(inner: mmfile_t) -> MMFile {
+        ensure_init();
+        let file = MMFile::from_inner(inner);
+        debug_assert!(file.is_compact());
+        file
+    }
+
+    /// A copy of the file's `mmfile_t` pointing at the same blocks, for
+    /// libxdiff functions that only read the file. libxdiff advances the
+    /// copy's read position, so the file itself needn't be borrowed mutably.
+    pub(crate) fn view(&self) -> mmfile_t {
+        self.inner
     }
 
     /// Get size of stored data in bytes
@@ -65,6 +298,18 @@ impl MMFile {
         unsafe { xdl_mmfile_size(addr_of_mut!(self.inner)) as usize }
     }
 
+    /// Count the lines in this file without building a line index. A
+    /// trailing line without a newline still counts as a line.
+    pub fn line_count(&self) -> usize {
+        count_lines([self.as_slice()])
+    }
+
+    /// Report the bytes allocated for this file against the bytes it stores.
+    /// Files built by appending can hold spare capacity from their allocation.
+    pub fn memory_usage(&self) -> MemoryUsage {
+        memory_usage(&self.inner)
+    }
+
     /// Checks if the entire file is a single allocation. In our library this
     /// is always true.
     pub fn is_compact(&self) -> bool {
@@ -74,113 +319,204 @@ impl MMFile {
     }
 
     /// Compute the patch to turn self into other
-    pub fn compute_patch(&mut self, other: &mut Self) -> Result<MMPatch, String> {
-        let mut patch = MMPatch::new();
-        unsafe { self.diff_raw_nopanic(other, |buf| patch.write_buf(buf))? };
-        Ok(patch)
+    pub fn compute_patch(&self, other: &Self) -> Result<Patch, Error> {
+        self.compute_patch_with(other, &DiffOptions::new())
     }
 
-    /// Apply a patch to a file. If successful, return the new file. If
-    /// unsuccessful, return (successfully patched part, rejected parts)
-    pub fn apply_patch(&mut self, patch: &mut MMPatch) -> Result<MMFile, (MMFile, MMFile)> {
-        patch.to_compact(); // patch must be compacted before use
-        let mut patched = MMPatch::new();
-        let mut rejected = MMPatch::new();
+    /// Compute the patch to turn self into other using the given options
+    pub fn compute_patch_with(&self, other: &Self, opts: &DiffOptions) -> Result<Patch, Error> {
+        let mut blocks = MMBlocks::new();
+        let mut stats = PatchStats::default();
+        // SAFETY: the callback only writes to blocks and doesn't panic
+        unsafe {
+            self.diff_records(other, opts, |record| {
+                if let [header] = record {
+                    if let Some(h) = HunkHeader::parse(header) {
+                        stats.add(&h);
+                    }
+                }
+                record
+                    .iter()
+                    .map(|buf| blocks.write_buf(buf))
+                    .min()
+                    .unwrap_or(0)
+            })?
+        };
+        let header = file_header(opts, self.as_slice(), other.as_slice(), stats.hunks > 0);
+        if header.is_empty() {
+            return Ok(Patch::from_parts(blocks.to_mmfile(), stats));
+        }
+        let mut text = header;
+        text.extend_from_slice(blocks.to_mmfile().as_slice());
+        Ok(Patch::from_parts(MMFile::from_bytes(&text), stats))
+    }
 
-        extern "C" fn emit_cb(
-            patch_ptr: *mut c_void,
-            buffers: *mut mmbuffer_t,
-            num: c_int,
-        ) -> c_int {
-            let ptr_to_patch = patch_ptr as *mut MMPatch;
-            let patch_ref = unsafe { &mut *ptr_to_patch };
+    /// Apply a patch to a file. If successful, return the new file. If some
+    /// hunks don't apply, return the successfully patched part along with
+    /// the rejected hunks, or the error if libxdiff failed.
+    pub fn apply_patch(&mut self, patch: &mut Patch) -> Result<MMFile, PatchError> {
+        self.apply_patch_mode(patch, XDL_PATCH_NORMAL as c_int)
+    }
 
-            let mut bytes_to_write = 0;
-            for i in 0..num {
-                let buffer = unsafe { buffers.add(i as usize) };
-                bytes_to_write += unsafe { (*buffer).size };
-            }
-            let bytes_written =
-                unsafe { xdl_writem_mmfile(addr_of_mut!(patch_ref.inner), buffers, num) };
-            if bytes_to_write == bytes_written {
-                0
-            } else {
-                -1
+    /// Apply a patch to a file using the given options. Results are returned
+    /// as for [`apply_patch`](Self::apply_patch).
+    #[allow(clippy::result_large_err)]
+    pub fn apply_patch_with(
+        &mut self,
+        patch: &mut Patch,
+        opts: &ApplyOptions,
+    ) -> Result<MMFile, PatchError> {
+        let mode = opts.mode(XDL_PATCH_NORMAL);
+        if !opts.preserve_line_endings {
+            return self.apply_patch_mode(patch, mode);
+        }
+        let ending = LineEnding::detect(self.as_slice());
+        let mut base = MMFile::from_bytes(&LineEnding::Lf.apply(self.as_slice()));
+        let mut patch = Patch::from_bytes(&LineEnding::Lf.apply(patch.as_slice()))
+            .map_err(PatchError::Failed)?;
+        let original = self.as_slice();
+        match base.apply_patch_mode(&mut patch, mode) {
+            Ok(patched) => {
+                restore_line_endings(original, &base, patched, ending).map_err(PatchError::Failed)
             }
+            Err(PatchError::Rejected { patched, rejected }) => Err(PatchError::Rejected {
+                patched: restore_line_endings(original, &base, patched, ending)
+                    .map_err(PatchError::Failed)?,
+                rejected,
+            }),
+            Err(e) => Err(e),
         }
+    }
+
+    /// Undo a patch: apply it with the roles of removed and added lines
+    /// swapped, turning the patch's target back into its source. Results are
+    /// returned as for [`apply_patch`](Self::apply_patch).
+    #[allow(clippy::result_large_err)]
+    pub fn apply_patch_reverse(&mut self, patch: &mut Patch) -> Result<MMFile, PatchError> {
+        self.apply_patch_mode(patch, XDL_PATCH_REVERSE as c_int)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn apply_patch_mode(&mut self, patch: &mut Patch, mode: c_int) -> Result<MMFile, PatchError> {
+        let mut patched = MMBlocks::new();
+        let mut rejected = MMBlocks::new();
+
         let mut emit_struct = xdemitcb_t {
             priv_: addr_of_mut!(patched) as *mut c_void,
-            outf: Some(emit_cb),
+            outf: Some(write_blocks_cb),
         };
         let mut reject_struct = xdemitcb_t {
             priv_: addr_of_mut!(rejected) as *mut c_void,
-            outf: Some(emit_cb),
+            outf: Some(write_blocks_cb),
         };
+        span!("xdl_patch");
+        clear_errno();
         let patch_result = unsafe {
             xdl_patch(
                 addr_of_mut!(self.inner),
-                addr_of_mut!(patch.inner),
-                XDL_PATCH_NORMAL as c_int,
+                addr_of_mut!(patch.file.inner),
+                mode,
                 addr_of_mut!(emit_struct),
                 addr_of_mut!(reject_struct),
             )
         };
-        if patch_result == 0 && rejected.size() == 0 {
-            Ok(patched.to_mmfile())
+        let rejected_size = rejected.size();
+        if rejected_size > 0 {
+            event!(rejected_bytes = rejected_size, "patch hunks rejected");
+        }
+        if rejected_size > 0 {
+            Err(PatchError::Rejected {
+                patched: patched.to_mmfile(),
+                rejected: rejected.to_mmfile(),
+            })
+        } else if patch_result != 0 {
+            Err(PatchError::Failed(Error::failed("patch", patch_result)))
         } else {
-            Err((patched.to_mmfile(), rejected.to_mmfile()))
+            Ok(patched.to_mmfile())
         }
     }
 
     #[cfg(feature = "std")]
     /// Compute the diff to turn self into other, returning diff through a
-    /// callback one line at a time. Returns `Err` if callback panics.
-    pub fn diff_raw<CB>(&mut self, other: &mut MMFile, callback: CB) -> Result<(), String>
+    /// callback one line at a time. Returns `Err` if callback panics, saying
+    /// where in the diff it happened and with what message.
+    pub fn diff_raw<CB>(&self, other: &MMFile, callback: CB) -> Result<(), Error>
     where
         CB: FnMut(&[u8]),
     {
-        let xpparam = xpparam_t { flags: 0 };
-        let conf = xdemitconf_t { ctxlen: 3 };
-        let mut boxed_cb: Box<dyn FnMut(&[u8])> = Box::new(callback);
-        let ptr_to_box = addr_of_mut!(boxed_cb);
-        let cb_ptr = ptr_to_box as *mut c_void;
-        extern "C" fn emit_cb(cb_ptr: *mut c_void, buffers: *mut mmbuffer_t, num: c_int) -> c_int {
-            let ptr_to_box = cb_ptr as *mut Box<dyn FnMut(&[u8])>;
+        self.diff_raw_with(other, &DiffOptions::new(), callback)
+    }
 
-            for i in 0..num {
-                let buffer = unsafe { buffers.add(i as usize) };
-                let slice =
-                    unsafe { from_raw_parts((*buffer).ptr as *const u8, (*buffer).size as usize) };
-                // This is unwind safe because our closure only closes over some pointers, no owned objects.
-                // After we return an error, the boxed closure will not be called any more,
-                // so any broken invariants in its closed-over variables won't be witnessed.
-                match catch_unwind(AssertUnwindSafe(|| unsafe { (*ptr_to_box)(slice) })) {
-                    Ok(_) => (),
-                    Err(_) => {
-                        // TODO: store the panic info somewhere
-                        return -1;
+    #[cfg(feature = "std")]
+    /// Like [`diff_raw`](Self::diff_raw), but diffing according to `opts`
+    pub fn diff_raw_with<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]),
+    {
+        let mut panic = None;
+        // SAFETY: the callback below catches any panic from the user callback
+        let result = unsafe {
+            self.diff_records(other, opts, |record| {
+                // After a panic the user callback is never called again, so
+                // broken invariants in its closed-over variables aren't witnessed.
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    record.iter().for_each(|buf| callback(buf))
+                }));
+                match result {
+                    Ok(()) => 0,
+                    Err(payload) => {
+                        panic = Some(panic_message(payload.as_ref()));
+                        -1
                     }
                 }
-            }
-            0
-        }
-        let mut emit_struct = xdemitcb_t {
-            priv_: cb_ptr,
-            outf: Some(emit_cb),
-        };
-        let err = unsafe {
-            xdl_diff(
-                addr_of_mut!(self.inner),
-                addr_of_mut!(other.inner),
-                addr_of!(xpparam),
-                addr_of!(conf),
-                addr_of_mut!(emit_struct),
-            )
+            })
         };
-        if err != 0 {
-            Err(format!("diff failed with err: {}", err))
-        } else {
-            Ok(())
+        result.map_err(|e| e.with_panic(panic))
+    }
+
+    /// Compute the diff to turn self into other, returning diff through a
+    /// callback one line at a time. Callback should return 0 on success and -1
+    /// on failure.
+    ///
+    ///
+    /// # Safety
+    /// The provided callback must not panic
+    pub unsafe fn diff_raw_nopanic<CB>(&self, other: &MMFile, callback: CB) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]) -> c_int,
+    {
+        unsafe { self.diff_raw_nopanic_with(other, &DiffOptions::new(), callback) }
+    }
+
+    /// Like [`diff_raw_nopanic`](Self::diff_raw_nopanic), but diffing
+    /// according to `opts`
+    ///
+    /// # Safety
+    /// The provided callback must not panic
+    pub unsafe fn diff_raw_nopanic_with<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]) -> c_int,
+    {
+        unsafe {
+            self.diff_records(other, opts, |record| {
+                for buf in record {
+                    let code = callback(buf);
+                    if code < 0 {
+                        return code;
+                    }
+                }
+                0
+            })
         }
     }
 
@@ -188,56 +524,436 @@ impl MMFile {
     /// callback one line at a time. Callback should return 0 on success and -1
     /// on failure.
     ///
+    /// Like [`diff_raw_nopanic`](Self::diff_raw_nopanic) this doesn't catch
+    /// panics, but instead of being undefined behavior a panic in the
+    /// callback aborts the process.
+    pub fn diff_raw_abort_on_panic<CB>(&self, other: &MMFile, callback: CB) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]) -> c_int,
+    {
+        self.diff_raw_abort_on_panic_with(other, &DiffOptions::new(), callback)
+    }
+
+    /// Like [`diff_raw_abort_on_panic`](Self::diff_raw_abort_on_panic), but
+    /// diffing according to `opts`
+    pub fn diff_raw_abort_on_panic_with<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]) -> c_int,
+    {
+        let guarded = move |buf: &[u8]| {
+            let guard = AbortOnUnwind;
+            let result = callback(buf);
+            forget(guard);
+            result
+        };
+        // SAFETY: a panic in the callback aborts before it can unwind into libxdiff
+        unsafe { self.diff_raw_nopanic_with(other, opts, guarded) }
+    }
+
+    #[cfg(feature = "std")]
+    /// Like [`diff_raw`](Self::diff_raw), but the callback can stop the diff
+    /// early by returning `ControlFlow::Break`. Returns `Ok(Some(value))`
+    /// with the value it broke with, `Ok(None)` if the diff ran to
+    /// completion, and `Err` if the diff failed or the callback panicked.
+    pub fn diff_raw_until<B, CB>(&self, other: &MMFile, callback: CB) -> Result<Option<B>, Error>
+    where
+        CB: FnMut(&[u8]) -> ControlFlow<B>,
+    {
+        self.diff_raw_until_with(other, &DiffOptions::new(), callback)
+    }
+
+    #[cfg(feature = "std")]
+    /// Like [`diff_raw_until`](Self::diff_raw_until), but diffing according
+    /// to `opts`
+    pub fn diff_raw_until_with<B, CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<Option<B>, Error>
+    where
+        CB: FnMut(&[u8]) -> ControlFlow<B>,
+    {
+        let stopped = Cell::new(None);
+        // SAFETY: panics are caught before they reach libxdiff
+        let result = unsafe {
+            self.diff_raw_nopanic_with(other, opts, |buf| {
+                break_code(&stopped, catch_unwind(AssertUnwindSafe(|| callback(buf))))
+            })
+        };
+        match (result, stopped.into_inner()) {
+            (_, Some(value)) => Ok(Some(value)),
+            (result, None) => result.map(|()| None),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Like [`diff_raw`](Self::diff_raw), but passes each buffer along with
+    /// what kind of buffer it is, so callers needn't guess from its bytes
+    pub fn diff_raw_tagged<CB>(&self, other: &MMFile, callback: CB) -> Result<(), Error>
+    where
+        CB: FnMut(BufferKind, &[u8]),
+    {
+        self.diff_raw_tagged_with(other, &DiffOptions::new(), callback)
+    }
+
+    #[cfg(feature = "std")]
+    /// Like [`diff_raw_tagged`](Self::diff_raw_tagged), but diffing
+    /// according to `opts`
+    pub fn diff_raw_tagged_with<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(BufferKind, &[u8]),
+    {
+        let mut panic = None;
+        // SAFETY: the callback below catches any panic from the user callback
+        let result = unsafe {
+            self.diff_records(other, opts, |record| {
+                let kinds = match record {
+                    [_] => [BufferKind::HunkHeader; 3],
+                    [prefix, ..] => {
+                        match prefix.first().copied().and_then(ChangeTag::from_prefix) {
+                            Some(tag) => [
+                                BufferKind::Prefix(tag),
+                                BufferKind::Content,
+                                BufferKind::NoNewlineMarker,
+                            ],
+                            None => return -1,
+                        }
+                    }
+                    [] => return -1,
+                };
+                let result = catch_unwind(AssertUnwindSafe(|| {
+                    record
+                        .iter()
+                        .zip(kinds)
+                        .for_each(|(buf, kind)| callback(kind, buf))
+                }));
+                match result {
+                    Ok(()) => 0,
+                    Err(payload) => {
+                        panic = Some(panic_message(payload.as_ref()));
+                        -1
+                    }
+                }
+            })
+        };
+        result.map_err(|e| e.with_panic(panic))
+    }
+
+    #[cfg(feature = "std")]
+    /// Compute the diff to turn self into other, passing each hunk header and
+    /// line to the callback as a single [`DiffEvent`], rather than as the
+    /// separate prefix and content buffers [`diff_raw`](Self::diff_raw)
+    /// passes. Returns `Err` if the callback panics, as `diff_raw` does.
+    pub fn diff_events<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(DiffEvent),
+    {
+        let mut panic = None;
+        // SAFETY: the callback below catches any panic from the user callback
+        let result = unsafe {
+            self.diff_records(other, opts, |record| {
+                let event = match record {
+                    [header] => match HunkHeader::parse(header) {
+                        Some(h) => DiffEvent::HunkStart(h),
+                        None => return -1,
+                    },
+                    [prefix, line, ..] => {
+                        match prefix.first().copied().and_then(ChangeTag::from_prefix) {
+                            Some(ChangeTag::Equal) => DiffEvent::Context(line),
+                            Some(ChangeTag::Delete) => DiffEvent::Removed(line),
+                            Some(ChangeTag::Insert) => DiffEvent::Added(line),
+                            None => return -1,
+                        }
+                    }
+                    _ => return -1,
+                };
+                match catch_unwind(AssertUnwindSafe(|| callback(event))) {
+                    Ok(()) => 0,
+                    Err(payload) => {
+                        panic = Some(panic_message(payload.as_ref()));
+                        -1
+                    }
+                }
+            })
+        };
+        result.map_err(|e| e.with_panic(panic))
+    }
+
+    /// Compute the diff to turn self into other, passing each record libxdiff
+    /// emits (a hunk header, or a line prefix followed by the line) to the
+    /// callback as one group of buffers. Callback should return 0 on success
+    /// and -1 on failure.
+    ///
+    /// # Safety
+    /// The provided callback must not panic
+    pub(crate) unsafe fn diff_records<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[&[u8]]) -> c_int,
+    {
+        if let Some(max_hunks) = opts.max_hunks {
+            return unsafe { self.diff_records_limited(other, opts, max_hunks, callback) }
+                .map(|_| ());
+        }
+        let mut position = EmitPosition::default();
+        let mut failed_at = None;
+        let result = unsafe {
+            self.diff_records_unlimited(other, opts, |record| {
+                position.advance(record);
+                let code = callback(record);
+                if code != 0 {
+                    failed_at = Some(position);
+                }
+                code
+            })
+        };
+        match (result, failed_at) {
+            (Err(_), Some(position)) => Err(position.error("diff")),
+            (result, _) => result,
+        }
+    }
+
+    /// [`diff_records`](Self::diff_records) without applying
+    /// [`DiffOptions::max_hunks`] or tracking where the callback failed
     ///
     /// # Safety
     /// The provided callback must not panic
-    pub unsafe fn diff_raw_nopanic<CB>(
-        &mut self,
-        other: &mut MMFile,
+    unsafe fn diff_records_unlimited<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
         callback: CB,
-    ) -> Result<(), String>
+    ) -> Result<(), Error>
     where
-        CB: FnMut(&[u8]) -> c_int,
+        CB: FnMut(&[&[u8]]) -> c_int,
     {
-        let xpparam = xpparam_t { flags: 0 };
-        let conf = xdemitconf_t { ctxlen: 3 };
-        let mut boxed_cb: Box<dyn FnMut(&[u8]) -> c_int> = Box::new(callback);
+        if opts.whole_file {
+            let lines = self.line_count().max(other.line_count());
+            let opts = opts.clone().whole_file(false).context_len(lines);
+            return unsafe { self.diff_records_unlimited(other, &opts, callback) };
+        }
+        if opts.ignore_bom {
+            let (old_bom, old_rest) = split_bom(self.as_slice());
+            let (new_bom, new_rest) = split_bom(other.as_slice());
+            if !old_bom.is_empty() || !new_bom.is_empty() {
+                let old = MMFile::from_bytes(old_rest);
+                let new = MMFile::from_bytes(new_rest);
+                let opts = opts.clone().ignore_bom(false);
+                return unsafe { old.diff_records_unlimited(&new, &opts, callback) };
+            }
+        }
+        if let Some((limit, fallback)) = opts.max_cost {
+            let cost = edit_cost(self.as_slice(), other.as_slice());
+            if cost > limit {
+                event!(cost, limit, "diff too expensive");
+                return match fallback {
+                    CostFallback::Error => Err(Error::TooExpensive { cost, limit }),
+                    CostFallback::Coarse => coarse_records(
+                        self.as_slice(),
+                        other.as_slice(),
+                        opts.context_len,
+                        callback,
+                    ),
+                };
+            }
+        }
+        if opts.trim_common {
+            let (old_range, new_range, skipped) =
+                trim_common_lines(self.as_slice(), other.as_slice(), opts.context_len);
+            if skipped > 0 || old_range.end < self.as_slice().len() {
+                let old = MMFile::from_bytes(&self.as_slice()[old_range]);
+                let new = MMFile::from_bytes(&other.as_slice()[new_range]);
+                let opts = opts.clone().trim_common(false);
+                let mut callback = callback;
+                return unsafe {
+                    old.diff_records_unlimited(&new, &opts, |record| match record {
+                        [header] => match HunkHeader::parse(header) {
+                            Some(h) => callback(&[&h.offset(skipped, skipped).to_bytes()]),
+                            None => callback(record),
+                        },
+                        _ => callback(record),
+                    })
+                };
+            }
+        }
+        if opts.indent_heuristic || opts.semantic_cleanup || opts.record_key.is_some() {
+            let mut raw = opts
+                .clone()
+                .indent_heuristic(false)
+                .semantic_cleanup(false)
+                .context_len(0);
+            let mut changes = match raw.record_key.take() {
+                Some(key) => keyed_changes(self.as_slice(), other.as_slice(), &key, &raw)?,
+                None => self.changed_lines(other, &raw)?,
+            };
+            if opts.indent_heuristic {
+                slide_changes(self.as_slice(), other.as_slice(), &mut changes);
+            }
+            if opts.semantic_cleanup {
+                changes = cleanup_semantic(self.as_slice(), other.as_slice(), changes);
+            }
+            return change_records(
+                self.as_slice(),
+                other.as_slice(),
+                &changes,
+                opts.context_len,
+                callback,
+            );
+        }
+        let xpparam = opts.xpparam();
+        let conf = opts.emitconf();
+        let mut boxed_cb: Box<RecordCallback> = Box::new(callback);
         let ptr_to_box = addr_of_mut!(boxed_cb);
         let cb_ptr = ptr_to_box as *mut c_void;
         extern "C" fn emit_cb(cb_ptr: *mut c_void, buffers: *mut mmbuffer_t, num: c_int) -> c_int {
-            let ptr_to_box = cb_ptr as *mut Box<dyn FnMut(&[u8]) -> c_int>;
+            let ptr_to_box = cb_ptr as *mut Box<RecordCallback>;
+            if num == 1 {
+                event!("hunk emitted");
+            }
 
+            let mut record = Vec::with_capacity(num as usize);
             for i in 0..num {
                 let buffer = unsafe { buffers.add(i as usize) };
                 let slice =
                     unsafe { from_raw_parts((*buffer).ptr as *const u8, (*buffer).size as usize) };
-                let cb_result = unsafe { (*ptr_to_box)(slice) };
-                if cb_result < 0 {
-                    return cb_result as c_int;
-                }
+                record.push(slice);
             }
-            0
+            unsafe { (*ptr_to_box)(&record) }
         }
         let mut emit_struct = xdemitcb_t {
             priv_: cb_ptr,
             outf: Some(emit_cb),
         };
+        span!("xdl_diff");
+        clear_errno();
         let err = unsafe {
             xdl_diff(
-                addr_of_mut!(self.inner),
-                addr_of_mut!(other.inner),
+                &mut self.view(),
+                &mut other.view(),
                 addr_of!(xpparam),
                 addr_of!(conf),
                 addr_of_mut!(emit_struct),
             )
         };
         if err != 0 {
-            Err(format!("diff failed with errno: {}", err))
+            Err(Error::failed("diff", err))
         } else {
             Ok(())
         }
     }
 
+    /// Like [`diff_records`](Self::diff_records), but stops after
+    /// `max_hunks` hunks, overriding [`DiffOptions::max_hunks`]. Returns
+    /// whether any hunks were left out.
+    ///
+    /// # Safety
+    /// The provided callback must not panic
+    pub(crate) unsafe fn diff_records_limited<CB>(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        max_hunks: usize,
+        mut callback: CB,
+    ) -> Result<bool, Error>
+    where
+        CB: FnMut(&[&[u8]]) -> c_int,
+    {
+        let mut opts = opts.clone();
+        opts.max_hunks = None;
+        let mut hunks = 0;
+        let mut truncated = false;
+        let result = unsafe {
+            self.diff_records(other, &opts, |record| {
+                if let [_header] = record {
+                    if hunks == max_hunks {
+                        truncated = true;
+                        return -1;
+                    }
+                    hunks += 1;
+                }
+                callback(record)
+            })
+        };
+        match result {
+            Err(_) if truncated => Ok(true),
+            result => result.map(|()| false),
+        }
+    }
+
+    /// Check whether two files differ under the given options, stopping as
+    /// soon as libxdiff reports the first change instead of collecting any
+    /// hunks. Byte-identical files are detected without diffing at all.
+    pub fn differs(&self, other: &MMFile, opts: &DiffOptions) -> Result<bool, Error> {
+        if self.as_slice() == other.as_slice() {
+            return Ok(false);
+        }
+        if !opts.ignore_bom && opts.record_key.is_none() {
+            // only options that can hide a byte difference require a diff
+            return Ok(true);
+        }
+        let mut found = false;
+        // SAFETY: the callback only sets a flag and doesn't panic
+        let result = unsafe {
+            self.diff_records_limited(other, opts, usize::MAX, |_| {
+                found = true;
+                // abort the diff now that the answer is known
+                -1
+            })
+        };
+        match result {
+            Ok(_) => Ok(false),
+            Err(_) if found => Ok(true),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Check whether every line of this file appears, in order, in `other`,
+    /// so that the diff to `other` only adds lines. Stops as soon as libxdiff
+    /// reports a removed line.
+    pub fn is_contained_in(&self, other: &MMFile, opts: &DiffOptions) -> Result<bool, Error> {
+        if self.as_slice() == other.as_slice() {
+            return Ok(true);
+        }
+        let opts = opts.clone().context_len(0);
+        let mut removed = false;
+        // SAFETY: the callback only sets a flag and doesn't panic
+        let result = unsafe {
+            self.diff_records(other, &opts, |record| match record {
+                [b"-", ..] => {
+                    removed = true;
+                    // abort the diff now that the answer is known
+                    -1
+                }
+                _ => 0,
+            })
+        };
+        match result {
+            Ok(()) => Ok(true),
+            Err(_) if removed => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
     #[cfg(feature = "std")]
     /// Compute the file that results from merging two sets of changes to the
     /// base file. The resulting file is passed line-by-line to the
@@ -250,7 +966,7 @@ impl MMFile {
         f2: &mut MMFile,
         accept_callback: CBA,
         reject_callback: CBR,
-    ) -> Result<(), String>
+    ) -> Result<(), Error>
     where
         CBA: FnMut(&[u8]),
         CBR: FnMut(&[u8]),
@@ -294,6 +1010,7 @@ impl MMFile {
             num: c_int,
         ) -> c_int {
             let ptr_to_box = cb_ptr as *mut Box<dyn FnMut(&[u8])>;
+            event!("merge reject emitted");
 
             for i in 0..num {
                 let buffer = unsafe { buffers.add(i as usize) };
@@ -316,6 +1033,88 @@ impl MMFile {
             priv_: void_rej_ptr,
             outf: Some(reject_cb),
         };
+        span!("xdl_merge3");
+        clear_errno();
+        let err = unsafe {
+            xdl_merge3(
+                addr_of_mut!(base.inner),
+                addr_of_mut!(f1.inner),
+                addr_of_mut!(f2.inner),
+                addr_of_mut!(emit_struct),
+                addr_of_mut!(reject_struct),
+            )
+        };
+        if err != 0 {
+            Err(Error::failed("merge", err))
+        } else {
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "std")]
+    /// Like [`merge3_raw`](Self::merge3_raw), but either callback can stop
+    /// the merge early by returning `ControlFlow::Break`. Returns
+    /// `Ok(Some(value))` with the value it broke with, `Ok(None)` if the
+    /// merge ran to completion, and `Err` if the merge failed or a callback
+    /// panicked.
+    pub fn merge3_raw_until<B, CBA, CBR>(
+        base: &mut MMFile,
+        f1: &mut MMFile,
+        f2: &mut MMFile,
+        mut accept_callback: CBA,
+        mut reject_callback: CBR,
+    ) -> Result<Option<B>, Error>
+    where
+        CBA: FnMut(&[u8]) -> ControlFlow<B>,
+        CBR: FnMut(&[u8]) -> ControlFlow<B>,
+    {
+        let stopped = Cell::new(None);
+        // SAFETY: panics are caught before they reach libxdiff
+        let result = unsafe {
+            Self::merge3_nopanic(
+                base,
+                f1,
+                f2,
+                |buf| {
+                    break_code(
+                        &stopped,
+                        catch_unwind(AssertUnwindSafe(|| accept_callback(buf))),
+                    )
+                },
+                |buf| {
+                    break_code(
+                        &stopped,
+                        catch_unwind(AssertUnwindSafe(|| reject_callback(buf))),
+                    )
+                },
+            )
+        };
+        match (result, stopped.into_inner()) {
+            (_, Some(value)) => Ok(Some(value)),
+            (result, None) => result.map(|()| None),
+        }
+    }
+
+    /// Compute the file that results from merging two sets of changes to the
+    /// base file, appending it directly to `output`. Any conflicting changes
+    /// are appended to `rejected`.
+    pub fn merge3_into(
+        base: &mut MMFile,
+        f1: &mut MMFile,
+        f2: &mut MMFile,
+        output: &mut MMBlocks,
+        rejected: &mut MMBlocks,
+    ) -> Result<(), Error> {
+        let mut emit_struct = xdemitcb_t {
+            priv_: output as *mut MMBlocks as *mut c_void,
+            outf: Some(write_blocks_cb),
+        };
+        let mut reject_struct = xdemitcb_t {
+            priv_: rejected as *mut MMBlocks as *mut c_void,
+            outf: Some(write_blocks_cb),
+        };
+        span!("xdl_merge3");
+        clear_errno();
         let err = unsafe {
             xdl_merge3(
                 addr_of_mut!(base.inner),
@@ -326,7 +1125,66 @@ impl MMFile {
             )
         };
         if err != 0 {
-            Err(format!("merge failed with err: {}", err))
+            Err(Error::failed("merge", err))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Merge two sets of changes to the base file, passing accepted and
+    /// rejected output to the callbacks one buffer at a time. Callbacks should
+    /// return 0 on success and -1 on failure.
+    ///
+    /// # Safety
+    /// The provided callbacks must not panic
+    pub(crate) unsafe fn merge3_nopanic<CBA, CBR>(
+        base: &mut MMFile,
+        f1: &mut MMFile,
+        f2: &mut MMFile,
+        accept_callback: CBA,
+        reject_callback: CBR,
+    ) -> Result<(), Error>
+    where
+        CBA: FnMut(&[u8]) -> c_int,
+        CBR: FnMut(&[u8]) -> c_int,
+    {
+        let mut boxed_acc_cb: Box<BufCallback> = Box::new(accept_callback);
+        let mut boxed_rej_cb: Box<BufCallback> = Box::new(reject_callback);
+        extern "C" fn emit_cb(cb_ptr: *mut c_void, buffers: *mut mmbuffer_t, num: c_int) -> c_int {
+            let ptr_to_box = cb_ptr as *mut Box<BufCallback>;
+
+            for i in 0..num {
+                let buffer = unsafe { buffers.add(i as usize) };
+                let slice =
+                    unsafe { from_raw_parts((*buffer).ptr as *const u8, (*buffer).size as usize) };
+                let cb_result = unsafe { (*ptr_to_box)(slice) };
+                if cb_result < 0 {
+                    return cb_result;
+                }
+            }
+            0
+        }
+        let mut emit_struct = xdemitcb_t {
+            priv_: addr_of_mut!(boxed_acc_cb) as *mut c_void,
+            outf: Some(emit_cb),
+        };
+        let mut reject_struct = xdemitcb_t {
+            priv_: addr_of_mut!(boxed_rej_cb) as *mut c_void,
+            outf: Some(emit_cb),
+        };
+        span!("xdl_merge3");
+        clear_errno();
+        let err = unsafe {
+            xdl_merge3(
+                addr_of_mut!(base.inner),
+                addr_of_mut!(f1.inner),
+                addr_of_mut!(f2.inner),
+                addr_of_mut!(emit_struct),
+                addr_of_mut!(reject_struct),
+            )
+        };
+        if err != 0 {
+            Err(Error::failed("merge", err))
         } else {
             Ok(())
         }
@@ -352,8 +1210,55 @@ impl MMFile {
         }
     }
 
-    /// Get a mutable view of the `MMFile`'s data as a slice
+    /// Join `parts` into one file, copying each into a single allocation of
+    /// the combined size. To join files without copying, use
+    /// [`MMBlocks::push_file`].
+    pub fn concat(parts: &[MMFile]) -> MMFile {
+        let len = parts.iter().map(|p| p.as_slice().len()).sum();
+        let mut file = MMFile::from_inner(init_mmfile(len));
+        for part in parts {
+            let bytes = part.as_slice();
+            // the single block was sized for every part, so writes can't fail
+            unsafe {
+                xdl_write_mmfile(
+                    addr_of_mut!(file.inner),
+                    bytes.as_ptr() as *const c_void,
+                    bytes.len() as c_long,
+                )
+            };
+        }
+        file
+    }
+
+    /// Split the file in two at byte offset `at`, keeping `[0, at)` in place
+    /// and returning a new file holding `[at, len)`, like `Vec::split_off`
+    ///
+    /// # Panics
+    ///
+    /// Panics if `at` is past the end of the file.
+    pub fn split_off(&mut self, at: usize) -> MMFile {
+        let len = self.as_slice().len();
+        assert!(
+            at <= len,
+            "split offset {} is past the end of a {} byte file",
+            at,
+            len
+        );
+        self.make_unique();
+        let tail = MMFile::from_bytes(&self.as_slice()[at..]);
+        if at < len {
+            // SAFETY: a compact file with contents has a head block holding
+            // all of them, so shrinking it keeps the file compact
+            unsafe { (*self.inner.head).size = at as c_long };
+            self.inner.fsize = at as c_long;
+        }
+        tail
+    }
+
+    /// Get a mutable view of the `MMFile`'s data as a slice. A file sharing
+    /// its buffer with a clone is given its own copy first.
     pub fn as_slice_mut(&mut self) -> &mut [u8] {
+        self.make_unique();
         assert!(self.is_compact());
         let head_block = self.inner.head;
         if head_block.is_null() {
@@ -373,26 +1278,114 @@ impl MMFile {
     }
 }
 
+impl Default for MMFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Builder for an [`MMFile`], for when the contents need normalizing or the
+/// allocation size matters
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MMFileBuilder {
+    capacity: usize,
+    content: Vec<u8>,
+    line_ending: Option<LineEnding>,
+    final_newline: bool,
+}
+
+impl MMFileBuilder {
+    /// Create a builder for an empty file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocate at least this many bytes for the file. The allocation always
+    /// grows to fit the contents.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set the initial contents of the file
+    pub fn content(mut self, content: impl Into<Vec<u8>>) -> Self {
+        self.content = content.into();
+        self
+    }
+
+    /// Rewrite every line ending in the contents to `ending`
+    pub fn line_ending(mut self, ending: LineEnding) -> Self {
+        self.line_ending = Some(ending);
+        self
+    }
+
+    /// Terminate the last line if it isn't already. Uses the line ending
+    /// set with [`line_ending`](Self::line_ending), or `\n` if none is set.
+    pub fn final_newline(mut self, final_newline: bool) -> Self {
+        self.final_newline = final_newline;
+        self
+    }
+
+    /// Create the file
+    pub fn build(&self) -> MMFile {
+        let mut content = match self.line_ending {
+            Some(ending) => ending.apply(&self.content),
+            None => self.content.clone(),
+        };
+        if self.final_newline && !content.is_empty() && !content.ends_with(b"\n") {
+            content.extend_from_slice(self.line_ending.unwrap_or_default().as_bytes());
+        }
+        MMFile::from_bytes_with_capacity(&content, self.capacity)
+    }
+}
+
 impl Clone for MMFile {
+    /// Make a file sharing this one's buffer, without copying it
     fn clone(&self) -> Self {
-        Self::from_bytes(self.as_slice())
+        let shares = self.shares.get_or_init(|| Arc::new(())).clone();
+        MMFile {
+            inner: self.inner,
+            shares: OnceLock::from(shares),
+            hash: self.hash.clone(),
+        }
     }
 }
 
 impl PartialEq for MMFile {
+    /// Files of different sizes, or whose content hashes have both already
+    /// been computed and differ, are told apart without comparing their
+    /// bytes. No hash is computed just to compare, since that reads the file
+    /// anyway.
     fn eq(&self, other: &Self) -> bool {
+        if let (Some(a), Some(b)) = (self.hash.get(), other.hash.get()) {
+            if a != b {
+                return false;
+            }
+        }
         self.as_slice() == other.as_slice()
     }
 }
 
+impl AsRef<[u8]> for MMFile {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+// equality compares contents, so borrowing as the contents is consistent
+impl Borrow<[u8]> for MMFile {
+    fn borrow(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
 impl Debug for MMFile {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match from_utf8(self.as_slice()) {
-            Ok(s) => f.write_fmt(format_args!("MMFile UTF:\n\"{}\"", s)),
-            Err(_) => {
-                // just use debug impl if not utf
-                Debug::fmt(&self, f)
-            }
-        }
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let bytes = self.as_slice();
+        f.debug_struct("MMFile")
+            .field("size", &bytes.len())
+            .field("blocks", &block_slices(&self.inner).count())
+            .field("preview", &preview(core::iter::once(bytes)))
+            .finish()
     }
 }
@@ -0,0 +1,349 @@
+use core::{
+    ffi::{c_int, c_long, c_ulong},
+    fmt,
+};
+use std::{sync::Arc, time::SystemTime};
+
+use libxdiff_sys::{
+    bdiffparam_t, xdemitconf_t, xpparam_t, XDF_NEED_MINIMAL, XDL_PATCH_IGNOREBSPACE,
+};
+
+#[cfg(feature = "git-header")]
+use crate::GitHeader;
+
+/// How line numbers in hunk headers are reported when only part of a file is
+/// diffed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HunkNumbering {
+    /// Line numbers count from the start of the diffed range
+    #[default]
+    Relative,
+    /// Line numbers count from the start of the whole file
+    Absolute,
+}
+
+bitflags::bitflags! {
+    /// Flags passed to libxdiff's diff algorithm through `xpparam_t`. Bits
+    /// without a named constant are passed through unchanged, so flags added
+    /// upstream can be set with [`XFlags::from_bits_retain`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+    pub struct XFlags: c_ulong {
+        /// Spend extra effort to produce the smallest possible diff
+        const NEED_MINIMAL = XDF_NEED_MINIMAL as c_ulong;
+
+        const _ = !0;
+    }
+}
+
+/// What to do when a diff exceeds [`DiffOptions::max_cost`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CostFallback {
+    /// Fail with [`Error::TooExpensive`](crate::Error::TooExpensive)
+    #[default]
+    Error,
+    /// Skip the diff algorithm and emit a single hunk replacing everything
+    /// between the lines shared at the start and end of both files
+    Coarse,
+}
+
+/// Function mapping a line to the key it is compared by
+pub(crate) type KeyFn = dyn Fn(&[u8]) -> Vec<u8> + Send + Sync;
+
+/// A function mapping each line to the key it is compared by
+#[derive(Clone)]
+pub(crate) struct RecordKey(pub(crate) Arc<KeyFn>);
+
+impl PartialEq for RecordKey {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for RecordKey {}
+
+impl fmt::Debug for RecordKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RecordKey(..)")
+    }
+}
+
+/// Options controlling how a diff is computed and emitted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffOptions {
+    pub(crate) context_len: usize,
+    pub(crate) whole_file: bool,
+    pub(crate) flags: XFlags,
+    pub(crate) numbering: HunkNumbering,
+    pub(crate) ignore_bom: bool,
+    pub(crate) trim_common: bool,
+    pub(crate) max_cost: Option<(usize, CostFallback)>,
+    pub(crate) max_hunks: Option<usize>,
+    pub(crate) indent_heuristic: bool,
+    pub(crate) semantic_cleanup: bool,
+    pub(crate) record_key: Option<RecordKey>,
+    #[cfg(feature = "git-header")]
+    pub(crate) git_header: Option<GitHeader>,
+    pub(crate) labels: Option<(String, String)>,
+    pub(crate) timestamps: Option<(SystemTime, SystemTime)>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context_len: 3,
+            whole_file: false,
+            flags: XFlags::empty(),
+            numbering: HunkNumbering::Relative,
+            ignore_bom: false,
+            trim_common: false,
+            max_cost: None,
+            max_hunks: None,
+            indent_heuristic: false,
+            semantic_cleanup: false,
+            record_key: None,
+            #[cfg(feature = "git-header")]
+            git_header: None,
+            labels: None,
+            timestamps: None,
+        }
+    }
+}
+
+impl DiffOptions {
+    /// Create options matching the defaults used by [`MMFile::diff_raw`](crate::MMFile::diff_raw)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the number of unchanged lines emitted around each change
+    pub fn context_len(mut self, lines: usize) -> Self {
+        self.context_len = lines;
+        self
+    }
+
+    /// Emit every line of both files as context, so a diff with any changes
+    /// is a single hunk covering the whole file, as side-by-side viewers
+    /// need. Overrides [`context_len`](Self::context_len). Identical files
+    /// still produce no hunks.
+    pub fn whole_file(mut self, enable: bool) -> Self {
+        self.whole_file = enable;
+        self
+    }
+
+    /// Spend extra effort to produce the smallest possible diff
+    pub fn minimal(mut self, minimal: bool) -> Self {
+        self.flags.set(XFlags::NEED_MINIMAL, minimal);
+        self
+    }
+
+    /// Replace all flags passed to libxdiff's diff algorithm
+    pub fn flags(mut self, flags: XFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Choose how hunk headers are numbered for range-restricted diffs
+    pub fn numbering(mut self, numbering: HunkNumbering) -> Self {
+        self.numbering = numbering;
+        self
+    }
+
+    /// Strip any UTF-8 or UTF-16 byte order mark from both files before
+    /// diffing, so a BOM added or removed by an editor isn't reported as a
+    /// change to the first line
+    pub fn ignore_bom(mut self, ignore: bool) -> Self {
+        self.ignore_bom = ignore;
+        self
+    }
+
+    /// Strip lines shared at the start and end of both files before handing
+    /// them to libxdiff, then renumber the resulting hunks. This makes
+    /// localized edits to large files much cheaper to diff, though an
+    /// ambiguous change may be aligned differently than without trimming.
+    pub fn trim_common(mut self, trim: bool) -> Self {
+        self.trim_common = trim;
+        self
+    }
+
+    /// Bound the work spent on a diff. The cost is estimated up front as the
+    /// product of the line counts of both files once common leading and
+    /// trailing lines are removed, which bounds the work of the diff
+    /// algorithm. When it exceeds `limit`, `fallback` decides the outcome;
+    /// with [`CostFallback::Error`] the diff returns
+    /// [`Error::TooExpensive`](crate::Error::TooExpensive).
+    pub fn max_cost(mut self, limit: usize, fallback: CostFallback) -> Self {
+        self.max_cost = Some((limit, fallback));
+        self
+    }
+
+    /// Stop emitting output after the first `hunks` hunks, for previews of
+    /// large diffs. Use [`MMFile::diff_hunks_limited`](crate::MMFile::diff_hunks_limited)
+    /// to find out whether any hunks were left out.
+    pub fn max_hunks(mut self, hunks: usize) -> Self {
+        self.max_hunks = Some(hunks);
+        self
+    }
+
+    /// After diffing, slide changes whose position is ambiguous, such as an
+    /// inserted block that repeats the lines around it, so their edges line
+    /// up with the indentation of the surrounding code, as git's indent
+    /// heuristic does. Only changes that purely insert or delete lines move.
+    pub fn indent_heuristic(mut self, enable: bool) -> Self {
+        self.indent_heuristic = enable;
+        self
+    }
+
+    /// After diffing, merge changes separated only by a few unchanged lines
+    /// that are shorter than the changes around them, as diff-match-patch's
+    /// semantic cleanup does. The diff gets larger but reads more like how a
+    /// person would describe the edit, which suits prose.
+    pub fn semantic_cleanup(mut self, enable: bool) -> Self {
+        self.semantic_cleanup = enable;
+        self
+    }
+
+    /// Compare lines by the key `key` maps each one to, such as the line
+    /// trimmed, lowercased or with comments stripped, while still emitting
+    /// the original lines. This generalizes options that ignore some kind of
+    /// difference. Keys are interned and the diff runs on the interned ids.
+    pub fn record_key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.record_key = Some(RecordKey(Arc::new(key)));
+        self
+    }
+
+    /// Start patch text with git's `diff --git`, mode, `index` and
+    /// `---`/`+++` lines for the file `header` describes, so the output of
+    /// [`MMFile::compute_patch_with`](crate::MMFile::compute_patch_with) or
+    /// [`MMFile::diff_unified`](crate::MMFile::diff_unified) can be fed to
+    /// `git apply` as-is. APIs returning structured hunks ignore this.
+    #[cfg(feature = "git-header")]
+    pub fn git_header(mut self, header: GitHeader) -> Self {
+        self.git_header = Some(header);
+        self
+    }
+
+    /// Start patch text with `--- <old>` and `+++ <new>` file header lines,
+    /// such as `a/file` and `b/file`, which many tools require before they
+    /// will parse a diff. These replace the labels `git_header` would write. Diffs without hunks get no labels.
+    pub fn labels(mut self, old: impl Into<String>, new: impl Into<String>) -> Self {
+        self.labels = Some((old.into(), new.into()));
+        self
+    }
+
+    /// Follow each `---`/`+++` label with a tab and the file's modification
+    /// time in UTC, in the traditional `diff -u` format, for tools that
+    /// require them. Has no effect unless labels are written.
+    /// [`Patch::file_labels`](crate::Patch::file_labels) reads them back.
+    pub fn timestamps(mut self, old: SystemTime, new: SystemTime) -> Self {
+        self.timestamps = Some((old, new));
+        self
+    }
+
+    pub(crate) fn xpparam(&self) -> xpparam_t {
+        xpparam_t {
+            flags: self.flags.bits(),
+        }
+    }
+
+    pub(crate) fn emitconf(&self) -> xdemitconf_t {
+        xdemitconf_t {
+            ctxlen: self.context_len as c_long,
+        }
+    }
+}
+
+/// Options controlling how a patch is applied
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApplyOptions {
+    pub(crate) ignore_whitespace: bool,
+    pub(crate) preserve_line_endings: bool,
+}
+
+impl ApplyOptions {
+    /// Create options matching the behavior of [`MMFile::apply_patch`](crate::MMFile::apply_patch)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ignore leading blanks when matching the patch's context and removed
+    /// lines against the file, so a patch still applies after the file was
+    /// reindented. Whitespace elsewhere in a line must still match.
+    pub fn ignore_whitespace(mut self, ignore: bool) -> Self {
+        self.ignore_whitespace = ignore;
+        self
+    }
+
+    /// Match lines regardless of `\n` vs `\r\n` endings. Lines the patch
+    /// leaves alone keep their own ending, and inserted lines use the ending
+    /// most common in the file being patched.
+    pub fn preserve_line_endings(mut self, preserve: bool) -> Self {
+        self.preserve_line_endings = preserve;
+        self
+    }
+
+    pub(crate) fn mode(&self, mode: u8) -> c_int {
+        let mut mode = mode as c_int;
+        if self.ignore_whitespace {
+            mode |= XDL_PATCH_IGNOREBSPACE as c_int;
+        }
+        mode
+    }
+}
+
+/// Algorithm used to compute binary deltas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BDiffAlgorithm {
+    /// Match fixed-size blocks of the source file. Faster, and tunable with
+    /// [`BDiffOptions::block_size`].
+    #[default]
+    Blocks,
+    /// Match content-defined chunks found with Rabin fingerprints. Slower, but
+    /// finds matches regardless of alignment; ignores the block size.
+    Rabin,
+}
+
+/// Options controlling how a binary delta is computed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BDiffOptions {
+    pub(crate) block_size: usize,
+    pub(crate) algorithm: BDiffAlgorithm,
+}
+
+impl Default for BDiffOptions {
+    fn default() -> Self {
+        Self {
+            block_size: 32,
+            algorithm: BDiffAlgorithm::Blocks,
+        }
+    }
+}
+
+impl BDiffOptions {
+    /// Create options matching the defaults used by [`MMFile::bdiff`](crate::MMFile::bdiff)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the size of the blocks matched between files. Smaller blocks find
+    /// more matches and produce smaller deltas but take longer; large inputs
+    /// such as disk images benefit from larger blocks.
+    pub fn block_size(mut self, bytes: usize) -> Self {
+        self.block_size = bytes;
+        self
+    }
+
+    /// Choose the delta algorithm
+    pub fn algorithm(mut self, algorithm: BDiffAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub(crate) fn bdiffparam(&self) -> bdiffparam_t {
+        bdiffparam_t {
+            bsize: self.block_size as c_long,
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use core::fmt::{self, Debug, Display, Formatter};
+
+use crate::{
+    hunk::{parse_hunks, write_hunk},
+    Error, Hunk, HunkHeader, MMFile,
+};
+
+/// Line counts accumulated from hunk headers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct PatchStats {
+    pub(crate) hunks: usize,
+    pub(crate) source_lines: usize,
+    pub(crate) target_lines: usize,
+}
+
+impl PatchStats {
+    pub(crate) fn add(&mut self, header: &HunkHeader) {
+        self.hunks += 1;
+        self.source_lines += header.old_len;
+        self.target_lines += header.new_len;
+    }
+}
+
+/// A validated unified diff that can be applied with
+/// [`MMFile::apply_patch`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub(crate) file: MMFile,
+    stats: PatchStats,
+}
+
+impl Patch {
+    /// Create a patch from the text of a unified diff. Returns `Err` if the
+    /// text contains no hunks or a hunk is malformed. Any lines before the
+    /// first hunk header, such as `---`/`+++` file headers, are kept as-is.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Patch, Error> {
+        let hunks = parse_hunks(bytes)?;
+        if hunks.is_empty() && !bytes.is_empty() {
+            return Err(Error::Invalid("patch contains no hunks".to_owned()));
+        }
+        let mut stats = PatchStats::default();
+        hunks.iter().for_each(|h| stats.add(&h.header));
+        Ok(Patch::from_parts(MMFile::from_bytes(bytes), stats))
+    }
+
+    pub(crate) fn from_parts(file: MMFile, stats: PatchStats) -> Patch {
+        Patch { file, stats }
+    }
+
+    /// Number of hunks in this patch
+    pub fn hunk_count(&self) -> usize {
+        self.stats.hunks
+    }
+
+    /// Total number of lines of the source file covered by this patch's hunks
+    pub fn source_lines(&self) -> usize {
+        self.stats.source_lines
+    }
+
+    /// Total number of lines of the target file covered by this patch's hunks
+    pub fn target_lines(&self) -> usize {
+        self.stats.target_lines
+    }
+
+    /// Whether this patch makes no changes
+    pub fn is_empty(&self) -> bool {
+        self.stats.hunks == 0
+    }
+
+    /// Parse this patch's hunks, with their headers, lines and line
+    /// indices, for displaying or filtering a patch without the files it was
+    /// made from. Byte offsets are unknown and left unset.
+    pub fn hunks(&self) -> Result<Vec<Hunk>, Error> {
+        parse_hunks(self.as_slice())
+    }
+
+    /// Get a view of the patch text as a slice
+    pub fn as_slice(&self) -> &[u8] {
+        self.file.as_slice()
+    }
+
+    /// Convert this patch into an MMFile containing its text
+    pub fn to_mmfile(self) -> MMFile {
+        self.file
+    }
+}
+
+/// Why a patch could not be applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatchError<R = MMFile> {
+    /// Some hunks didn't match the file. `patched` is the result of applying
+    /// the others, and `rejected` holds the hunks that didn't apply.
+    Rejected { patched: MMFile, rejected: R },
+    /// libxdiff failed, for example by running out of memory
+    Failed(Error),
+}
+
+impl<R> Display for PatchError<R> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Rejected { .. } => f.write_str("patch hunks were rejected"),
+            PatchError::Failed(e) => Display::fmt(e, f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Debug> std::error::Error for PatchError<R> {}
+
+/// A hunk of a patch that could not be applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedHunk {
+    /// Position of the hunk within the patch, counting from 0, or `None` if
+    /// libxdiff rejected a hunk that doesn't match any hunk of the patch
+    pub index: Option<usize>,
+    pub hunk: Hunk,
+}
+
+/// What [`MMFile::apply_patch_resolving`] should do with a hunk that could
+/// not be applied, or what a [`MergeDriver`](crate::MergeDriver) decided
+/// for a conflicting region
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Replace the lines of the file the hunk or conflict covers with this
+    /// content
+    Replace(Vec<u8>),
+    /// Leave the file as it is and continue. In a merge, "our" lines are
+    /// kept and the conflict stays recorded.
+    Skip,
+    /// Stop applying the patch or merging
+    Abort,
+}
+
+impl MMFile {
+    /// Apply a patch to a file one hunk at a time, calling `resolve` for each
+    /// hunk that is rejected to decide how to continue. Replacement content
+    /// takes the place of the lines the hunk's header names in the partially
+    /// patched file. Returns `Err` if `resolve` aborts or the patch is
+    /// malformed.
+    pub fn apply_patch_resolving<F>(&self, patch: &Patch, mut resolve: F) -> Result<MMFile, Error>
+    where
+        F: FnMut(&RejectedHunk) -> Resolution,
+    {
+        let mut current = self.clone();
+        // lines added by earlier hunks, to find later ones in the patched file
+        let mut delta = 0isize;
+        for (index, hunk) in parse_hunks(patch.as_slice())?.into_iter().enumerate() {
+            let old = hunk.old_lines();
+            let start = (old.start as isize + delta).max(0) as usize;
+            let mut shifted = hunk.clone();
+            shifted.header = HunkHeader {
+                old_start: start + (hunk.header.old_len > 0) as usize,
+                old_len: old.len(),
+                new_start: start + (hunk.header.new_len > 0) as usize,
+                new_len: hunk.header.new_len,
+            };
+            let mut text = Vec::new();
+            write_hunk(&mut text, &shifted);
+            let mut single = Patch::from_bytes(&text)?;
+            match current.apply_patch(&mut single) {
+                Ok(patched) => {
+                    current = patched;
+                    delta += hunk.header.new_len as isize - old.len() as isize;
+                    continue;
+                }
+                Err(PatchError::Failed(e)) => return Err(e),
+                Err(PatchError::Rejected { .. }) => {}
+            }
+            match resolve(&RejectedHunk {
+                index: Some(index),
+                hunk,
+            }) {
+                Resolution::Replace(content) => {
+                    let lines: Vec<&[u8]> = current
+                        .as_slice()
+                        .split_inclusive(|b| *b == b'\n')
+                        .collect();
+                    let end = (start + old.len()).min(lines.len());
+                    let start = start.min(end);
+                    let mut replaced = lines[..start].concat();
+                    replaced.extend_from_slice(&content);
+                    replaced.extend(lines[end..].concat());
+                    let added = content.split_inclusive(|b| *b == b'\n').count();
+                    delta += added as isize - (end - start) as isize;
+                    current = MMFile::from_bytes(&replaced);
+                }
+                Resolution::Skip => {}
+                Resolution::Abort => {
+                    return Err(Error::Other(format!("patch aborted at hunk {}", index)))
+                }
+            }
+        }
+        Ok(current)
+    }
+
+    /// Apply a patch to a file. If successful, return the new file. If some
+    /// hunks don't apply, return the successfully patched part along with
+    /// every hunk that was rejected. Fails with [`PatchError::Failed`] if
+    /// libxdiff fails or its rejected hunks can't be parsed.
+    pub fn apply_patch_hunks(
+        &mut self,
+        patch: &mut Patch,
+    ) -> Result<MMFile, PatchError<Vec<RejectedHunk>>> {
+        let (patched, rejected) = match self.apply_patch(patch) {
+            Ok(patched) => return Ok(patched),
+            Err(PatchError::Rejected { patched, rejected }) => (patched, rejected),
+            Err(PatchError::Failed(e)) => return Err(PatchError::Failed(e)),
+        };
+        let patch_hunks = parse_hunks(patch.as_slice()).map_err(PatchError::Failed)?;
+        let rejected_hunks = parse_hunks(rejected.as_slice()).map_err(PatchError::Failed)?;
+        Err(PatchError::Rejected {
+            patched,
+            rejected: match_rejects(&patch_hunks, rejected_hunks),
+        })
+    }
+}
+
+/// How a patch relates to a file, as found by [`MMFile::patch_status`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatchStatus {
+    /// The patch applies cleanly
+    Applies,
+    /// The patch doesn't apply, but undoing it does, so the file appears to
+    /// already contain its changes
+    AlreadyApplied,
+    /// The patch applies neither forward nor in reverse
+    Conflicts,
+}
+
+impl MMFile {
+    /// Check whether `patch` applies to this file, and if not, whether the
+    /// file already contains its changes, by trying to apply it forward and
+    /// then in reverse. A patch that applies cleanly is reported as
+    /// [`PatchStatus::Applies`] even if reversing it would also succeed.
+    /// Returns `Err` if libxdiff fails rather than rejecting hunks.
+    pub fn patch_status(&mut self, patch: &mut Patch) -> Result<PatchStatus, Error> {
+        match self.apply_patch(patch) {
+            Ok(_) => return Ok(PatchStatus::Applies),
+            Err(PatchError::Failed(e)) => return Err(e),
+            Err(PatchError::Rejected { .. }) => {}
+        }
+        match self.apply_patch_reverse(patch) {
+            Ok(_) => Ok(PatchStatus::AlreadyApplied),
+            Err(PatchError::Failed(e)) => Err(e),
+            Err(PatchError::Rejected { .. }) => Ok(PatchStatus::Conflicts),
+        }
+    }
+}
+
+/// Find the index of each rejected hunk within the original patch. Rejects
+/// are emitted in patch order, so each is matched to the first identical hunk
+/// after the previous match. A reject matching no later hunk gets no index.
+pub(crate) fn match_rejects(patch_hunks: &[Hunk], rejected: Vec<Hunk>) -> Vec<RejectedHunk> {
+    let same_lines = |a: &Hunk, b: &Hunk| {
+        a.changes.len() == b.changes.len()
+            && a.changes
+                .iter()
+                .zip(b.changes.iter())
+                .all(|(x, y)| x.tag == y.tag && x.line == y.line)
+    };
+    let mut next = 0;
+    rejected
+        .into_iter()
+        .map(|hunk| {
+            let index = patch_hunks[next.min(patch_hunks.len())..]
+                .iter()
+                .position(|h| same_lines(h, &hunk))
+                .map(|i| next + i);
+            if let Some(index) = index {
+                next = index + 1;
+            }
+            RejectedHunk { index, hunk }
+        })
+        .collect()
+}
+
+/// Why [`verify_patch`] failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyError {
+    /// The hunk at this index (and possibly later ones) could not be applied
+    Rejected { hunk: usize },
+    /// The patch applied, but the result first differs from the expected
+    /// file at this 0-based line, which falls in the given hunk if any
+    Mismatch { line: usize, hunk: Option<usize> },
+    /// libxdiff failed to apply the patch
+    Failed,
+}
+
+impl Display for VerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::Rejected { hunk } => write!(f, "hunk {} could not be applied", hunk),
+            VerifyError::Mismatch {
+                line,
+                hunk: Some(hunk),
+            } => write!(f, "output differs at line {} in hunk {}", line + 1, hunk),
+            VerifyError::Mismatch { line, hunk: None } => {
+                write!(f, "output differs at line {} outside any hunk", line + 1)
+            }
+            VerifyError::Failed => f.write_str("patch could not be applied"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for VerifyError {}
+
+/// Check that applying `patch` to `base` produces exactly `expected`,
+/// reporting the first hunk where it doesn't.
+pub fn verify_patch(
+    base: &mut MMFile,
+    patch: &mut Patch,
+    expected: &MMFile,
+) -> Result<(), VerifyError> {
+    let patched = match base.apply_patch_hunks(patch) {
+        Ok(patched) => patched,
+        Err(PatchError::Rejected { rejected, .. }) => {
+            return Err(match rejected.iter().find_map(|r| r.index) {
+                Some(hunk) => VerifyError::Rejected { hunk },
+                None => VerifyError::Failed,
+            })
+        }
+        Err(PatchError::Failed(_)) => return Err(VerifyError::Failed),
+    };
+    let Some(line) = first_difference(patched.as_slice(), expected.as_slice()) else {
+        return Ok(());
+    };
+    let hunks = parse_hunks(patch.as_slice()).map_err(|_| VerifyError::Failed)?;
+    let hunk = hunks.iter().position(|h| {
+        let lines = h.new_lines();
+        lines.contains(&line) || (lines.is_empty() && lines.start == line)
+    });
+    Err(VerifyError::Mismatch { line, hunk })
+}
+
+/// 0-based index of the first line that differs between `a` and `b`
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    let mut a_lines = a.split_inclusive(|b| *b == b'\n');
+    let mut b_lines = b.split_inclusive(|b| *b == b'\n');
+    let mut index = 0;
+    loop {
+        match (a_lines.next(), b_lines.next()) {
+            (None, None) => return None,
+            (Some(x), Some(y)) if x == y => index += 1,
+            _ => return Some(index),
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{hunk::parse_hunks, ChangeTag, Error, Hunk, MMFile, Patch, PatchError};
+
+/// A patch parsed and checked once, to be applied to many files. Each
+/// target is patched directly when every hunk matches exactly at the lines
+/// its header names, which takes a single pass over the file; only targets
+/// where a hunk has moved or doesn't match fall back to libxdiff.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreparedPatch {
+    patch: Patch,
+    hunks: Vec<Hunk>,
+    /// Whether hunks are in order and don't overlap, so they can be applied
+    /// in one pass
+    ordered: bool,
+}
+
+impl PreparedPatch {
+    /// Parse `patch` for repeated application. Returns `Err` if the patch
+    /// text is malformed.
+    pub fn new(patch: Patch) -> Result<PreparedPatch, Error> {
+        let hunks = parse_hunks(patch.as_slice())?;
+        let ordered = hunks
+            .windows(2)
+            .all(|pair| pair[0].old_lines().end <= pair[1].old_lines().start);
+        Ok(PreparedPatch {
+            patch,
+            hunks,
+            ordered,
+        })
+    }
+
+    /// The patch being applied
+    pub fn patch(&self) -> &Patch {
+        &self.patch
+    }
+
+    /// Apply the patch to `target`. Results are returned as for
+    /// [`MMFile::apply_patch`].
+    #[allow(clippy::result_large_err)]
+    pub fn apply(&self, target: &mut MMFile) -> Result<MMFile, PatchError> {
+        if let Some(patched) = self.apply_exact(target.as_slice()) {
+            return Ok(MMFile::from_bytes(&patched));
+        }
+        target.apply_patch(&mut self.patch.clone())
+    }
+
+    /// Apply every hunk at exactly the lines its header names, or return
+    /// `None` if any of them doesn't match there
+    fn apply_exact(&self, target: &[u8]) -> Option<Vec<u8>> {
+        if !self.ordered {
+            return None;
+        }
+        let lines: Vec<&[u8]> = target.split_inclusive(|b| *b == b'\n').collect();
+        let mut out = Vec::with_capacity(target.len());
+        let mut next = 0;
+        for hunk in self.hunks.iter() {
+            let range = hunk.old_lines();
+            let old_side = hunk.changes.iter().filter(|c| c.tag != ChangeTag::Insert);
+            if range.end > lines.len()
+                || !old_side
+                    .map(|c| &c.line[..])
+                    .eq(lines[range.clone()].iter().copied())
+            {
+                return None;
+            }
+            lines[next..range.start]
+                .iter()
+                .for_each(|l| out.extend_from_slice(l));
+            hunk.changes
+                .iter()
+                .filter(|c| c.tag != ChangeTag::Delete)
+                .for_each(|c| out.extend_from_slice(&c.line));
+            next = range.end;
+        }
+        lines[next..].iter().for_each(|l| out.extend_from_slice(l));
+        Some(out)
+    }
+}
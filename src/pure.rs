@@ -0,0 +1,308 @@
+//! A pure-Rust line diff engine implementing the core diff, patch and merge
+//! operations on byte slices with Myers' algorithm, for comparing against
+//! libxdiff or for code paths that must not call into C. Output uses the same
+//! [`Hunk`] types and unified diff format as the libxdiff-backed APIs.
+//! Disabling the default `ffi` feature leaves libxdiff out of the build
+//! entirely.
+
+use core::ops::Range;
+
+use crate::{
+    hunk::{locate_hunks, parse_hunks, write_hunk},
+    Change, ChangeTag, Error, Hunk, HunkHeader,
+};
+
+/// Compute the diff to turn `old` into `new` as structured hunks with
+/// `context_len` lines of context, including line numbers and byte offsets
+pub fn diff_hunks(old: &[u8], new: &[u8], context_len: usize) -> Vec<Hunk> {
+    let old_lines = split_lines(old);
+    let new_lines = split_lines(new);
+    let script = edit_script(&old_lines, &new_lines);
+
+    // position in each file before every step of the script
+    let mut positions = Vec::with_capacity(script.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for tag in script.iter() {
+        positions.push((o, n));
+        o += (*tag != ChangeTag::Insert) as usize;
+        n += (*tag != ChangeTag::Delete) as usize;
+    }
+    positions.push((o, n));
+
+    // first and last step of each group of changes close enough to share a hunk
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in script
+        .iter()
+        .enumerate()
+        .filter(|(_, tag)| **tag != ChangeTag::Equal)
+    {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last - 1 <= 2 * context_len => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    let mut hunks: Vec<Hunk> = groups
+        .into_iter()
+        .map(|(first, last)| {
+            let start = first.saturating_sub(context_len);
+            let end = (last + context_len + 1).min(script.len());
+            let (old_first, new_first) = positions[start];
+            let (old_end, new_end) = positions[end];
+            let (old_len, new_len) = (old_end - old_first, new_end - new_first);
+            let changes = script[start..end]
+                .iter()
+                .zip(positions[start..end].iter())
+                .map(|(&tag, &(o, n))| Change {
+                    tag,
+                    line: match tag {
+                        ChangeTag::Insert => new_lines[n].to_vec(),
+                        _ => old_lines[o].to_vec(),
+                    },
+                    old_index: (tag != ChangeTag::Insert).then_some(o),
+                    new_index: (tag != ChangeTag::Delete).then_some(n),
+                    old_offset: None,
+                    new_offset: None,
+                })
+                .collect();
+            Hunk {
+                // an empty side is numbered by the line it follows
+                header: HunkHeader {
+                    old_start: old_first + (old_len > 0) as usize,
+                    old_len,
+                    new_start: new_first + (new_len > 0) as usize,
+                    new_len,
+                },
+                changes,
+                old_bytes: None,
+                new_bytes: None,
+            }
+        })
+        .collect();
+    locate_hunks(&mut hunks, old, new);
+    hunks
+}
+
+/// Compute the unified diff to turn `old` into `new`, in the same format as
+/// [`MMFile::compute_patch`](crate::MMFile::compute_patch)
+pub fn diff(old: &[u8], new: &[u8], context_len: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    for hunk in diff_hunks(old, new, context_len).iter() {
+        write_hunk(&mut out, hunk);
+    }
+    out
+}
+
+/// Apply a unified diff to `base`. Hunks must apply exactly at the lines
+/// their headers name; returns `Err` describing the first hunk that doesn't.
+pub fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    let lines = split_lines(base);
+    let mut out = Vec::with_capacity(base.len());
+    let mut next = 0;
+    for hunk in parse_hunks(patch)? {
+        let range = hunk.old_lines();
+        if range.start < next || range.end > lines.len() {
+            return Err(Error::Invalid(format!(
+                "hunk {:?} does not fit the file",
+                String::from_utf8_lossy(&hunk.header.to_bytes())
+            )));
+        }
+        lines[next..range.start]
+            .iter()
+            .for_each(|l| out.extend_from_slice(l));
+        let old_side = hunk.changes.iter().filter(|c| c.tag != ChangeTag::Insert);
+        if !old_side
+            .map(|c| &c.line[..])
+            .eq(lines[range.clone()].iter().copied())
+        {
+            return Err(Error::Invalid(format!(
+                "hunk {:?} does not match the file",
+                String::from_utf8_lossy(&hunk.header.to_bytes())
+            )));
+        }
+        hunk.changes
+            .iter()
+            .filter(|c| c.tag != ChangeTag::Delete)
+            .for_each(|c| out.extend_from_slice(&c.line));
+        next = range.end;
+    }
+    lines[next..].iter().for_each(|l| out.extend_from_slice(l));
+    Ok(out)
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`. Where the changes
+/// overlap, `ours` wins, as in [`MMFile::merge3`](crate::MMFile::merge3).
+/// Returns the merged file and the lines of `base` that "their" rejected
+/// changes covered.
+pub fn merge3(base: &[u8], ours: &[u8], theirs: &[u8]) -> (Vec<u8>, Vec<Range<usize>>) {
+    let lines = split_lines(base);
+    let ours = edits(base, ours);
+    let theirs = edits(base, theirs);
+    let mut merged = Vec::with_capacity(base.len());
+    let mut conflicts = Vec::new();
+    let mut next = 0;
+    let (mut i, mut j) = (0, 0);
+    loop {
+        let edit = match (ours.get(i), theirs.get(j)) {
+            (None, None) => break,
+            (Some(a), Some(b)) if overlaps(&a.base, &b.base) => {
+                if a != b {
+                    conflicts.push(b.base.clone());
+                }
+                j += 1;
+                continue;
+            }
+            (Some(a), Some(b)) if b.base.start < a.base.start => {
+                j += 1;
+                b
+            }
+            (Some(a), _) => {
+                i += 1;
+                a
+            }
+            (None, Some(b)) => {
+                j += 1;
+                b
+            }
+        };
+        lines[next..edit.base.start]
+            .iter()
+            .for_each(|l| merged.extend_from_slice(l));
+        edit.lines.iter().for_each(|l| merged.extend_from_slice(l));
+        next = edit.base.end;
+    }
+    lines[next..]
+        .iter()
+        .for_each(|l| merged.extend_from_slice(l));
+    (merged, conflicts)
+}
+
+/// One change to a base file: the lines it replaces and their replacement
+#[derive(PartialEq)]
+struct Edit {
+    base: Range<usize>,
+    lines: Vec<Vec<u8>>,
+}
+
+fn edits(base: &[u8], changed: &[u8]) -> Vec<Edit> {
+    diff_hunks(base, changed, 0)
+        .into_iter()
+        .map(|hunk| Edit {
+            base: hunk.old_lines(),
+            lines: hunk
+                .changes
+                .into_iter()
+                .filter(|c| c.tag == ChangeTag::Insert)
+                .map(|c| c.line)
+                .collect(),
+        })
+        .collect()
+}
+
+/// Whether two edits touch the same lines, or insert at the same place
+fn overlaps(a: &Range<usize>, b: &Range<usize>) -> bool {
+    a.start == b.start || (a.start < b.end && b.start < a.end)
+}
+
+fn split_lines(bytes: &[u8]) -> Vec<&[u8]> {
+    bytes.split_inclusive(|b| *b == b'\n').collect()
+}
+
+/// The shortest sequence of operations turning `a` into `b`, found with
+/// Myers' O(ND) algorithm after stripping common leading and trailing lines.
+/// Only the diagonals each round reaches are kept, so memory grows with the
+/// square of the number of differences rather than with the file length.
+/// Within each run of changes, deletions come before insertions, as libxdiff
+/// emits them.
+fn edit_script(a: &[&[u8]], b: &[&[u8]]) -> Vec<ChangeTag> {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[prefix..a.len() - suffix], &b[prefix..b.len() - suffix]);
+
+    let mut script = vec![ChangeTag::Equal; prefix];
+    script.extend(myers(a_mid, b_mid));
+    script.resize(script.len() + suffix, ChangeTag::Equal);
+
+    // move deletions ahead of insertions within each run of changes
+    let mut start = 0;
+    while start < script.len() {
+        if script[start] == ChangeTag::Equal {
+            start += 1;
+            continue;
+        }
+        let end = script[start..]
+            .iter()
+            .position(|tag| *tag == ChangeTag::Equal)
+            .map_or(script.len(), |i| start + i);
+        script[start..end].sort_by_key(|tag| *tag == ChangeTag::Insert);
+        start = end;
+    }
+    script
+}
+
+fn myers(a: &[&[u8]], b: &[&[u8]]) -> Vec<ChangeTag> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // the furthest x reached on diagonals -d..=d after every round d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    'search: for d in 0..=max as isize {
+        for k in (-d..=d).step_by(2) {
+            let i = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+                break 'search;
+            }
+        }
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+    }
+
+    // walk back through the saved rounds to recover the path
+    let mut ops = Vec::with_capacity(max);
+    let (mut x, mut y) = (n, m);
+    for d in (1..trace.len() as isize).rev() {
+        let prev = &trace[d as usize - 1];
+        let reached = |k: isize| prev[(k + d - 1) as usize];
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && reached(k - 1) < reached(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = reached(prev_k);
+        let prev_y = prev_x - prev_k;
+        while x > prev_x && y > prev_y {
+            ops.push(ChangeTag::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        ops.push(if x == prev_x {
+            ChangeTag::Insert
+        } else {
+            ChangeTag::Delete
+        });
+        x = prev_x;
+        y = prev_y;
+    }
+    // round 0 only follows the main diagonal
+    ops.resize(ops.len() + x as usize, ChangeTag::Equal);
+    ops.reverse();
+    ops
+}
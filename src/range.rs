@@ -0,0 +1,67 @@
+use core::ops::Range;
+
+#[cfg(feature = "std")]
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::{
+    lines::{byte_range, line_starts},
+    MMFile,
+};
+#[cfg(feature = "std")]
+use crate::{DiffOptions, Error, HunkHeader, HunkNumbering};
+
+impl MMFile {
+    /// Copy lines `lines` (0-based, half-open) of this file into a new
+    /// `MMFile`. Lines past the end of the file are ignored. Returns the new
+    /// file along with the index of its first line in this one.
+    pub fn slice_lines(&self, lines: Range<usize>) -> (MMFile, usize) {
+        let data = self.as_slice();
+        let starts = line_starts(data);
+        let first = lines.start.min(starts.len() - 1);
+        (MMFile::from_bytes(&data[byte_range(&starts, lines)]), first)
+    }
+
+    #[cfg(feature = "std")]
+    /// Compute the diff to turn lines `range` of self into lines `other_range`
+    /// of other (both 0-based, half-open), returning diff through a callback
+    /// one line at a time. Hunk headers are numbered relative to the ranges or
+    /// to the whole files according to [`DiffOptions::numbering`]. Returns
+    /// `Err` if callback panics.
+    pub fn diff_range_raw<CB>(
+        &self,
+        range: Range<usize>,
+        other: &MMFile,
+        other_range: Range<usize>,
+        opts: &DiffOptions,
+        mut callback: CB,
+    ) -> Result<(), Error>
+    where
+        CB: FnMut(&[u8]),
+    {
+        let (old, old_first) = self.slice_lines(range);
+        let (new, new_first) = other.slice_lines(other_range);
+        let (old_offset, new_offset) = match opts.numbering {
+            HunkNumbering::Relative => (0, 0),
+            HunkNumbering::Absolute => (old_first, new_first),
+        };
+        // SAFETY: the callback below catches any panic from the user callback
+        unsafe {
+            old.diff_records(&new, opts, |record| {
+                // After a panic the user callback is never called again, so
+                // broken invariants in its closed-over variables aren't witnessed.
+                let result = catch_unwind(AssertUnwindSafe(|| match record {
+                    [header] => match HunkHeader::parse(header) {
+                        Some(h) => callback(&h.offset(old_offset, new_offset).to_bytes()),
+                        None => callback(header),
+                    },
+                    _ => record.iter().for_each(|buf| callback(buf)),
+                }));
+                if result.is_ok() {
+                    0
+                } else {
+                    -1
+                }
+            })
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use crate::{
+    hunk::{parse_hunks, write_hunk},
+    Change, ChangeTag, DiffOptions, Error, Hunk, HunkHeader, MMFile, Patch, PatchError,
+};
+
+impl Patch {
+    /// Rewrite this patch with at most `context_len` lines of context around
+    /// each change, splitting hunks whose changes end up further apart than
+    /// twice that. Context can only be removed this way; use
+    /// [`recontext`](Self::recontext) to add more.
+    pub fn reduce_context(&self, context_len: usize) -> Result<Patch, Error> {
+        let hunks = parse_hunks(self.as_slice())?;
+        let mut out = preamble(self.as_slice()).to_vec();
+        for hunk in hunks.iter() {
+            for part in split_hunk(hunk, context_len) {
+                write_hunk(&mut out, &part);
+            }
+        }
+        Patch::from_bytes(&out)
+    }
+
+    /// Rewrite this patch with exactly `context_len` lines of context by
+    /// applying it to `base` and diffing again. Returns `Err` if the patch
+    /// doesn't apply cleanly to `base`.
+    pub fn recontext(&self, base: &mut MMFile, context_len: usize) -> Result<Patch, Error> {
+        let mut patch = self.clone();
+        let target = match base.apply_patch(&mut patch) {
+            Ok(target) => target,
+            Err(PatchError::Rejected { .. }) => {
+                return Err(Error::Invalid(
+                    "patch does not apply cleanly to the base file".to_owned(),
+                ))
+            }
+            Err(PatchError::Failed(e)) => return Err(e),
+        };
+        let opts = DiffOptions::new().context_len(context_len);
+        let recomputed = base.compute_patch_with(&target, &opts)?;
+        if recomputed.is_empty() {
+            return Ok(recomputed);
+        }
+        let mut out = preamble(self.as_slice()).to_vec();
+        out.extend_from_slice(recomputed.as_slice());
+        Patch::from_bytes(&out)
+    }
+
+    /// Create a patch from the text of a unified diff whose hunks were edited
+    /// by hand or dropped, recomputing every hunk header's line counts and
+    /// the new file's line numbers from the lines that follow it. Each hunk
+    /// keeps the old file position its header names. An empty line inside a
+    /// hunk is read as an empty context line, as editors often strip the
+    /// trailing space.
+    pub fn recount(text: &[u8]) -> Result<Patch, Error> {
+        let mut hunks: Vec<Hunk> = Vec::new();
+        for line in text[preamble(text).len()..].split_inclusive(|b| *b == b'\n') {
+            if line.starts_with(b"@@") {
+                let header = HunkHeader::parse(line).ok_or_else(|| {
+                    Error::Invalid(format!(
+                        "malformed hunk header: {:?}",
+                        String::from_utf8_lossy(line)
+                    ))
+                })?;
+                hunks.push(Hunk {
+                    header,
+                    changes: Vec::new(),
+                    old_bytes: None,
+                    new_bytes: None,
+                });
+                continue;
+            }
+            // lines before the first header are in the preamble, so a hunk exists
+            let Some(hunk) = hunks.last_mut() else {
+                continue;
+            };
+            if line.starts_with(b"\\") {
+                if let Some(change) = hunk.changes.last_mut() {
+                    if change.line.ends_with(b"\n") {
+                        change.line.pop();
+                    }
+                }
+                continue;
+            }
+            let (tag, content) = match line.first().copied().and_then(ChangeTag::from_prefix) {
+                Some(tag) => (tag, &line[1..]),
+                None if line == b"\n" || line == b"\r\n" => (ChangeTag::Equal, line),
+                None => {
+                    return Err(Error::Invalid(format!(
+                        "unexpected line in hunk: {:?}",
+                        String::from_utf8_lossy(line)
+                    )))
+                }
+            };
+            hunk.changes.push(Change {
+                tag,
+                line: content.to_owned(),
+                old_index: None,
+                new_index: None,
+                old_offset: None,
+                new_offset: None,
+            });
+        }
+        let mut out = preamble(text).to_vec();
+        write_recounted(&mut out, &mut hunks);
+        Patch::from_bytes(&out)
+    }
+
+    /// Create a patch from structured hunks, such as a filtered subset of
+    /// [`MMFile::diff_hunks`], recomputing the headers the same way as
+    /// [`recount`](Self::recount)
+    pub fn from_hunks(hunks: &[Hunk]) -> Result<Patch, Error> {
+        let mut out = Vec::new();
+        write_recounted(&mut out, &mut hunks.to_vec());
+        Patch::from_bytes(&out)
+    }
+
+    /// Rewrite this patch in a deterministic form suitable for hashing or
+    /// content addressing: any file headers and other text before the first
+    /// hunk are dropped, hunks are sorted by their position in the old file,
+    /// and headers are recomputed and written in full with `\n` endings.
+    /// Lines of the file keep their own terminators, since those are content.
+    pub fn canonicalize(&self) -> Result<Patch, Error> {
+        let mut hunks = parse_hunks(self.as_slice())?;
+        hunks.retain(|h| h.changes.iter().any(|c| c.tag != ChangeTag::Equal));
+        hunks.sort_by_key(|h| h.old_lines().start);
+        let mut out = Vec::new();
+        write_recounted(&mut out, &mut hunks);
+        Patch::from_bytes(&out)
+    }
+}
+
+/// Fix up the header of each hunk to match its lines, keeping its position
+/// in the old file, and append the hunks to `out`
+fn write_recounted(out: &mut Vec<u8>, hunks: &mut [Hunk]) {
+    let mut delta = 0;
+    for hunk in hunks.iter_mut() {
+        let old_first = hunk.old_lines().start;
+        let old_len = count(&hunk.changes, ChangeTag::Insert);
+        let new_len = count(&hunk.changes, ChangeTag::Delete);
+        let new_first = (old_first as isize + delta) as usize;
+        hunk.header = HunkHeader {
+            old_start: old_first + (old_len > 0) as usize,
+            old_len,
+            new_start: new_first + (new_len > 0) as usize,
+            new_len,
+        };
+        delta += new_len as isize - old_len as isize;
+        write_hunk(out, hunk);
+    }
+}
+
+/// The part of a patch before its first hunk header, such as `---`/`+++`
+/// file headers
+pub(crate) fn preamble(patch: &[u8]) -> &[u8] {
+    let len = patch
+        .split_inclusive(|b| *b == b'\n')
+        .take_while(|line| !line.starts_with(b"@@"))
+        .map(<[u8]>::len)
+        .sum();
+    &patch[..len]
+}
+
+/// Split a hunk into hunks with at most `context_len` lines of context
+/// around each run of changes. Hunks with no changes are dropped.
+fn split_hunk(hunk: &Hunk, context_len: usize) -> Vec<Hunk> {
+    let changes = &hunk.changes;
+    // first and last index of each group of changes close enough to share a hunk
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in changes
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.tag != ChangeTag::Equal)
+    {
+        match groups.last_mut() {
+            Some((_, last)) if i - *last - 1 <= 2 * context_len => *last = i,
+            _ => groups.push((i, i)),
+        }
+    }
+    let old_lines = |changes: &[Change]| count(changes, ChangeTag::Insert);
+    let new_lines = |changes: &[Change]| count(changes, ChangeTag::Delete);
+    groups
+        .into_iter()
+        .map(|(first, last)| {
+            let start = first.saturating_sub(context_len);
+            let end = (last + context_len + 1).min(changes.len());
+            let part = &changes[start..end];
+            let old_first = hunk.old_lines().start + old_lines(&changes[..start]);
+            let new_first = hunk.new_lines().start + new_lines(&changes[..start]);
+            let (old_len, new_len) = (old_lines(part), new_lines(part));
+            Hunk {
+                // an empty side is numbered by the line it follows
+                header: HunkHeader {
+                    old_start: old_first + (old_len > 0) as usize,
+                    old_len,
+                    new_start: new_first + (new_len > 0) as usize,
+                    new_len,
+                },
+                changes: part.to_vec(),
+                old_bytes: None,
+                new_bytes: None,
+            }
+        })
+        .collect()
+}
+
+/// Number of changes that aren't tagged `excluded`
+fn count(changes: &[Change], excluded: ChangeTag) -> usize {
+    changes.iter().filter(|c| c.tag != excluded).count()
+}
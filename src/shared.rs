@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use crate::{DiffOptions, Error, Hunk, MMFile, Patch};
+
+/// An immutable file that is cheap to clone and can be diffed from several
+/// threads at once. Like any file, each diff gives libxdiff its own copy of
+/// the file's descriptor, so concurrent diffs don't share iteration state.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SharedMMFile {
+    // always marked as shared, so cloning it only bumps the share count
+    file: MMFile,
+}
+
+// SAFETY: `MMFile` is only `!Sync` because of its raw `mmfile_t`. Through
+// `&SharedMMFile` the descriptor is only copied, and the buffer it points to
+// is never mutated while the file is shared: mutation goes through `&mut`,
+// which copies the buffer first since the share count is set at creation.
+// The share count and content hash caches are `OnceLock`s, which are `Sync`.
+unsafe impl Sync for SharedMMFile {}
+
+// fails to compile unless every field of `MMFile` besides the raw `mmfile_t`
+// is `Sync` on its own
+#[allow(dead_code)]
+fn assert_fields_sync(file: &MMFile) {
+    fn is_sync<T: Sync>(_: &T) {}
+    let MMFile {
+        inner: _,
+        shares,
+        hash,
+    } = file;
+    is_sync(shares);
+    is_sync(hash);
+}
+
+impl SharedMMFile {
+    /// Freeze `file` so it can be shared between threads
+    pub fn new(file: MMFile) -> SharedMMFile {
+        file.shares.get_or_init(|| Arc::new(()));
+        SharedMMFile { file }
+    }
+
+    /// Get a view of the file's data as a slice
+    pub fn as_slice(&self) -> &[u8] {
+        self.file.as_slice()
+    }
+
+    /// Hash of the file's contents, as [`MMFile::content_hash`] computes it
+    pub fn content_hash(&self) -> u64 {
+        self.file.content_hash()
+    }
+
+    /// A file sharing this one's buffer, for passing to any [`MMFile`]
+    /// method. The buffer is only copied if that file is mutated.
+    pub fn to_mmfile(&self) -> MMFile {
+        self.file.clone()
+    }
+
+    /// Compute the patch to turn self into other using the given options
+    pub fn compute_patch_with(
+        &self,
+        other: &SharedMMFile,
+        opts: &DiffOptions,
+    ) -> Result<Patch, Error> {
+        self.to_mmfile()
+            .compute_patch_with(&other.to_mmfile(), opts)
+    }
+
+    /// Compute the diff to turn self into other as a list of structured
+    /// hunks, as [`MMFile::diff_hunks`] does
+    pub fn diff_hunks(&self, other: &SharedMMFile, opts: &DiffOptions) -> Result<Vec<Hunk>, Error> {
+        self.to_mmfile().diff_hunks(&other.to_mmfile(), opts)
+    }
+}
+
+impl From<MMFile> for SharedMMFile {
+    fn from(file: MMFile) -> Self {
+        SharedMMFile::new(file)
+    }
+}
@@ -0,0 +1,331 @@
+//! rsync-style differential sync: a [`Signature`] summarizes a file as block
+//! checksums, a delta against a new file can be computed from the signature
+//! alone, and the delta is applied to the original file as a libxdiff
+//! [`BinaryPatch`].
+//!
+//! A sync is built from [`signature`], [`delta`] and [`apply_delta`]: the
+//! side holding the old file sends its signature with
+//! [`Signature::to_bytes`], the side holding the new file replies with the
+//! delta's bytes, and the first side applies it.
+
+use std::collections::HashMap;
+
+use libxdiff_sys::{XDL_BDOP_CPY, XDL_BDOP_INS, XDL_BDOP_INSB};
+
+use crate::{
+    binary::BPATCH_HEADER_LEN,
+    hash::{hash_bytes, HASH_KIND},
+    BinaryPatch, Error, MMFile,
+};
+
+/// First line of every serialized signature
+const MAGIC: &[u8] = b"libxdiff-signature 1\n";
+
+/// Compute the signature of `file` using blocks of `block_size` bytes. See
+/// [`MMFile::signature`].
+pub fn signature(file: &mut MMFile, block_size: usize) -> Result<Signature, Error> {
+    file.signature(block_size)
+}
+
+/// Compute the delta turning the file `signature` was taken of into
+/// `new_file`. See [`Signature::delta`].
+pub fn delta(signature: &Signature, new_file: &MMFile) -> Result<BinaryPatch, Error> {
+    signature.delta(new_file)
+}
+
+/// Apply a delta to the file its signature was taken of, producing the new
+/// file
+pub fn apply_delta(old_file: &mut MMFile, delta: &BinaryPatch) -> Result<MMFile, Error> {
+    old_file.apply_binary_patch(&mut delta.clone())
+}
+
+/// Checksums of one block of the source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockChecksum {
+    /// rsync's rolling checksum, used to find candidate matches
+    pub weak: u32,
+    /// Hash of the block's contents, used to confirm candidates
+    pub strong: u64,
+}
+
+/// Block checksums of a file, sufficient to compute a delta against it
+/// without access to its contents
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    block_size: usize,
+    source_len: usize,
+    /// libxdiff's binary patch header for the source file
+    header: [u8; BPATCH_HEADER_LEN],
+    blocks: Vec<BlockChecksum>,
+}
+
+impl Signature {
+    /// Size of each block; the last block may be shorter
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Size of the file the signature was generated from
+    pub fn source_len(&self) -> usize {
+        self.source_len
+    }
+
+    /// Checksums of each block of the source file
+    pub fn blocks(&self) -> &[BlockChecksum] {
+        &self.blocks
+    }
+
+    /// Compute a delta turning the signed file into `target`. The delta is a
+    /// [`BinaryPatch`] that copies matching blocks from the original file and
+    /// inserts everything else literally.
+    ///
+    /// Blocks are matched by a 64-bit hash, so, as with rsync, a hash
+    /// collision could produce an incorrect result.
+    pub fn delta(&self, target: &MMFile) -> Result<BinaryPatch, Error> {
+        let target = target.as_slice();
+        let bs = self.block_size;
+        let mut by_weak: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (i, block) in self.blocks.iter().enumerate() {
+            by_weak.entry(block.weak).or_default().push(i);
+        }
+        let find = |window: &[u8], weak: u32| -> Option<usize> {
+            let candidates = by_weak.get(&weak)?;
+            let strong = hash_bytes(window);
+            candidates
+                .iter()
+                .copied()
+                .find(|&i| self.blocks[i].strong == strong && self.block_len(i) == window.len())
+        };
+
+        let mut delta = DeltaWriter::new(&self.header);
+        let mut literal_start = 0;
+        let mut pos = 0;
+        let mut rolling = (target.len() >= bs).then(|| Rolling::new(&target[..bs]));
+        while let Some(sum) = rolling.as_mut() {
+            if let Some(block) = find(&target[pos..pos + bs], sum.value()) {
+                delta.insert(&target[literal_start..pos]);
+                delta.copy(block * bs, bs);
+                pos += bs;
+                literal_start = pos;
+                rolling = (target.len() - pos >= bs).then(|| Rolling::new(&target[pos..pos + bs]));
+            } else if pos + bs < target.len() {
+                sum.roll(target[pos], target[pos + bs], bs);
+                pos += 1;
+            } else {
+                break;
+            }
+        }
+        // the source's final block may be shorter than the rest
+        if let Some(last) = self.blocks.len().checked_sub(1) {
+            let len = self.block_len(last);
+            if len < bs && target.len() - literal_start >= len {
+                let tail = &target[target.len() - len..];
+                if find(tail, weak_checksum(tail)) == Some(last) {
+                    delta.insert(&target[literal_start..target.len() - len]);
+                    delta.copy(last * bs, len);
+                    literal_start = target.len();
+                }
+            }
+        }
+        delta.insert(&target[literal_start..]);
+        BinaryPatch::from_bytes(&delta.finish())
+    }
+
+    /// Serialize the signature: the line `libxdiff-signature 1`, then a byte
+    /// naming the strong hash, the block size and source length as
+    /// little-endian `u64`s, libxdiff's source header, and each block's weak
+    /// checksum as a `u32` and strong hash as a `u64`, both little-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(HASH_KIND);
+        out.extend((self.block_size as u64).to_le_bytes());
+        out.extend((self.source_len as u64).to_le_bytes());
+        out.extend_from_slice(&self.header);
+        for block in self.blocks.iter() {
+            out.extend(block.weak.to_le_bytes());
+            out.extend(block.strong.to_le_bytes());
+        }
+        out
+    }
+
+    /// Deserialize a signature. Returns `Err` if the data is malformed, or
+    /// was written by a build using a different strong hash, which depends
+    /// on the `xxhash` feature.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Signature, Error> {
+        let rest = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| Error::Invalid("not a signature".to_owned()))?;
+        let (&kind, rest) = rest
+            .split_first()
+            .ok_or_else(|| Error::Invalid("signature is truncated".to_owned()))?;
+        if kind != HASH_KIND {
+            return Err(Error::Invalid(
+                "signature uses a different strong hash".to_owned(),
+            ));
+        }
+        let fixed = 16 + BPATCH_HEADER_LEN;
+        if rest.len() < fixed {
+            return Err(Error::Invalid("signature is truncated".to_owned()));
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(rest[at..at + 8].try_into().unwrap());
+        let block_size = u64_at(0) as usize;
+        let source_len = u64_at(8) as usize;
+        let header = rest[16..fixed].try_into().unwrap();
+        let blocks: Vec<BlockChecksum> = rest[fixed..]
+            .chunks(12)
+            .map(|chunk| match chunk.len() {
+                12 => Ok(BlockChecksum {
+                    weak: u32::from_le_bytes(chunk[..4].try_into().unwrap()),
+                    strong: u64::from_le_bytes(chunk[4..].try_into().unwrap()),
+                }),
+                _ => Err(Error::Invalid("signature is truncated".to_owned())),
+            })
+            .collect::<Result<_, _>>()?;
+        if block_size == 0 || blocks.len() != source_len.div_ceil(block_size) {
+            return Err(Error::Invalid(
+                "signature block count does not match its length".to_owned(),
+            ));
+        }
+        Ok(Signature {
+            block_size,
+            source_len,
+            header,
+            blocks,
+        })
+    }
+
+    fn block_len(&self, index: usize) -> usize {
+        (self.source_len - index * self.block_size).min(self.block_size)
+    }
+}
+
+impl MMFile {
+    /// Compute the rsync-style signature of this file using blocks of
+    /// `block_size` bytes
+    pub fn signature(&mut self, block_size: usize) -> Result<Signature, Error> {
+        if block_size == 0 {
+            return Err(Error::Invalid(
+                "signature block size must be positive".to_owned(),
+            ));
+        }
+        // every binary patch against this file starts with the same header,
+        // so take it from libxdiff rather than recomputing its fingerprint
+        let patch = self.bdiff(&MMFile::new())?;
+        let header: [u8; BPATCH_HEADER_LEN] = patch
+            .as_slice()
+            .get(..BPATCH_HEADER_LEN)
+            .and_then(|h| h.try_into().ok())
+            .ok_or_else(|| Error::Other("bdiff produced a truncated header".to_owned()))?;
+        let blocks = self
+            .as_slice()
+            .chunks(block_size)
+            .map(|block| BlockChecksum {
+                weak: weak_checksum(block),
+                strong: hash_bytes(block),
+            })
+            .collect();
+        Ok(Signature {
+            block_size,
+            source_len: self.as_slice().len(),
+            header,
+            blocks,
+        })
+    }
+}
+
+/// rsync's rolling checksum of a whole window
+fn weak_checksum(window: &[u8]) -> u32 {
+    Rolling::new(window).value()
+}
+
+struct Rolling {
+    a: u32,
+    b: u32,
+}
+
+impl Rolling {
+    fn new(window: &[u8]) -> Self {
+        let len = window.len() as u32;
+        let mut a = 0u32;
+        let mut b = 0u32;
+        for (i, byte) in window.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        Rolling {
+            a: a & 0xffff,
+            b: b & 0xffff,
+        }
+    }
+
+    /// Slide the window one byte forward
+    fn roll(&mut self, out: u8, incoming: u8, len: usize) {
+        self.a = self
+            .a
+            .wrapping_sub(out as u32)
+            .wrapping_add(incoming as u32)
+            & 0xffff;
+        self.b = self
+            .b
+            .wrapping_sub((len as u32).wrapping_mul(out as u32))
+            .wrapping_add(self.a)
+            & 0xffff;
+    }
+
+    fn value(&self) -> u32 {
+        self.a | (self.b << 16)
+    }
+}
+
+/// Encodes libxdiff binary patch operations, merging adjacent copies
+struct DeltaWriter {
+    out: Vec<u8>,
+    pending_copy: Option<(usize, usize)>,
+}
+
+impl DeltaWriter {
+    fn new(header: &[u8]) -> Self {
+        DeltaWriter {
+            out: header.to_vec(),
+            pending_copy: None,
+        }
+    }
+
+    fn copy(&mut self, offset: usize, len: usize) {
+        match self.pending_copy.as_mut() {
+            Some((start, size)) if *start + *size == offset => *size += len,
+            _ => {
+                self.flush_copy();
+                self.pending_copy = Some((offset, len));
+            }
+        }
+    }
+
+    fn insert(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        self.flush_copy();
+        if bytes.len() <= u8::MAX as usize {
+            self.out.push(XDL_BDOP_INS as u8);
+            self.out.push(bytes.len() as u8);
+        } else {
+            self.out.push(XDL_BDOP_INSB as u8);
+            self.out.extend((bytes.len() as u32).to_le_bytes());
+        }
+        self.out.extend_from_slice(bytes);
+    }
+
+    fn flush_copy(&mut self) {
+        if let Some((offset, len)) = self.pending_copy.take() {
+            self.out.push(XDL_BDOP_CPY as u8);
+            self.out.extend((offset as u32).to_le_bytes());
+            self.out.extend((len as u32).to_le_bytes());
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.flush_copy();
+        self.out
+    }
+}
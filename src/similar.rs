@@ -0,0 +1,127 @@
+use similar::{group_diff_ops, DiffOp};
+
+use crate::{hunk::HunkBuilder, Change, ChangeTag, Hunk, HunkHeader};
+
+impl From<similar::ChangeTag> for ChangeTag {
+    fn from(tag: similar::ChangeTag) -> Self {
+        match tag {
+            similar::ChangeTag::Equal => ChangeTag::Equal,
+            similar::ChangeTag::Delete => ChangeTag::Delete,
+            similar::ChangeTag::Insert => ChangeTag::Insert,
+        }
+    }
+}
+
+impl From<ChangeTag> for similar::ChangeTag {
+    fn from(tag: ChangeTag) -> Self {
+        match tag {
+            ChangeTag::Equal => similar::ChangeTag::Equal,
+            ChangeTag::Delete => similar::ChangeTag::Delete,
+            ChangeTag::Insert => similar::ChangeTag::Insert,
+        }
+    }
+}
+
+impl<T: AsRef<[u8]> + Clone> From<similar::Change<T>> for Change {
+    fn from(change: similar::Change<T>) -> Self {
+        Change {
+            tag: change.tag().into(),
+            line: change.value_ref().as_ref().to_vec(),
+            old_index: change.old_index(),
+            new_index: change.new_index(),
+            old_offset: None,
+            new_offset: None,
+        }
+    }
+}
+
+impl Hunk {
+    /// Describe this hunk as `similar` diff operations over lines. A run of
+    /// deletions directly followed by insertions becomes a `Replace`.
+    pub fn to_diff_ops(&self) -> Vec<DiffOp> {
+        let mut ops = Vec::new();
+        let mut old_index = self.old_lines().start;
+        let mut new_index = self.new_lines().start;
+        let mut changes = self.changes.iter().peekable();
+        while let Some(first) = changes.next() {
+            let mut len = 1;
+            while changes.next_if(|c| c.tag == first.tag).is_some() {
+                len += 1;
+            }
+            let op = match first.tag {
+                ChangeTag::Equal => DiffOp::Equal {
+                    old_index,
+                    new_index,
+                    len,
+                },
+                ChangeTag::Insert => DiffOp::Insert {
+                    old_index,
+                    new_index,
+                    new_len: len,
+                },
+                ChangeTag::Delete => {
+                    let mut new_len = 0;
+                    while changes.next_if(|c| c.tag == ChangeTag::Insert).is_some() {
+                        new_len += 1;
+                    }
+                    if new_len > 0 {
+                        DiffOp::Replace {
+                            old_index,
+                            old_len: len,
+                            new_index,
+                            new_len,
+                        }
+                    } else {
+                        DiffOp::Delete {
+                            old_index,
+                            old_len: len,
+                            new_index,
+                        }
+                    }
+                }
+            };
+            old_index = op.old_range().end;
+            new_index = op.new_range().end;
+            ops.push(op);
+        }
+        ops
+    }
+}
+
+/// Group line-level `similar` diff operations computed over the lines of
+/// `old` and `new` (for example from `TextDiff::from_lines(..).ops()`) into
+/// hunks with `context_len` lines of context, as libxdiff would emit them.
+pub fn hunks_from_diff_ops(
+    ops: &[DiffOp],
+    old: &[u8],
+    new: &[u8],
+    context_len: usize,
+) -> Vec<Hunk> {
+    let old_lines: Vec<&[u8]> = old.split_inclusive(|b| *b == b'\n').collect();
+    let new_lines: Vec<&[u8]> = new.split_inclusive(|b| *b == b'\n').collect();
+    let mut builder = HunkBuilder::default();
+    for group in group_diff_ops(ops.to_vec(), context_len) {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else {
+            continue;
+        };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+        let start = |range: &core::ops::Range<usize>| match range.len() {
+            0 => range.start,
+            _ => range.start + 1,
+        };
+        builder.header(HunkHeader {
+            old_start: start(&old_range),
+            old_len: old_range.len(),
+            new_start: start(&new_range),
+            new_len: new_range.len(),
+        });
+        for op in &group {
+            for change in op.iter_changes(&old_lines, &new_lines) {
+                builder.line(change.tag().into(), change.value());
+            }
+        }
+    }
+    builder.locate(old, new);
+    builder.hunks
+}
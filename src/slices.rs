@@ -0,0 +1,93 @@
+//! Entry points working on byte slices, for callers that don't otherwise
+//! need [`MMFile`]s
+
+use core::fmt::{self, Display, Formatter};
+
+use crate::{ConflictRegion, Error, MMFile, MergeOptions, Patch, PatchError, RejectedHunk};
+
+/// Why [`apply`] failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApplyError {
+    /// The patch is not a valid unified diff
+    InvalidPatch(Error),
+    /// Some hunks could not be applied. `patched` is the result of applying
+    /// the others.
+    Rejected {
+        patched: Vec<u8>,
+        hunks: Vec<RejectedHunk>,
+    },
+    /// libxdiff failed, for example by running out of memory
+    Failed(Error),
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ApplyError::InvalidPatch(msg) => write!(f, "invalid patch: {}", msg),
+            ApplyError::Rejected { hunks, .. } => {
+                match (hunks.len(), hunks.iter().find_map(|h| h.index)) {
+                    (0, _) => f.write_str("patch could not be applied"),
+                    (count, Some(first)) => write!(
+                        f,
+                        "{} hunk(s) could not be applied, starting with hunk {}",
+                        count, first
+                    ),
+                    (count, None) => write!(f, "{} hunk(s) could not be applied", count),
+                }
+            }
+            ApplyError::Failed(e) => write!(f, "patch could not be applied: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ApplyError {}
+
+/// Apply the unified diff `patch` to `base`, returning the patched bytes
+pub fn apply(base: &[u8], patch: &[u8]) -> Result<Vec<u8>, ApplyError> {
+    let mut patch = Patch::from_bytes(patch).map_err(ApplyError::InvalidPatch)?;
+    match MMFile::from_bytes(base).apply_patch_hunks(&mut patch) {
+        Ok(patched) => Ok(patched.as_slice().to_vec()),
+        Err(PatchError::Rejected { patched, rejected }) => Err(ApplyError::Rejected {
+            patched: patched.as_slice().to_vec(),
+            hunks: rejected,
+        }),
+        Err(PatchError::Failed(e)) => Err(ApplyError::Failed(e)),
+    }
+}
+
+/// Result of [`merge`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeOutput {
+    /// The merged bytes. Where the two sides conflict, "our" side is kept.
+    pub merged: Vec<u8>,
+    /// Every region where "their" changes could not be applied
+    pub conflicts: Vec<ConflictRegion>,
+}
+
+impl MergeOutput {
+    /// Whether the merge completed without any conflicts
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merge the changes `ours` and `theirs` made to `base`, as
+/// [`MMFile::merge3_with`] does
+pub fn merge(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    opts: &MergeOptions,
+) -> Result<MergeOutput, Error> {
+    let outcome = MMFile::merge3_with(
+        &mut MMFile::from_bytes(base),
+        &mut MMFile::from_bytes(ours),
+        &mut MMFile::from_bytes(theirs),
+        opts,
+    )?;
+    Ok(MergeOutput {
+        merged: outcome.merged.as_slice().to_vec(),
+        conflicts: outcome.conflicts,
+    })
+}
@@ -0,0 +1,181 @@
+use core::{ffi::c_int, ops::Range};
+
+use crate::{cost::normalize_empty, hunk::NO_NEWLINE, Error, HunkHeader, LineChange};
+
+/// Slide each change that only inserts or only deletes lines, where the
+/// lines around it make its position ambiguous, to where its edges best
+/// follow the indentation of the surrounding lines, in the spirit of git's
+/// indent heuristic. Positions that score the same keep libxdiff's choice.
+pub(crate) fn slide_changes(a: &[u8], b: &[u8], changes: &mut [LineChange]) {
+    let a_lines: Vec<&[u8]> = a.split_inclusive(|b| *b == b'\n').collect();
+    let b_lines: Vec<&[u8]> = b.split_inclusive(|b| *b == b'\n').collect();
+    for i in 0..changes.len() {
+        let change = &changes[i];
+        let insert = change.old.is_empty() && !change.new.is_empty();
+        let delete = change.new.is_empty() && !change.old.is_empty();
+        if !insert && !delete {
+            continue;
+        }
+        // the side holding the lines, and the empty position on the other
+        let sides = |c: &LineChange| {
+            if insert {
+                (c.new.clone(), c.old.clone())
+            } else {
+                (c.old.clone(), c.new.clone())
+            }
+        };
+        let (lines, other_len) = if insert {
+            (&b_lines, a_lines.len())
+        } else {
+            (&a_lines, b_lines.len())
+        };
+        let (group, at) = sides(change);
+        let (min_group, min_at) = match i.checked_sub(1).map(|p| sides(&changes[p])) {
+            Some((g, o)) => (g.end, o.end),
+            None => (0, 0),
+        };
+        let (max_group, max_at) = match changes.get(i + 1).map(sides) {
+            Some((g, o)) => (g.start, o.start),
+            None => (lines.len(), other_len),
+        };
+
+        let mut up = 0;
+        while group.start - up > min_group
+            && at.start - up > min_at
+            && lines[group.start - up - 1] == lines[group.end - up - 1]
+        {
+            up += 1;
+        }
+        let mut down = 0;
+        while group.end + down < max_group
+            && at.start + down < max_at
+            && lines[group.start + down] == lines[group.end + down]
+        {
+            down += 1;
+        }
+        if up == 0 && down == 0 {
+            continue;
+        }
+
+        let score = |shift: isize| {
+            let start = (group.start as isize + shift) as usize;
+            split_penalty(lines, start) + split_penalty(lines, start + group.len())
+        };
+        let mut best = 0;
+        let mut best_score = score(0);
+        for shift in -(up as isize)..=down as isize {
+            let s = score(shift);
+            if s < best_score {
+                best = shift;
+                best_score = s;
+            }
+        }
+        let moved = |r: Range<usize>| {
+            let start = (r.start as isize + best) as usize;
+            start..start + r.len()
+        };
+        let change = &mut changes[i];
+        change.old = moved(change.old.clone());
+        change.new = moved(change.new.clone());
+    }
+}
+
+/// How unnatural it is to start or end a change just before line `at`:
+/// the difference in indentation across the split, less a bonus for
+/// splitting next to a blank line. Splits at the edges of the file cost
+/// nothing.
+fn split_penalty(lines: &[&[u8]], at: usize) -> isize {
+    let before = lines[..at].iter().rev().find_map(|l| indent(l));
+    let after = lines[at..].iter().find_map(|l| indent(l));
+    let mut penalty = match (before, after) {
+        (Some(before), Some(after)) => before.abs_diff(after) as isize,
+        _ => 0,
+    };
+    let is_blank = |i: usize| lines.get(i).is_some_and(|l| indent(l).is_none());
+    if (at > 0 && is_blank(at - 1)) || is_blank(at) {
+        penalty -= 1;
+    }
+    penalty
+}
+
+/// Width of a line's leading whitespace, counting tabs to the next multiple
+/// of 8, or `None` if the line is blank
+fn indent(line: &[u8]) -> Option<usize> {
+    let mut width = 0;
+    for b in line {
+        match b {
+            b' ' => width += 1,
+            b'\t' => width += 8 - width % 8,
+            b'\n' | b'\r' => return None,
+            _ => return Some(width),
+        }
+    }
+    None
+}
+
+/// Emit hunks for context-free `changes` between `a` and `b`, with
+/// `context_len` lines of context, in the same record format as libxdiff
+pub(crate) fn change_records<CB>(
+    a: &[u8],
+    b: &[u8],
+    changes: &[LineChange],
+    context_len: usize,
+    mut callback: CB,
+) -> Result<(), Error>
+where
+    CB: FnMut(&[&[u8]]) -> c_int,
+{
+    let a_lines: Vec<&[u8]> = a.split_inclusive(|b| *b == b'\n').collect();
+    let b_lines: Vec<&[u8]> = b.split_inclusive(|b| *b == b'\n').collect();
+    let mut emit = |record: &[&[u8]]| match callback(record) {
+        0 => Ok(()),
+        code => Err(Error::Failed { op: "emit", code }),
+    };
+
+    let mut rest = changes;
+    while let Some(first) = rest.first() {
+        // changes close enough to share their context go in one hunk
+        let mut len = 1;
+        while len < rest.len() && rest[len].old.start - rest[len - 1].old.end <= 2 * context_len {
+            len += 1;
+        }
+        let (group, next) = rest.split_at(len);
+        rest = next;
+        let last = &group[len - 1];
+        let lead = first.old.start.min(context_len);
+        let trail = (a_lines.len() - last.old.end).min(context_len);
+        let old_first = first.old.start - lead;
+        let new_first = first.new.start - lead;
+        let header = HunkHeader {
+            old_start: old_first + 1,
+            old_len: last.old.end + trail - old_first,
+            new_start: new_first + 1,
+            new_len: last.new.end + trail - new_first,
+        };
+        emit(&[&normalize_empty(header).to_bytes()])?;
+
+        let mut next_line = old_first;
+        for change in group {
+            emit_lines(&mut emit, b" ", &a_lines[next_line..change.old.start])?;
+            emit_lines(&mut emit, b"-", &a_lines[change.old.clone()])?;
+            emit_lines(&mut emit, b"+", &b_lines[change.new.clone()])?;
+            next_line = change.old.end;
+        }
+        emit_lines(&mut emit, b" ", &a_lines[next_line..last.old.end + trail])?;
+    }
+    Ok(())
+}
+
+fn emit_lines<E>(emit: &mut E, prefix: &[u8], lines: &[&[u8]]) -> Result<(), Error>
+where
+    E: FnMut(&[&[u8]]) -> Result<(), Error>,
+{
+    for line in lines {
+        if line.ends_with(b"\n") {
+            emit(&[prefix, line])?;
+        } else {
+            emit(&[prefix, line, NO_NEWLINE])?;
+        }
+    }
+    Ok(())
+}
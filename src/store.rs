@@ -0,0 +1,133 @@
+//! A versioned document store: the first version of a document is kept as a
+//! snapshot and each later version as a patch against the one before it,
+//! with a fresh snapshot taken every few versions so reconstructing any
+//! version never replays more than a bounded number of patches.
+
+use crate::{Error, MMFile, Patch, PatchError};
+
+/// Default number of versions between snapshots
+const DEFAULT_SNAPSHOT_INTERVAL: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Snapshot(MMFile),
+    Delta(Patch),
+}
+
+/// The versions of a single document, stored as a chain of patches
+#[derive(Debug, Clone, PartialEq)]
+pub struct Store {
+    entries: Vec<Entry>,
+    latest: MMFile,
+    snapshot_interval: usize,
+    // versions stored as patches since the last snapshot
+    since_snapshot: usize,
+}
+
+impl Store {
+    /// Create a store whose version 0 is `initial`
+    pub fn new(initial: MMFile) -> Self {
+        Store {
+            entries: vec![Entry::Snapshot(initial.clone())],
+            latest: initial,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+            since_snapshot: 0,
+        }
+    }
+
+    /// Store a full snapshot instead of a patch after this many consecutive
+    /// patches. Smaller intervals make old versions faster to reconstruct
+    /// at the cost of space. An interval of 0 stores every version as a
+    /// snapshot.
+    pub fn with_snapshot_interval(mut self, versions: usize) -> Self {
+        self.snapshot_interval = versions;
+        self
+    }
+
+    /// Number of versions stored
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Always false, since a store holds at least its initial version
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The most recent version
+    pub fn latest(&self) -> &MMFile {
+        &self.latest
+    }
+
+    /// Number of versions stored as full snapshots
+    pub fn snapshot_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| matches!(e, Entry::Snapshot(_)))
+            .count()
+    }
+
+    /// Add a new version, diffing it against the latest one. Returns the
+    /// number of the new version.
+    pub fn push(&mut self, version: MMFile) -> Result<usize, Error> {
+        if self.since_snapshot >= self.snapshot_interval {
+            self.entries.push(Entry::Snapshot(version.clone()));
+            self.since_snapshot = 0;
+        } else {
+            let patch = self.latest.compute_patch(&version)?;
+            self.entries.push(Entry::Delta(patch));
+            self.since_snapshot += 1;
+        }
+        self.latest = version;
+        Ok(self.entries.len() - 1)
+    }
+
+    /// Store the latest version as a snapshot, so the next pushed version
+    /// starts a new patch chain
+    pub fn snapshot(&mut self) {
+        if let Some(entry) = self.entries.last_mut() {
+            *entry = Entry::Snapshot(self.latest.clone());
+        }
+        self.since_snapshot = 0;
+    }
+
+    /// Reconstruct a version by applying patches to the nearest snapshot
+    /// before it. Returns `Err` if the version doesn't exist or a stored
+    /// patch fails to apply.
+    pub fn get(&mut self, version: usize) -> Result<MMFile, Error> {
+        if version >= self.entries.len() {
+            return Err(Error::Invalid(format!(
+                "version {} does not exist; the store has {} versions",
+                version,
+                self.entries.len()
+            )));
+        }
+        if version + 1 == self.entries.len() {
+            return Ok(self.latest.clone());
+        }
+        let (base, mut file) = self.entries[..=version]
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, e)| match e {
+                Entry::Snapshot(file) => Some((i, file.clone())),
+                Entry::Delta(_) => None,
+            })
+            .ok_or_else(|| Error::Other("store has no snapshot".to_owned()))?;
+        for (i, entry) in self.entries[base + 1..=version].iter_mut().enumerate() {
+            if let Entry::Delta(patch) = entry {
+                file = match file.apply_patch(patch) {
+                    Ok(file) => file,
+                    Err(PatchError::Rejected { .. }) => {
+                        return Err(Error::Other(format!(
+                            "patch for version {} failed to apply",
+                            base + 1 + i
+                        )))
+                    }
+                    Err(PatchError::Failed(e)) => return Err(e),
+                };
+            }
+        }
+        Ok(file)
+    }
+}
@@ -0,0 +1,207 @@
+use std::io::{BufRead, BufReader, Read};
+
+use crate::{ChangeTag, Error, HunkHeader, MMBlocks, MMFile};
+
+/// Apply a unified diff read from `reader` to `base`, parsing and applying
+/// one line at a time so the patch is never held in memory. Lines before
+/// the first hunk header are ignored.
+///
+/// Unlike [`MMFile::apply_patch`], hunks must apply exactly at the lines
+/// their headers name; returns `Err` describing the first line that doesn't
+/// match, a malformed hunk, or a read failure.
+pub fn apply_patch_from_reader<R: Read>(base: &MMFile, reader: R) -> Result<MMFile, Error> {
+    let mut applier = StreamApplier::new(base.as_slice());
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|e| Error::io("patch", e))?;
+        if read == 0 {
+            break;
+        }
+        applier.line(&line)?;
+    }
+    applier.finish()
+}
+
+struct StreamApplier<'a> {
+    base: &'a [u8],
+    // byte offset and index of the next unconsumed base line
+    base_pos: usize,
+    base_next: usize,
+    output: MMBlocks,
+    header: Option<HunkHeader>,
+    // lines of the current hunk still expected on each side
+    old_left: usize,
+    new_left: usize,
+    // net change in line count from all hunks before the current one
+    delta: isize,
+    // the previous hunk line, held back in case a missing-newline marker follows
+    pending: Option<(ChangeTag, Vec<u8>)>,
+}
+
+impl<'a> StreamApplier<'a> {
+    fn new(base: &'a [u8]) -> Self {
+        StreamApplier {
+            base,
+            base_pos: 0,
+            base_next: 0,
+            output: MMBlocks::new(),
+            header: None,
+            old_left: 0,
+            new_left: 0,
+            delta: 0,
+            pending: None,
+        }
+    }
+
+    fn line(&mut self, line: &[u8]) -> Result<(), Error> {
+        if line.starts_with(b"@@") {
+            let header = HunkHeader::parse(line).ok_or_else(|| {
+                Error::Invalid(format!(
+                    "malformed hunk header: {:?}",
+                    String::from_utf8_lossy(line)
+                ))
+            })?;
+            return self.start_hunk(header);
+        }
+        if line.starts_with(b"\\") {
+            // "\ No newline at end of file" applies to the previous line
+            if let Some((_, prev)) = self.pending.as_mut() {
+                if prev.ends_with(b"\n") {
+                    prev.pop();
+                }
+            }
+            return Ok(());
+        }
+        let tag = line.first().copied().and_then(ChangeTag::from_prefix);
+        match (tag, self.header.is_some()) {
+            (_, false) => Ok(()),
+            (Some(tag), true) => {
+                self.flush()?;
+                match tag {
+                    ChangeTag::Insert => self.new_left = take(self.new_left, line)?,
+                    ChangeTag::Delete => self.old_left = take(self.old_left, line)?,
+                    ChangeTag::Equal => {
+                        self.old_left = take(self.old_left, line)?;
+                        self.new_left = take(self.new_left, line)?;
+                    }
+                }
+                self.pending = Some((tag, line[1..].to_owned()));
+                Ok(())
+            }
+            (None, true) => Err(Error::Invalid(format!(
+                "unexpected line in hunk: {:?}",
+                String::from_utf8_lossy(line)
+            ))),
+        }
+    }
+
+    fn start_hunk(&mut self, header: HunkHeader) -> Result<(), Error> {
+        self.end_hunk()?;
+        // an empty side may be numbered 0, so derive its position from the
+        // other side instead of trusting the header
+        let start = if header.old_len > 0 {
+            header.old_start.saturating_sub(1)
+        } else if header.new_len > 0 {
+            (header.new_start.saturating_sub(1) as isize - self.delta).max(0) as usize
+        } else {
+            header.old_start
+        };
+        if start < self.base_next {
+            return Err(Error::Invalid(format!(
+                "hunk at line {} overlaps the previous hunk",
+                start + 1
+            )));
+        }
+        while self.base_next < start {
+            let line = self.next_base_line().ok_or_else(|| {
+                Error::Invalid(format!(
+                    "hunk starts at line {} past end of file",
+                    start + 1
+                ))
+            })?;
+            self.write(line)?;
+        }
+        self.delta += header.new_len as isize - header.old_len as isize;
+        self.old_left = header.old_len;
+        self.new_left = header.new_len;
+        self.header = Some(header);
+        Ok(())
+    }
+
+    /// Apply the held-back hunk line
+    fn flush(&mut self) -> Result<(), Error> {
+        let Some((tag, line)) = self.pending.take() else {
+            return Ok(());
+        };
+        if tag != ChangeTag::Insert {
+            let line_number = self.base_next + 1;
+            let base_line = self.next_base_line().unwrap_or_default();
+            if base_line != line.as_slice() {
+                return Err(Error::Invalid(format!(
+                    "patch does not match line {}: expected {:?}, found {:?}",
+                    line_number,
+                    String::from_utf8_lossy(&line),
+                    String::from_utf8_lossy(base_line)
+                )));
+            }
+        }
+        if tag != ChangeTag::Delete {
+            self.write(&line)?;
+        }
+        Ok(())
+    }
+
+    fn end_hunk(&mut self) -> Result<(), Error> {
+        self.flush()?;
+        if self.old_left > 0 || self.new_left > 0 {
+            let header = self.header.map(|h| h.to_bytes()).unwrap_or_default();
+            return Err(Error::Invalid(format!(
+                "hunk line counts do not match header: {:?}",
+                String::from_utf8_lossy(&header)
+            )));
+        }
+        Ok(())
+    }
+
+    fn next_base_line(&mut self) -> Option<&'a [u8]> {
+        let rest = &self.base[self.base_pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        let len = rest
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        self.base_pos += len;
+        self.base_next += 1;
+        Some(&rest[..len])
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<(), Error> {
+        match self.output.write_buf(buf) {
+            0 => Ok(()),
+            _ => Err(Error::Other("failed to write patched output".to_owned())),
+        }
+    }
+
+    fn finish(mut self) -> Result<MMFile, Error> {
+        self.end_hunk()?;
+        let rest = &self.base[self.base_pos..];
+        self.write(rest)?;
+        Ok(self.output.to_mmfile())
+    }
+}
+
+/// Count one hunk line against the number remaining for its side
+fn take(left: usize, line: &[u8]) -> Result<usize, Error> {
+    left.checked_sub(1).ok_or_else(|| {
+        Error::Invalid(format!(
+            "hunk has more lines than its header: {:?}",
+            String::from_utf8_lossy(line)
+        ))
+    })
+}
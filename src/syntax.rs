@@ -0,0 +1,91 @@
+//! Hook for syntax-aware diffing. An external lexer, such as one built on
+//! tree-sitter, splits each file into the records to compare and says which
+//! records are equivalent, so differences in formatting, comments or other
+//! irrelevant detail don't show up as changes.
+
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+use crate::{
+    tokenize::{byte_range, interned_file},
+    DiffOptions, Error, TokenChange,
+};
+
+/// One record found by a [`Lexer`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lexeme {
+    /// Where the record is in the file
+    pub range: Range<usize>,
+    /// The record's equivalence class. Records with the same class compare
+    /// equal whatever their bytes; records with no class compare by bytes.
+    pub class: Option<u64>,
+}
+
+impl Lexeme {
+    /// A record compared by its bytes
+    pub fn new(range: Range<usize>) -> Self {
+        Lexeme { range, class: None }
+    }
+
+    /// A record equal to every other record of `class`
+    pub fn with_class(range: Range<usize>, class: u64) -> Self {
+        Lexeme {
+            range,
+            class: Some(class),
+        }
+    }
+}
+
+/// Supplies the records a syntax-aware diff compares
+pub trait Lexer {
+    /// The records of `bytes`, in order and not overlapping. Bytes outside
+    /// every record, such as comments, are ignored when comparing.
+    fn lex(&self, bytes: &[u8]) -> Vec<Lexeme>;
+}
+
+impl<F: Fn(&[u8]) -> Vec<Lexeme>> Lexer for F {
+    fn lex(&self, bytes: &[u8]) -> Vec<Lexeme> {
+        self(bytes)
+    }
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Key<'a> {
+    Bytes(&'a [u8]),
+    Class(u64),
+}
+
+fn key<'a>(bytes: &'a [u8], lexeme: &Lexeme) -> Key<'a> {
+    match lexeme.class {
+        Some(class) => Key::Class(class),
+        None => Key::Bytes(&bytes[lexeme.range.clone()]),
+    }
+}
+
+/// Compute the records changed between `old` and `new`, as found by
+/// `lexer`, with libxdiff. Context settings in `opts` are ignored.
+pub fn diff_syntax<L: Lexer + ?Sized>(
+    old: &[u8],
+    new: &[u8],
+    lexer: &L,
+    opts: &DiffOptions,
+) -> Result<Vec<TokenChange>, Error> {
+    let old_records = lexer.lex(old);
+    let new_records = lexer.lex(new);
+    let mut ids = BTreeMap::new();
+    let old_file = interned_file(old_records.iter().map(|l| key(old, l)), &mut ids);
+    let new_file = interned_file(new_records.iter().map(|l| key(new, l)), &mut ids);
+    let changes = old_file.changed_lines(&new_file, opts)?;
+
+    let old_ranges: Vec<Range<usize>> = old_records.into_iter().map(|l| l.range).collect();
+    let new_ranges: Vec<Range<usize>> = new_records.into_iter().map(|l| l.range).collect();
+    Ok(changes
+        .into_iter()
+        .map(|c| TokenChange {
+            old_bytes: byte_range(&old_ranges, &c.old, old.len()),
+            new_bytes: byte_range(&new_ranges, &c.new, new.len()),
+            old: c.old,
+            new: c.new,
+        })
+        .collect())
+}
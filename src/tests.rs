@@ -1,4 +1,12 @@
-use crate::{MMBlocks, MMFile};
+use crate::{
+    diff_syntax, diff_tokens, parse_conflict_markers, verify_patch, version, ApplyError,
+    ApplyOptions, BDiffAlgorithm, BDiffOptions, BinaryPatch, BufferKind, ChangeTag, CharPatch,
+    CharPatchOptions, CompactionPolicy, ConflictMarkers, ConflictRegion, CostFallback, DiffEvent,
+    DiffOptions, Error, ErrorKind, Hunk, HunkHeader, HunkNumbering, IncrementalDiff, Lexeme,
+    LineChange, LineEnding, MMBlocks, MMFile, MMFileBuilder, MemoryUsage, MergeDriver,
+    MergeOptions, MergeOrigin, MergeRegion, NonUtf8, Patch, PatchBundle, PatchError, PatchStatus,
+    PreparedPatch, Resolution, Separated, SharedMMFile, VerifyError, Words, XFlags,
+};
 
 #[test]
 fn new_empty() {
@@ -42,13 +50,89 @@ fn clone_blocks() {
     assert!(f.eq(&mut f2));
 }
 
+#[test]
+fn blocks_compaction_policy() {
+    let mut f = MMBlocks::new().with_compaction_policy(CompactionPolicy::MaxBlocks(2));
+    for _ in 0..10 {
+        assert_eq!(f.write_buf(b"line\n"), 0);
+        assert!(f.block_count() <= 2);
+    }
+    assert_eq!(f.to_mmfile().as_slice(), b"line\n".repeat(10).as_slice());
+
+    let mut f = MMBlocks::new();
+    f.set_compaction_policy(CompactionPolicy::MaxFragmentation(0.5));
+    for _ in 0..10 {
+        assert_eq!(f.write_buf(b"line\n"), 0);
+    }
+    let (_, largest) = f.block_stats();
+    assert!(largest * 2 >= f.size());
+
+    let mut f = MMBlocks::new();
+    for _ in 0..10 {
+        f.write_buf(b"line\n");
+    }
+    assert!(!f.is_compact());
+    assert!(f.block_count() > 1);
+    f.to_compact();
+    assert_eq!(f.block_count(), 1);
+    assert_eq!(MMBlocks::new().block_count(), 0);
+}
+
+#[test]
+fn blocks_from_chunks() {
+    let mut f = MMBlocks::from_chunks(&[b"hello ", b"", b"world\n"]);
+    assert_eq!(f.size(), 12);
+    assert_eq!(f.to_mmfile().as_slice(), b"hello world\n");
+
+    let frames = vec![b"a\n".to_vec(), b"b\n".to_vec()];
+    let mut f: MMBlocks = frames.into_iter().collect();
+    let mut expected = MMBlocks::from_bytes(b"a\nb\n");
+    assert!(f.eq(&mut expected));
+
+    let f: MMBlocks = "x\ny\n".split_inclusive('\n').map(str::as_bytes).collect();
+    assert_eq!(f.to_mmfile().as_slice(), b"x\ny\n");
+}
+
+#[test]
+fn blocks_extend() {
+    let mut f = MMBlocks::from_bytes(b"start\n");
+    f.extend([b"one\n".as_slice(), b"two\n".as_slice()]);
+    f.extend(vec![b"three\n".to_vec()]);
+    assert_eq!(f.to_mmfile().as_slice(), b"start\none\ntwo\nthree\n");
+}
+
+#[test]
+fn content_hash() {
+    let f = MMFile::from_bytes(b"hello world\n");
+    let f2 = MMFile::from_bytes(b"hello world\n");
+    let f3 = MMFile::from_bytes(b"hello world!\n");
+    assert_eq!(f.content_hash(), f2.content_hash());
+    assert_ne!(f.content_hash(), f3.content_hash());
+
+    let blocks = MMBlocks::from_chunks(&[b"hello ", b"world\n"]);
+    assert_eq!(blocks.content_hash(), f.content_hash());
+    assert_eq!(MMBlocks::new().content_hash(), MMFile::new().content_hash());
+}
+
 #[test]
 fn eq() {
     let mut data = Vec::new();
     data.extend((0..240).cycle().take(15_000));
     let f = MMFile::from_bytes(data.as_slice());
     let mut f2 = MMFile::from_bytes(data.as_slice());
-    assert!(f.eq(&mut f2));
+    assert!(f.eq(&f2));
+
+    // the cached hash is dropped when a file changes
+    let hash = f2.content_hash();
+    f2.as_slice_mut()[0] = b'x';
+    assert_ne!(f2.content_hash(), hash);
+    assert!(!f.eq(&f2));
+
+    // a clone keeps the hash of the contents it shares
+    let clone = f2.clone();
+    f2.as_slice_mut()[0] = data[0];
+    assert!(f.eq(&f2));
+    assert_ne!(clone.content_hash(), f2.content_hash());
 }
 
 #[test]
@@ -77,12 +161,12 @@ fn as_slice_mut() {
 #[test]
 fn diff_simple() {
     let data = b"hello world\n";
-    let mut f = MMFile::from_bytes(data);
+    let f = MMFile::from_bytes(data);
     let data2 = b"hello world!\n";
-    let mut f2 = MMFile::from_bytes(data2);
+    let f2 = MMFile::from_bytes(data2);
 
     let mut lines = Vec::<Vec<u8>>::new();
-    f.diff_raw(&mut f2, |line: &[u8]| {
+    f.diff_raw(&f2, |line: &[u8]| {
         lines.push(line.to_owned());
     })
     .unwrap();
@@ -106,20 +190,20 @@ fn diff_simple() {
 #[test]
 fn diff_panic() {
     let data = b"hello world\n";
-    let mut f = MMFile::from_bytes(data);
+    let f = MMFile::from_bytes(data);
     let data2 = b"hello world!\n";
-    let mut f2 = MMFile::from_bytes(data2);
+    let f2 = MMFile::from_bytes(data2);
 
     let mut lines = Vec::<Vec<u8>>::new();
-    let diff_result = f.diff_raw(&mut f2, |line: &[u8]| {
+    let diff_result = f.diff_raw(&f2, |line: &[u8]| {
         if lines.len() > 1 {
             panic!("too many lines!");
         }
         lines.push(line.to_owned());
     });
     assert_eq!(
-        diff_result,
-        Result::Err("diff failed with err: -1".to_owned())
+        diff_result.map_err(|e| e.to_string()),
+        Result::Err("diff callback panicked on line 0 of hunk 0: too many lines!".to_owned())
     );
 }
 
@@ -127,12 +211,12 @@ fn diff_panic() {
 fn diff_with_mutation() {
     // do the simple diff first
     let data = b"hello world\n";
-    let mut f = MMFile::from_bytes(data);
+    let f = MMFile::from_bytes(data);
     let data2 = b"hello world!\n";
     let mut f2 = MMFile::from_bytes(data2);
 
     let mut lines = Vec::<Vec<u8>>::new();
-    f.diff_raw(&mut f2, |line: &[u8]| {
+    f.diff_raw(&f2, |line: &[u8]| {
         lines.push(line.to_owned());
     })
     .unwrap();
@@ -154,7 +238,7 @@ fn diff_with_mutation() {
     // now change a letter and run the diff again
     f2.as_slice_mut()[0] = "j".as_bytes()[0];
     let mut lines = Vec::<Vec<u8>>::new();
-    f.diff_raw(&mut f2, |line: &[u8]| {
+    f.diff_raw(&f2, |line: &[u8]| {
         lines.push(line.to_owned());
     })
     .unwrap();
@@ -306,7 +390,7 @@ fn merge3_panic_emit() {
     );
 
     assert_eq!(
-        merge_result,
+        merge_result.map_err(|e| e.to_string()),
         Result::Err("merge failed with err: -1".to_owned())
     );
 }
@@ -338,7 +422,7 @@ fn merge3_panic_reject() {
     );
 
     assert_eq!(
-        merge_result,
+        merge_result.map_err(|e| e.to_string()),
         Result::Err("merge failed with err: -1".to_owned())
     );
 }
@@ -348,15 +432,15 @@ fn patch_simple() {
     let data = b"header\nline2\nline3\nline4\nhello world\n";
     let mut f = MMFile::from_bytes(data);
     let data2 = b"header\nline2\nline3\nline4\nhello world changed\n";
-    let mut f2 = MMFile::from_bytes(data2);
+    let f2 = MMFile::from_bytes(data2);
     let data3 = b"header\nline2\nline3\nline4\nhello world changed\n";
-    let mut f3 = MMFile::from_bytes(data3);
+    let f3 = MMFile::from_bytes(data3);
 
-    let mut patch = f.compute_patch(&mut f2).unwrap();
+    let mut patch = f.compute_patch(&f2).unwrap();
 
     let patch_result = f.apply_patch(&mut patch).unwrap();
 
-    assert!(patch_result.eq(&mut f3));
+    assert!(patch_result.eq(&f3));
 }
 
 #[test]
@@ -364,9 +448,9 @@ fn patch_reject() {
     let data = b"header\nline2\nline3\nline4\nhello world\n";
     let mut f = MMFile::from_bytes(data);
     let data2 = b"header changed\nline2\nline3\nline4\nhello world changed\n";
-    let mut f2 = MMFile::from_bytes(data2);
+    let f2 = MMFile::from_bytes(data2);
 
-    let mut patch = f.compute_patch(&mut f2).unwrap();
+    let mut patch = f.compute_patch(&f2).unwrap();
 
     eprintln!("patch: {:?}", patch.clone().to_mmfile());
     // modify base file to break patch assumptions
@@ -375,5 +459,2422 @@ fn patch_reject() {
     let patch_result = f.apply_patch(&mut patch);
 
     // when patch fails, original file is returned alongside failed patch segments
-    assert_eq!(patch_result, Err((f.clone(), patch.clone().to_mmfile())));
+    assert_eq!(
+        patch_result,
+        Err(PatchError::Rejected {
+            patched: f.clone(),
+            rejected: patch.clone().to_mmfile()
+        })
+    );
+}
+
+#[test]
+fn diff_range() {
+    let f = MMFile::from_bytes(b"a\nb\nc\nd\ne\n");
+    let f2 = MMFile::from_bytes(b"x\nb\nC\nd\ny\n");
+
+    let mut lines = Vec::<String>::new();
+    f.diff_range_raw(1..4, &f2, 1..4, &DiffOptions::new(), |line: &[u8]| {
+        lines.push(String::from_utf8_lossy(line).into_owned());
+    })
+    .unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "@@ -1,3 +1,3 @@\n",
+            " ",
+            "b\n",
+            "-",
+            "c\n",
+            "+",
+            "C\n",
+            " ",
+            "d\n"
+        ],
+    );
+
+    let opts = DiffOptions::new().numbering(HunkNumbering::Absolute);
+    let mut lines = Vec::<String>::new();
+    f.diff_range_raw(1..4, &f2, 1..4, &opts, |line: &[u8]| {
+        lines.push(String::from_utf8_lossy(line).into_owned());
+    })
+    .unwrap();
+    assert_eq!(lines[0], "@@ -2,3 +2,3 @@\n");
+    assert_eq!(lines.len(), 9);
+}
+
+#[test]
+fn diff_hunks_offsets() {
+    let f = MMFile::from_bytes(b"one\ntwo\nthree\n");
+    let f2 = MMFile::from_bytes(b"one\n2\nthree\n");
+
+    let hunks = f.diff_hunks(&f2, &DiffOptions::new()).unwrap();
+    assert_eq!(hunks.len(), 1);
+    let hunk = &hunks[0];
+    assert_eq!(
+        hunk.header,
+        HunkHeader {
+            old_start: 1,
+            old_len: 3,
+            new_start: 1,
+            new_len: 3
+        }
+    );
+    assert_eq!(hunk.old_bytes, Some(0..14));
+    assert_eq!(hunk.new_bytes, Some(0..12));
+
+    let summary: Vec<_> = hunk
+        .changes
+        .iter()
+        .map(|c| (c.tag, c.line.as_slice(), c.old_offset, c.new_offset))
+        .collect();
+    assert_eq!(
+        summary,
+        vec![
+            (ChangeTag::Equal, &b"one\n"[..], Some(0), Some(0)),
+            (ChangeTag::Delete, &b"two\n"[..], Some(4), None),
+            (ChangeTag::Insert, &b"2\n"[..], None, Some(4)),
+            (ChangeTag::Equal, &b"three\n"[..], Some(8), Some(6)),
+        ]
+    );
+}
+
+#[test]
+fn merge3_outcome_clean() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut f3 = MMFile::from_bytes(b"header_changed\nline2\nline3\nline4\nhello world\n");
+
+    let outcome = MMFile::merge3(&mut f, &mut f2, &mut f3).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(
+        outcome.merged.as_slice(),
+        b"header_changed\nline2\nline3\nline4\nhello world changed\n"
+    );
+}
+
+#[test]
+fn merge3_outcome_conflicts() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut f3 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world also changed\n");
+
+    let outcome = MMFile::merge3(&mut f, &mut f2, &mut f3).unwrap();
+    assert_eq!(outcome.merged, f2);
+    assert_eq!(
+        outcome.conflicts,
+        vec![ConflictRegion {
+            base: 4..5,
+            ours: 4..5,
+            theirs: 4..5,
+        }]
+    );
+}
+
+#[test]
+fn merge3_labeled_regions() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut f3 = MMFile::from_bytes(b"header_changed\nline2\nline3\nline4\nhello world\n");
+
+    let (outcome, regions) = MMFile::merge3_labeled(&mut f, &mut f2, &mut f3).unwrap();
+    assert!(outcome.is_clean());
+    let region = |origin, lines| MergeRegion { origin, lines };
+    assert_eq!(
+        regions,
+        vec![
+            region(MergeOrigin::Theirs, 0..1),
+            region(MergeOrigin::Base, 1..4),
+            region(MergeOrigin::Ours, 4..5),
+        ]
+    );
+
+    let mut f3 = f2.clone();
+    let (_, regions) = MMFile::merge3_labeled(&mut f, &mut f2, &mut f3).unwrap();
+    assert_eq!(regions.last().unwrap().origin, MergeOrigin::Both);
+}
+
+#[test]
+fn merge3_driver() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut f3 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world also changed\n");
+    let opts = MergeOptions::new();
+
+    let mut keep_both = |base: &[u8], ours: &[u8], theirs: &[u8]| {
+        assert_eq!(base, b"hello world\n");
+        Resolution::Replace([ours, theirs].concat())
+    };
+    let outcome =
+        MMFile::merge3_with_driver(&mut f, &mut f2, &mut f3, &opts, &mut keep_both).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(
+        outcome.merged.as_slice(),
+        b"header\nline2\nline3\nline4\nhello world changed\nhello world also changed\n"
+    );
+
+    let mut skip = |_: &[u8], _: &[u8], _: &[u8]| Resolution::Skip;
+    let outcome = MMFile::merge3_with_driver(&mut f, &mut f2, &mut f3, &opts, &mut skip).unwrap();
+    assert_eq!(outcome.merged, f2);
+    assert_eq!(outcome.conflicts.len(), 1);
+
+    struct Abort;
+    impl MergeDriver for Abort {
+        fn resolve(&mut self, _: &[u8], _: &[u8], _: &[u8]) -> Resolution {
+            Resolution::Abort
+        }
+    }
+    assert!(MMFile::merge3_with_driver(&mut f, &mut f2, &mut f3, &opts, &mut Abort).is_err());
+}
+
+#[test]
+fn merge3_recursive() {
+    let numbered = |lines: &[&str]| MMFile::from_bytes(lines.concat().as_bytes());
+    let lines: Vec<String> = (1..=12).map(|i| format!("{}\n", i)).collect();
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let mut bases = vec![
+        numbered(&lines),
+        numbered(&[&lines[..], &["13\n"]].concat()),
+    ];
+    let mut ours = numbered(&[&["one\n"], &lines[1..], &["13\n"]].concat());
+    let mut theirs = numbered(&[&lines[..11], &["twelve\n", "13\n"]].concat());
+
+    let opts = MergeOptions::new();
+    let outcome = MMFile::merge3_recursive(&mut bases, &mut ours, &mut theirs, &opts).unwrap();
+    assert!(outcome.is_clean());
+    let expected = numbered(&[&["one\n"], &lines[1..11], &["twelve\n", "13\n"]].concat());
+    assert_eq!(outcome.merged, expected);
+
+    assert!(MMFile::merge3_recursive(&mut [], &mut ours, &mut theirs, &opts).is_err());
+}
+
+#[test]
+fn merge2_marks_differences() {
+    let mut ours = MMFile::from_bytes(b"a\nb\nc\n");
+    let mut theirs = MMFile::from_bytes(b"a\nx\nc\n");
+    let outcome = MMFile::merge2(&mut ours, &mut theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(
+        outcome.merged.as_slice(),
+        b"a\n<<<<<<< ours\nb\n=======\nx\n>>>>>>> theirs\nc\n"
+    );
+    assert_eq!(
+        outcome.conflicts,
+        vec![ConflictRegion {
+            base: 1..1,
+            ours: 1..2,
+            theirs: 1..2,
+        }]
+    );
+    let marked = parse_conflict_markers(outcome.merged.as_slice()).unwrap();
+    assert_eq!(marked[0].ours, b"b\n");
+
+    let mut same = ours.clone();
+    let outcome = MMFile::merge2(&mut ours, &mut same, &MergeOptions::new()).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.merged, ours);
+
+    // a line only one side has is an insertion against the shared lines,
+    // so it merges without markers
+    let mut shorter = MMFile::from_bytes(b"a\nc\n");
+    let outcome = MMFile::merge2(&mut ours, &mut shorter, &MergeOptions::new()).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.merged.as_slice(), b"a\nb\nc\n");
+    let outcome = MMFile::merge2(&mut shorter, &mut ours, &MergeOptions::new()).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.merged.as_slice(), b"a\nb\nc\n");
+
+    let mut diff3 = ConflictMarkers::new("mine", "yours").base_label("base");
+    let Resolution::Replace(text) = diff3.resolve(b"o\n", b"m", b"y\n") else {
+        panic!("markers always replace");
+    };
+    assert_eq!(
+        text,
+        b"<<<<<<< mine\nm\n||||||| base\no\n=======\ny\n>>>>>>> yours\n"
+    );
+}
+
+#[test]
+fn merge_n_contributors() {
+    let lines: Vec<String> = (1..=12).map(|i| format!("{}\n", i)).collect();
+    let edit = |i: usize, line: &str| {
+        let mut edited = lines.clone();
+        edited[i] = line.to_owned();
+        MMFile::from_bytes(edited.concat().as_bytes())
+    };
+    let mut base = MMFile::from_bytes(lines.concat().as_bytes());
+    let mut others = vec![edit(0, "one\n"), edit(11, "twelve\n"), edit(0, "uno\n")];
+
+    let outcome = MMFile::merge_n(&mut base, &mut others, &MergeOptions::new()).unwrap();
+    let mut expected = lines.clone();
+    expected[0] = "one\n".to_owned();
+    expected[11] = "twelve\n".to_owned();
+    assert_eq!(outcome.merged.as_slice(), expected.concat().as_bytes());
+    assert_eq!(outcome.conflicts.len(), 1);
+    assert_eq!(outcome.conflicts[0].contributor, 2);
+    assert_eq!(outcome.conflicts[0].region.base, 0..1);
+
+    let outcome = MMFile::merge_n(&mut base, &mut [], &MergeOptions::new()).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.merged, base);
+}
+
+#[test]
+fn merge_bytes() {
+    let base = b"header\nline2\nline3\nline4\nhello world\n";
+    let ours = b"header\nline2\nline3\nline4\nhello world changed\n";
+    let theirs = b"header_changed\nline2\nline3\nline4\nhello world\n";
+    let output = crate::merge(base, ours, theirs, &MergeOptions::new()).unwrap();
+    assert!(output.is_clean());
+    assert_eq!(
+        output.merged,
+        b"header_changed\nline2\nline3\nline4\nhello world changed\n"
+    );
+
+    let theirs = b"header\nline2\nline3\nline4\nhello world also changed\n";
+    let output = crate::merge(base, ours, theirs, &MergeOptions::new()).unwrap();
+    assert_eq!(output.merged, ours);
+    assert_eq!(output.conflicts.len(), 1);
+}
+
+#[test]
+fn conflict_markers_roundtrip() {
+    let conflict = b"<<<<<<< ours\nhello world changed\n||||||| base\nhello world\n=======\nhello world also changed\n>>>>>>> theirs\n";
+    let mut data = b"header\nline2\n".to_vec();
+    data.extend_from_slice(conflict);
+    data.extend_from_slice(b"footer\n");
+
+    let conflicts = parse_conflict_markers(&data).unwrap();
+    assert_eq!(conflicts.len(), 1);
+    let c = &conflicts[0];
+    assert_eq!(c.ours_label, "ours");
+    assert_eq!(c.base_label.as_deref(), Some("base"));
+    assert_eq!(c.theirs_label, "theirs");
+    assert_eq!(c.ours, b"hello world changed\n");
+    assert_eq!(c.base.as_deref(), Some(&b"hello world\n"[..]));
+    assert_eq!(c.theirs, b"hello world also changed\n");
+    assert_eq!(c.lines, 2..9);
+    assert_eq!(&data[c.bytes.clone()], conflict);
+    assert_eq!(c.to_bytes(), conflict);
+
+    assert!(parse_conflict_markers(b"<<<<<<< ours\nline\n=======\n").is_err());
+}
+
+#[test]
+fn merge3_into_blocks() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut f3 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world also changed\n");
+
+    let mut output = MMBlocks::from_bytes(b"existing\n");
+    let mut rejected = MMBlocks::new();
+    MMFile::merge3_into(&mut f, &mut f2, &mut f3, &mut output, &mut rejected).unwrap();
+
+    assert_eq!(
+        output.to_mmfile().as_slice(),
+        b"existing\nheader\nline2\nline3\nline4\nhello world changed\n"
+    );
+    assert_eq!(
+        rejected.to_mmfile().as_slice(),
+        b"@@ -2,4 +2,4 @@\n line2\n line3\n line4\n-hello world\n+hello world also changed\n"
+    );
+
+    // output written by libxdiff follows the compaction policy
+    let mut output =
+        MMBlocks::from_bytes(b"existing\n").with_compaction_policy(CompactionPolicy::MaxBlocks(1));
+    MMFile::merge3_into(&mut f, &mut f2, &mut f3, &mut output, &mut MMBlocks::new()).unwrap();
+    assert_eq!(output.block_count(), 1);
+}
+
+#[test]
+fn patch_reject_hunks() {
+    let data = b"header\nline2\nline3\nline4\nline5\nline6\nline7\nline8\nline9\nhello world\n";
+    let mut f = MMFile::from_bytes(data);
+    let data2 = b"header changed\nline2\nline3\nline4\nline5\nline6\nline7\nline8\nline9\nhello world changed\n";
+    let f2 = MMFile::from_bytes(data2);
+
+    let mut patch = f.compute_patch(&f2).unwrap();
+    // modify the end of the base file so only the second hunk is rejected
+    let len = f.as_slice().len();
+    f.as_slice_mut()[len - 2] = b'D';
+
+    let Err(PatchError::Rejected { patched, rejected }) = f.apply_patch_hunks(&mut patch) else {
+        panic!("expected the second hunk to be rejected");
+    };
+    assert!(patched.as_slice().starts_with(b"header changed\n"));
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].index, Some(1));
+    assert_eq!(
+        rejected[0].hunk.changes.last().unwrap().line,
+        b"hello world changed\n"
+    );
+}
+
+#[test]
+fn apply_bytes() {
+    let base = b"header\nline2\nline3\nline4\nhello world\n";
+    let patch = b"@@ -4,2 +4,2 @@\n line4\n-hello world\n+hello world changed\n";
+    assert_eq!(
+        crate::apply(base, patch).unwrap(),
+        b"header\nline2\nline3\nline4\nhello world changed\n"
+    );
+
+    let Err(ApplyError::Rejected { patched, hunks }) = crate::apply(b"other\n", patch) else {
+        panic!("patch should be rejected");
+    };
+    assert_eq!(patched, b"other\n");
+    assert_eq!(hunks[0].index, Some(0));
+    assert!(matches!(
+        crate::apply(base, b"@@ -1,1 @@\n"),
+        Err(ApplyError::InvalidPatch(_))
+    ));
+}
+
+#[test]
+fn patch_validation() {
+    let f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(patch.hunk_count(), 1);
+    assert_eq!(patch.source_lines(), 4);
+    assert_eq!(patch.target_lines(), 4);
+
+    let parsed = Patch::from_bytes(patch.as_slice()).unwrap();
+    assert_eq!(parsed, patch);
+
+    assert!(Patch::from_bytes(b"not a patch\n").is_err());
+    // file headers before the first hunk are kept, not parsed as lines
+    let labeled = b"diff --git a/f b/f\n--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+b\n";
+    let labeled = Patch::from_bytes(labeled).unwrap();
+    assert_eq!(
+        (labeled.hunk_count(), labeled.hunks().unwrap().len()),
+        (1, 1)
+    );
+    // header claims more lines than the hunk contains
+    assert!(Patch::from_bytes(b"@@ -1,2 +1,2 @@\n-a\n+b\n").is_err());
+    // malformed headers are errors rather than panics, even after file headers
+    let zero_start = b"--- a/f\n+++ b/f\n@@ -0,1 +0,1 @@\n-a\n+b\n";
+    assert!(matches!(
+        Patch::from_bytes(zero_start),
+        Err(Error::Invalid(_))
+    ));
+}
+
+#[test]
+fn patch_header_at_line_zero() {
+    // a non-empty side starting at line 0 used to underflow
+    for patch in [
+        &b"@@ -0,1 +0,1 @@\n-a\n+b\n"[..],
+        b"@@ -0,1 +1,1 @@\n-a\n+b\n",
+        b"@@ -1,1 +0,1 @@\n-a\n+b\n",
+    ] {
+        assert!(matches!(Patch::from_bytes(patch), Err(Error::Invalid(_))));
+    }
+    // an empty side is numbered 0 when it is at the start of the file
+    assert!(Patch::from_bytes(b"@@ -0,0 +1,1 @@\n+a\n").is_ok());
+}
+
+#[test]
+fn fuzz_regressions() {
+    // crash inputs found by the fuzz targets, kept so they stay fixed
+    let text = b"@@ -0,1 +1,1 @@\n-a\n+c\n";
+    assert!(matches!(Patch::from_bytes(text), Err(Error::Invalid(_))));
+    #[cfg(feature = "pure-rust")]
+    assert!(crate::pure::apply(b"a\nb\n", text).is_err());
+}
+
+#[test]
+fn verify_patch_result() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let f2 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+    let mut patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(verify_patch(&mut f, &mut patch, &f2), Ok(()));
+
+    let wrong = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world CHANGED\n");
+    assert_eq!(
+        verify_patch(&mut f, &mut patch, &wrong),
+        Err(VerifyError::Mismatch {
+            line: 4,
+            hunk: Some(0)
+        })
+    );
+
+    let len = f.as_slice().len();
+    f.as_slice_mut()[len - 2] = b'D';
+    assert_eq!(
+        verify_patch(&mut f, &mut patch, &f2),
+        Err(VerifyError::Rejected { hunk: 0 })
+    );
+}
+
+#[cfg(feature = "arbitrary")]
+#[test]
+fn arbitrary_patch_applies() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    let raw = b"\x0chello\nworld\n\x0dhello\nthere\n!";
+    let mut u = Unstructured::new(raw);
+    let mut old = MMFile::arbitrary(&mut u).unwrap();
+    let new = MMFile::arbitrary(&mut u).unwrap();
+    let mut patch = old.compute_patch(&new).unwrap();
+    assert_eq!(old.apply_patch(&mut patch).unwrap(), new);
+
+    let mut u = Unstructured::new(raw);
+    let patch = Patch::arbitrary(&mut u).unwrap();
+    assert!(Patch::from_bytes(patch.as_slice()).is_ok());
+}
+
+#[test]
+fn merge3_preserve_line_endings() {
+    let mut f = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world\n");
+    let mut f2 = MMFile::from_bytes(b"header\r\nline2\r\nline3\r\nline4\r\nhello world\r\n");
+    let mut f3 = MMFile::from_bytes(b"header\nline2\nline3\nline4\nhello world changed\n");
+
+    let opts = MergeOptions::new().preserve_line_endings(true);
+    let outcome = MMFile::merge3_with(&mut f, &mut f2, &mut f3, &opts).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(
+        outcome.merged.as_slice(),
+        b"header\r\nline2\r\nline3\r\nline4\r\nhello world changed\r\n"
+    );
+    assert_eq!(
+        LineEnding::detect(outcome.merged.as_slice()),
+        LineEnding::CrLf
+    );
+
+    let conflicts =
+        parse_conflict_markers(b"<<<<<<< a\r\nx\r\n=======\r\ny\r\n>>>>>>> b\r\n").unwrap();
+    assert_eq!(conflicts[0].line_ending, LineEnding::CrLf);
+    assert_eq!(
+        conflicts[0].to_bytes(),
+        b"<<<<<<< a\r\nx\r\n=======\r\ny\r\n>>>>>>> b\r\n"
+    );
+}
+
+#[cfg(feature = "encoding")]
+#[test]
+fn diff_utf16() {
+    use crate::TextEncoding;
+
+    let old: Vec<u8> = "\u{feff}héllo\nworld\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let new: Vec<u8> = "\u{feff}héllo\nthere\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    let encoding = TextEncoding::detect(&old);
+    assert_eq!(encoding, TextEncoding::Utf16Le);
+
+    let f = MMFile::from_encoded(&old, encoding);
+    let f2 = MMFile::from_encoded(&new, encoding);
+    assert_eq!(f.to_encoded(encoding), old);
+
+    let mut lines = Vec::<String>::new();
+    f.diff_raw(&f2, |line: &[u8]| {
+        lines.push(String::from_utf8_lossy(line).into_owned());
+    })
+    .unwrap();
+    assert_eq!(
+        lines,
+        vec![
+            "@@ -1,2 +1,2 @@\n",
+            " ",
+            "\u{feff}héllo\n",
+            "-",
+            "world\n",
+            "+",
+            "there\n"
+        ]
+    );
+
+    let latin1 = TextEncoding::Latin1;
+    assert_eq!(latin1.encode(&latin1.decode(b"caf\xe9\n")), b"caf\xe9\n");
+}
+
+#[test]
+fn diff_ignore_bom() {
+    let mut f = MMFile::from_bytes(b"\xEF\xBB\xBFhello\nworld\n");
+    let mut f2 = MMFile::from_bytes(b"hello\nworld\n");
+
+    let hunks = f.diff_hunks(&f2, &DiffOptions::new()).unwrap();
+    assert_eq!(hunks.len(), 1);
+    let opts = DiffOptions::new().ignore_bom(true);
+    assert!(f.diff_hunks(&f2, &opts).unwrap().is_empty());
+
+    let mut f3 = MMFile::from_bytes(b"hello\nthere\n");
+    let opts = MergeOptions::new().ignore_bom(true);
+    let outcome = MMFile::merge3_with(&mut f2, &mut f, &mut f3, &opts).unwrap();
+    assert!(outcome.is_clean());
+    assert_eq!(outcome.merged.as_slice(), b"\xEF\xBB\xBFhello\nthere\n");
+}
+
+#[cfg(feature = "similar")]
+#[test]
+fn similar_interop() {
+    use similar::{DiffOp, TextDiff};
+
+    let old = b"one\ntwo\nthree\n";
+    let new = b"one\n2\nthree\n";
+    let f = MMFile::from_bytes(old);
+    let f2 = MMFile::from_bytes(new);
+    let hunks = f.diff_hunks(&f2, &DiffOptions::new()).unwrap();
+    assert_eq!(
+        hunks[0].to_diff_ops(),
+        vec![
+            DiffOp::Equal {
+                old_index: 0,
+                new_index: 0,
+                len: 1
+            },
+            DiffOp::Replace {
+                old_index: 1,
+                old_len: 1,
+                new_index: 1,
+                new_len: 1
+            },
+            DiffOp::Equal {
+                old_index: 2,
+                new_index: 2,
+                len: 1
+            },
+        ]
+    );
+
+    let diff = TextDiff::from_lines("one\ntwo\nthree\n", "one\n2\nthree\n");
+    let converted = crate::hunks_from_diff_ops(diff.ops(), old, new, 3);
+    assert_eq!(converted, hunks);
+}
+
+#[cfg(feature = "diffy")]
+#[test]
+fn diffy_roundtrip() {
+    let old = b"header\nline2\nline3\nline4\nhello world\n";
+    let new = b"header\nline2\nline3\nline4\nhello world changed\n";
+    let mut f = MMFile::from_bytes(old);
+    let f2 = MMFile::from_bytes(new);
+    let patch = f.compute_patch(&f2).unwrap();
+
+    let diffy_patch = patch.to_diffy().unwrap();
+    assert_eq!(diffy_patch.hunks().len(), 1);
+    assert_eq!(diffy::apply_bytes(old, &diffy_patch).unwrap(), new);
+
+    let diffy_patch = diffy::create_patch_bytes(old, new);
+    let mut converted = Patch::from_diffy(&diffy_patch).unwrap();
+    assert_eq!(f.apply_patch(&mut converted).unwrap(), f2);
+}
+
+#[cfg(feature = "gix")]
+#[test]
+fn gix_sink() {
+    use core::ops::Range;
+    use imara_diff::{intern::InternedInput, sources::lines, Sink};
+
+    #[derive(Default)]
+    struct Changes(Vec<(Range<u32>, Range<u32>)>);
+    impl Sink for Changes {
+        type Out = Vec<(Range<u32>, Range<u32>)>;
+        fn process_change(&mut self, before: Range<u32>, after: Range<u32>) {
+            self.0.push((before, after));
+        }
+        fn finish(self) -> Self::Out {
+            self.0
+        }
+    }
+
+    let input = InternedInput::new(lines("a\nb\nc\nd\n"), lines("a\nB\nc\nd\ne\n"));
+    let changes = crate::gix::diff(&input, Changes::default()).unwrap();
+    assert_eq!(changes, vec![(1..2, 1..2), (4..4, 4..5)]);
+}
+
+#[cfg(feature = "alloc-stats")]
+#[test]
+fn alloc_stats_counts() {
+    // other tests allocate concurrently, so only check monotonic counters
+    let before = crate::alloc_stats();
+    let f = MMFile::from_bytes(b"hello world\n");
+    let f2 = MMFile::from_bytes(b"hello world!\n");
+    f.compute_patch(&f2).unwrap();
+    let after = crate::alloc_stats();
+    assert!(after.allocations > before.allocations);
+    assert!(after.frees > 0);
+    assert!(after.peak_bytes >= after.live_bytes);
+    assert!(after.peak_bytes > 0);
+}
+
+#[test]
+fn diffstat_summary() {
+    let f = MMFile::from_bytes(b"a\nb\nc\n");
+    let f2 = MMFile::from_bytes(b"a\nB\nc\nd\n");
+    let patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(patch.line_changes(), (2, 1));
+    assert_eq!(
+        patch.diffstat("file.txt", 40),
+        " file.txt | 3 ++-\n 1 file changed, 2 insertions(+), 1 deletion(-)\n"
+    );
+
+    let big =
+        Patch::from_bytes(format!("@@ -1,0 +1,20 @@\n{}", "+x\n".repeat(20)).as_bytes()).unwrap();
+    let rendered = crate::diffstat(&[("long/name.rs", &big), ("f", &patch)], 10);
+    assert_eq!(
+        rendered,
+        concat!(
+            " long/name.rs | 20 ++++++++++\n",
+            " f            |  3 +-\n",
+            " 2 files changed, 22 insertions(+), 1 deletion(-)\n",
+        )
+    );
+}
+
+#[test]
+fn patch_summary() {
+    let f = MMFile::from_bytes(b"a\nb\nc\n");
+    let f2 = MMFile::from_bytes(b"a\nB\nc\nd\n");
+    let patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(
+        patch.summary(),
+        "1 hunk: +2/\u{2212}1 lines, affects lines 2\u{2013}3"
+    );
+
+    let text = "@@ -0,0 +1 @@\n+top\n@@ -10,2 +11,1 @@\n-x\n y\n@@ -40,0 +41,2 @@\n+p\n+q\n";
+    let patch = Patch::from_bytes(text.as_bytes()).unwrap();
+    assert_eq!(
+        patch.summary(),
+        "3 hunks: +3/\u{2212}1 lines, affects lines at start, 10, after 40"
+    );
+    assert_eq!(Patch::from_bytes(b"").unwrap().summary(), "no changes");
+}
+
+#[test]
+fn binary_patch_roundtrip() {
+    let mut data = Vec::new();
+    data.extend((0..240).cycle().take(15_000));
+    let mut old = MMFile::from_bytes(&data);
+    data[7_000..7_010].copy_from_slice(b"0123456789");
+    data.extend_from_slice(b"tail");
+    let new = MMFile::from_bytes(&data);
+
+    let mut patch = old.bdiff(&new).unwrap();
+    assert_eq!(old.apply_binary_patch(&mut patch).unwrap(), new);
+    let mut patch = old.rabdiff(&new).unwrap();
+    assert!(patch.as_slice().len() < data.len());
+    assert_eq!(old.apply_binary_patch(&mut patch).unwrap(), new);
+
+    assert!(BinaryPatch::from_bytes(b"short").is_err());
+
+    for opts in [
+        BDiffOptions::new().block_size(8),
+        BDiffOptions::new().block_size(4096),
+        BDiffOptions::new().algorithm(BDiffAlgorithm::Rabin),
+    ] {
+        let mut patch = old.bdiff_with(&new, &opts).unwrap();
+        assert_eq!(old.apply_binary_patch(&mut patch).unwrap(), new);
+    }
+    assert!(old
+        .bdiff_with(&new, &BDiffOptions::new().block_size(0))
+        .is_err());
+}
+
+#[test]
+fn signature_delta() {
+    let mut data = Vec::new();
+    data.extend((0..=255u8).cycle().take(10_005));
+    let mut old = MMFile::from_bytes(&data);
+    let sig = old.signature(64).unwrap();
+    assert_eq!(sig.blocks().len(), 157);
+    assert_eq!(sig.source_len(), 10_005);
+
+    // shifted insert, modified block, and unchanged short final block
+    data.insert(100, b'x');
+    data[5_000] ^= 0xff;
+    let new = MMFile::from_bytes(&data);
+    let mut delta = sig.delta(&new).unwrap();
+    assert!(delta.as_slice().len() < 1_000);
+    assert_eq!(old.apply_binary_patch(&mut delta).unwrap(), new);
+
+    let small = MMFile::from_bytes(b"abc");
+    let mut delta = sig.delta(&small).unwrap();
+    assert_eq!(old.apply_binary_patch(&mut delta).unwrap(), small);
+    assert!(old.signature(0).is_err());
+}
+
+#[test]
+fn changed_lines() {
+    let f = MMFile::from_bytes(b"a\nb\nc\nd\ne\nf\n");
+    let f2 = MMFile::from_bytes(b"new\na\nB\nc\nd\nf\n");
+    let changes = f
+        .changed_lines(&f2, &DiffOptions::new().context_len(10))
+        .unwrap();
+    assert_eq!(
+        changes,
+        vec![
+            LineChange {
+                old: 0..0,
+                new: 0..1
+            },
+            LineChange {
+                old: 1..2,
+                new: 2..3
+            },
+            LineChange {
+                old: 4..5,
+                new: 5..5
+            },
+        ]
+    );
+}
+
+#[test]
+fn differs() {
+    let f = MMFile::from_bytes(b"hello world\n");
+    let f2 = MMFile::from_bytes(b"hello world\n");
+    let f3 = MMFile::from_bytes(b"hello world!\n");
+    let opts = DiffOptions::new();
+    assert!(!f.differs(&f2, &opts).unwrap());
+    assert!(f.differs(&f3, &opts).unwrap());
+
+    let bom = MMFile::from_bytes(b"\xEF\xBB\xBFhello world\n");
+    assert!(f.differs(&bom, &opts).unwrap());
+    let opts = opts.ignore_bom(true);
+    assert!(!f.differs(&bom, &opts).unwrap());
+    assert!(bom.differs(&f3, &opts).unwrap());
+}
+
+#[test]
+fn diff_trim_common() {
+    let old: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+    let edits = [
+        old.replace("line 500\n", "changed\n"),
+        format!("first\n{}", old),
+        format!("{}last", old),
+        old.replace("line 3\n", "").replace("line 990\n", "x\ny\n"),
+        old.clone(),
+    ];
+    for new in edits.iter() {
+        let f = MMFile::from_bytes(old.as_bytes());
+        let f2 = MMFile::from_bytes(new.as_bytes());
+        let expected = f.diff_hunks(&f2, &DiffOptions::new()).unwrap();
+        let trimmed = f
+            .diff_hunks(&f2, &DiffOptions::new().trim_common(true))
+            .unwrap();
+        assert_eq!(trimmed, expected);
+    }
+}
+
+#[test]
+fn diff_max_cost() {
+    let old: String = (0..100).map(|i| format!("a{}\n", i)).collect();
+    let new: String = (0..100).map(|i| format!("b{}\n", i)).collect();
+    let mut f = MMFile::from_bytes(format!("head\n{}tail", old).as_bytes());
+    let f2 = MMFile::from_bytes(format!("head\n{}tail", new).as_bytes());
+
+    let opts = DiffOptions::new().max_cost(100 * 100, CostFallback::Error);
+    assert!(f.differs(&f2, &opts).unwrap());
+    // only ignore_bom makes differs run a diff that can be too expensive
+    let opts = DiffOptions::new()
+        .max_cost(50, CostFallback::Error)
+        .ignore_bom(true);
+    assert_eq!(
+        f.differs(&f2, &opts),
+        Err(Error::TooExpensive {
+            cost: 100 * 100,
+            limit: 50
+        })
+    );
+    assert!(f.diff_hunks(&f2, &opts).is_err());
+
+    let opts = DiffOptions::new().max_cost(50, CostFallback::Coarse);
+    let hunks = f.diff_hunks(&f2, &opts).unwrap();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].header.old_len, 102);
+    assert_eq!(hunks[0].old_changed_lines(), 1..101);
+    assert_eq!(hunks[0].new_changed_lines(), 1..101);
+
+    let mut patch = f.compute_patch(&f2).unwrap();
+    let coarse = f.diff_hunks(&f2, &opts.clone().context_len(0)).unwrap();
+    assert_eq!(coarse[0].header.old_len, 100);
+    assert_eq!(f.apply_patch(&mut patch).unwrap(), f2);
+}
+
+#[test]
+fn diff_xflags() {
+    let opts = DiffOptions::new().minimal(true);
+    assert_eq!(opts, DiffOptions::new().flags(XFlags::NEED_MINIMAL));
+    assert_eq!(opts.xpparam().flags, XFlags::NEED_MINIMAL.bits());
+    assert_eq!(opts.minimal(false).xpparam().flags, 0);
+
+    let upstream = XFlags::from_bits_retain(1 << 20);
+    let opts = DiffOptions::new().flags(upstream).minimal(true);
+    assert_eq!(
+        opts.xpparam().flags,
+        (1 << 20) | XFlags::NEED_MINIMAL.bits()
+    );
+
+    let f = MMFile::from_bytes(b"a\nb\nc\n");
+    let f2 = MMFile::from_bytes(b"a\nc\n");
+    let hunks = f
+        .diff_hunks(&f2, &DiffOptions::new().flags(XFlags::NEED_MINIMAL))
+        .unwrap();
+    assert_eq!(hunks.len(), 1);
+}
+
+#[test]
+fn apply_patch_line_map() {
+    let mut f = MMFile::from_bytes(b"a\nb\nc\nd\ne\nf\ng\n");
+    let f2 = MMFile::from_bytes(b"new\na\nB\nc\nd\nf\ng\n");
+    let mut patch = f.compute_patch(&f2).unwrap();
+    let (patched, map) = f.apply_patch_mapped(&mut patch).unwrap();
+    assert_eq!(patched, f2);
+    assert_eq!(
+        map.to_vec(),
+        vec![Some(1), None, Some(3), Some(4), None, Some(5), Some(6)]
+    );
+    assert_eq!(map.nearest(1), 2);
+    assert_eq!(map.nearest(4), 5);
+    assert_eq!(map.get(7), None);
+    assert_eq!(map.nearest(7), 7);
+}
+
+#[test]
+fn blame_patch_chain() {
+    let base = MMFile::from_bytes(b"a\nb\nc\n");
+    let v1 = MMFile::from_bytes(b"a\nx\nb\nc\n");
+    let v2 = MMFile::from_bytes(b"a\nx\nb\nC\ny\n");
+    let mut patches = vec![
+        base.clone().compute_patch(&v1).unwrap(),
+        v1.compute_patch(&v2).unwrap(),
+    ];
+    let result = crate::blame(&base, &mut patches).unwrap();
+    assert_eq!(result.file, v2);
+    assert_eq!(result.origins, vec![None, Some(0), None, Some(1), Some(1)]);
+    assert_eq!(result.origin(1), Some(0));
+    assert_eq!(result.origin(10), None);
+
+    let mut bad = vec![patches.pop().unwrap()];
+    assert!(matches!(
+        crate::blame(&base, &mut bad),
+        Err(Error::Invalid(_))
+    ));
+}
+
+#[test]
+fn incremental_diff() {
+    let old: String = (0..50).map(|i| format!("line {}\n", i)).collect();
+    let mut new = old.replace("line 10\n", "ten\n");
+    let opts = DiffOptions::new();
+    let mut diff = IncrementalDiff::new(
+        MMFile::from_bytes(old.as_bytes()),
+        MMFile::from_bytes(new.as_bytes()),
+        &opts,
+    )
+    .unwrap();
+    assert_eq!(diff.hunks().len(), 1);
+
+    let mut edit = |diff: &mut IncrementalDiff, from: &str, to: &str| {
+        let start = new.find(from).unwrap();
+        diff.edit(start..start + from.len(), to.as_bytes()).unwrap();
+        new.replace_range(start..start + from.len(), to);
+        let f = MMFile::from_bytes(old.as_bytes());
+        let f2 = MMFile::from_bytes(new.as_bytes());
+        let expected = f
+            .diff_hunks(&f2, &DiffOptions::new().context_len(0))
+            .unwrap();
+        assert_eq!(diff.new_file(), &f2);
+        assert_eq!(diff.hunks(), expected.as_slice());
+    };
+    // a new hunk, an edit touching an existing one, and a line removal
+    edit(&mut diff, "line 30\n", "thirty\nmore\n");
+    edit(&mut diff, "ten\n", "line 10\nline 11 and a half\n");
+    edit(&mut diff, "line 40\n", "");
+    edit(&mut diff, "line 0", "zero");
+    edit(&mut diff, "line 49\n", "last");
+    assert_eq!(diff.hunks().len(), 5);
+
+    assert!(diff.edit(5..1000, b"").is_err());
+}
+
+#[test]
+fn apply_patch_from_reader() {
+    let f = MMFile::from_bytes(b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n");
+    let f2 = MMFile::from_bytes(b"new\na\nb\nc\nd\nE\nf\ng\nh\ni\nj");
+    let patch = f.compute_patch(&f2).unwrap();
+    let mut text = b"--- a/file\n+++ b/file\n".to_vec();
+    text.extend_from_slice(patch.as_slice());
+    let patched = crate::apply_patch_from_reader(&f, text.as_slice()).unwrap();
+    assert_eq!(patched, f2);
+
+    let other = MMFile::from_bytes(b"a\nb\nc\nd\nX\nf\ng\nh\ni\nj\n");
+    let err = crate::apply_patch_from_reader(&other, patch.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("line 5"), "{}", err);
+    assert!(crate::apply_patch_from_reader(&f, b"@@ -1,2 +1,1 @@\n a\n".as_slice()).is_err());
+}
+
+#[test]
+fn apply_patch_resolving() {
+    let base = MMFile::from_bytes(b"1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    let patch = Patch::from_bytes(
+        b"@@ -2,1 +2,2 @@\n-2\n+two\n+2.5\n@@ -5,1 +6,1 @@\n-nope\n+five\n@@ -8,1 +9,1 @@\n-8\n+eight\n",
+    )
+    .unwrap();
+
+    let mut seen = Vec::new();
+    let patched = base
+        .apply_patch_resolving(&patch, |rejected| {
+            seen.push(rejected.index);
+            Resolution::Replace(b"FIVE\n".to_vec())
+        })
+        .unwrap();
+    assert_eq!(seen, vec![Some(1)]);
+    assert_eq!(
+        patched.as_slice(),
+        b"1\ntwo\n2.5\n3\n4\nFIVE\n6\n7\neight\n9\n"
+    );
+
+    let patched = base
+        .apply_patch_resolving(&patch, |_| Resolution::Skip)
+        .unwrap();
+    assert_eq!(
+        patched.as_slice(),
+        b"1\ntwo\n2.5\n3\n4\n5\n6\n7\neight\n9\n"
+    );
+
+    let err = base
+        .apply_patch_resolving(&patch, |_| Resolution::Abort)
+        .unwrap_err();
+    assert_eq!(err.to_string(), "patch aborted at hunk 1");
+}
+
+#[test]
+fn apply_patch_reverse() {
+    let mut f = MMFile::from_bytes(b"a\nb\nc\n");
+    let mut f2 = MMFile::from_bytes(b"a\nB\nc\nd\n");
+    let mut patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(f2.apply_patch_reverse(&mut patch).unwrap(), f);
+    assert!(f.apply_patch_reverse(&mut patch).is_err());
+}
+
+#[test]
+fn non_utf8_policy() {
+    let f = MMFile::from_bytes(b"caf\xe9\nok\n");
+    let f2 = MMFile::from_bytes(b"caf\xe9!\nok\n");
+    assert_eq!(
+        f.to_string_with(NonUtf8::Lossy).unwrap(),
+        "caf\u{fffd}\nok\n"
+    );
+    assert_eq!(f.to_string_with(NonUtf8::Escape).unwrap(), "caf\\xe9\nok\n");
+    assert_eq!(
+        f.to_string_with(NonUtf8::Error).unwrap_err(),
+        Error::Invalid("invalid UTF-8 at byte 3".to_owned())
+    );
+
+    let opts = DiffOptions::new();
+    assert_eq!(
+        f.diff_unified(&f2, &opts, NonUtf8::Escape).unwrap(),
+        "@@ -1,2 +1,2 @@\n-caf\\xe9\n+caf\\xe9!\n ok\n"
+    );
+    assert!(f.diff_unified(&f2, &opts, NonUtf8::Error).is_err());
+    let patch = f.compute_patch(&f2).unwrap();
+    assert_eq!(
+        patch.to_string(),
+        patch.to_string_with(NonUtf8::Lossy).unwrap()
+    );
+}
+
+#[test]
+fn versioned_store() {
+    use crate::store::Store;
+
+    let versions: Vec<MMFile> = (0..10)
+        .map(|v| {
+            let text: String = (0..20)
+                .map(|i| format!("line {}\n", if i == v { 100 + i } else { i }))
+                .collect();
+            MMFile::from_bytes(text.as_bytes())
+        })
+        .collect();
+    let mut store = Store::new(versions[0].clone()).with_snapshot_interval(3);
+    for (i, v) in versions.iter().enumerate().skip(1) {
+        assert_eq!(store.push(v.clone()).unwrap(), i);
+    }
+    assert_eq!(store.len(), 10);
+    assert_eq!(store.snapshot_count(), 3);
+    assert_eq!(store.latest(), &versions[9]);
+    for (i, v) in versions.iter().enumerate() {
+        assert_eq!(&store.get(i).unwrap(), v);
+    }
+    assert!(store.get(10).is_err());
+
+    store.snapshot();
+    assert_eq!(store.snapshot_count(), 4);
+    assert_eq!(&store.get(8).unwrap(), &versions[8]);
+}
+
+#[test]
+fn patch_bundle_roundtrip() {
+    let mut f = MMFile::from_bytes(b"a\nb\n");
+    let f2 = MMFile::from_bytes(b"a\nB\n");
+    let f3 = MMFile::from_bytes(b"A\nb\nc");
+    let mut bundle = PatchBundle::new();
+    bundle.push("src/one.txt", f.compute_patch(&f2).unwrap());
+    bundle.push("two words.txt", f.compute_patch(&f3).unwrap());
+    bundle.push("empty", Patch::from_bytes(b"").unwrap());
+
+    let bytes = bundle.to_bytes();
+    assert!(bytes.starts_with(b"libxdiff-bundle 1\n11 "));
+    let read = PatchBundle::from_bytes(&bytes).unwrap();
+    assert_eq!(read, bundle);
+    assert_eq!(read.len(), 3);
+    let names: Vec<&str> = read.iter().map(|(name, _)| name).collect();
+    assert_eq!(names, vec!["src/one.txt", "two words.txt", "empty"]);
+    let mut patch = read.get("two words.txt").unwrap().clone();
+    assert_eq!(f.apply_patch(&mut patch).unwrap(), f3);
+
+    assert!(matches!(
+        PatchBundle::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(Error::Invalid(_))
+    ));
+    assert!(matches!(
+        PatchBundle::from_bytes(b"something else\n"),
+        Err(Error::Invalid(_))
+    ));
+
+    struct Failing;
+    impl std::io::Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::BrokenPipe.into())
+        }
+    }
+    let err = PatchBundle::read_from(Failing).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Io);
+    assert!(matches!(
+        err,
+        Error::Io {
+            kind: std::io::ErrorKind::BrokenPipe,
+            ..
+        }
+    ));
+}
+
+#[test]
+fn mmfile_byte_views() {
+    use std::borrow::Borrow;
+
+    let file = MMFile::from_bytes(b"hello\n");
+    assert_eq!(file.as_ref(), b"hello\n");
+    let borrowed: &[u8] = file.borrow();
+    assert_eq!(borrowed, b"hello\n");
+
+    fn len_of<B: AsRef<[u8]>>(bytes: B) -> usize {
+        bytes.as_ref().len()
+    }
+    assert_eq!(len_of(&file), 6);
+    assert_eq!(len_of(MMFile::new()), 0);
+}
+
+#[test]
+fn mmfile_builder() {
+    #[derive(Default)]
+    struct Document {
+        file: MMFile,
+        blocks: MMBlocks,
+    }
+    let mut doc = Document::default();
+    assert_eq!(doc.file.size(), 0);
+    assert_eq!(doc.blocks.size(), 0);
+
+    let mut f = MMFile::builder().capacity(1024).content("a\nb").build();
+    assert_eq!(f.as_slice(), b"a\nb");
+    assert_eq!(f.size(), 3);
+    assert!(f.is_compact());
+
+    let f = MMFile::builder()
+        .content(&b"a\r\nb\nc"[..])
+        .line_ending(LineEnding::CrLf)
+        .final_newline(true)
+        .build();
+    assert_eq!(f.as_slice(), b"a\r\nb\r\nc\r\n");
+
+    let builder = MMFileBuilder::new().content("x").final_newline(true);
+    assert_eq!(builder.build().as_slice(), b"x\n");
+    assert_eq!(
+        MMFileBuilder::new().final_newline(true).build(),
+        MMFile::new()
+    );
+}
+
+#[test]
+fn debug_preview() {
+    let f = MMFile::from_bytes(b"hi\n");
+    assert_eq!(
+        format!("{:?}", f),
+        r#"MMFile { size: 3, blocks: 1, preview: "hi\n" }"#
+    );
+    let f = MMFile::from_bytes(b"\xff\xfe");
+    assert_eq!(
+        format!("{:?}", f),
+        "MMFile { size: 2, blocks: 1, preview: \"\u{fffd}\u{fffd}\" }"
+    );
+    assert_eq!(
+        format!("{:?}", MMFile::new()),
+        r#"MMFile { size: 0, blocks: 0, preview: "" }"#
+    );
+
+    let long = "é".repeat(100);
+    let debug = format!("{:?}", MMFile::from_bytes(long.as_bytes()));
+    assert!(debug.starts_with("MMFile { size: 200, blocks: 1, preview: \"éé"));
+    assert!(debug.ends_with(&format!("{}…\" }}", "é".repeat(32))));
+
+    let blocks = MMBlocks::from_chunks(&[b"ab", b"cd"]);
+    assert_eq!(
+        format!("{:?}", blocks),
+        r#"MMBlocks { size: 4, blocks: 2, preview: "abcd" }"#
+    );
+}
+
+#[test]
+fn diff_raw_abort_on_panic() {
+    let f = MMFile::from_bytes(b"hello world\n");
+    let f2 = MMFile::from_bytes(b"hello world!\n");
+    let mut lines = Vec::new();
+    f.diff_raw_abort_on_panic(&f2, |line: &[u8]| {
+        lines.push(line.to_owned());
+        0
+    })
+    .unwrap();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[0], b"@@ -1,1 +1,1 @@\n");
+
+    let mut calls = 0;
+    let result = f.diff_raw_abort_on_panic(&f2, |_| {
+        calls += 1;
+        -1
+    });
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+}
+
+#[test]
+fn diff_raw_with_options() {
+    let f = MMFile::from_bytes(b"a\nb\nc\n");
+    let f2 = MMFile::from_bytes(b"a\nB\nc\n");
+    let opts = DiffOptions::new().context_len(0);
+    let mut lines = Vec::new();
+    f.diff_raw_with(&f2, &opts, |line| lines.push(line.to_owned()))
+        .unwrap();
+    assert_eq!(lines[0], b"@@ -2,1 +2,1 @@\n");
+    assert_eq!(lines.len(), 5);
+
+    let mut nopanic = Vec::new();
+    unsafe {
+        f.diff_raw_nopanic_with(&f2, &opts, |line| {
+            nopanic.push(line.to_owned());
+            0
+        })
+    }
+    .unwrap();
+    assert_eq!(nopanic, lines);
+
+    let mut kinds = Vec::new();
+    f.diff_raw_tagged_with(&f2, &opts, |kind, _| kinds.push(kind))
+        .unwrap();
+    assert_eq!(kinds.len(), 5);
+    assert_eq!(kinds[0], BufferKind::HunkHeader);
+}
+
+#[test]
+fn diff_merge_control_flow() {
+    use std::ops::ControlFlow;
+
+    let f = MMFile::from_bytes(b"a\nb\nc\n");
+    let f2 = MMFile::from_bytes(b"a\nB\nc\n");
+    let mut seen = 0;
+    let first_header = f
+        .diff_raw_until(&f2, |buf| {
+            seen += 1;
+            if buf.starts_with(b"@@") {
+                ControlFlow::Break(buf.to_owned())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+        .unwrap();
+    assert_eq!(first_header.unwrap(), b"@@ -1,3 +1,3 @@\n");
+    assert_eq!(seen, 1);
+
+    let done: Option<()> = f
+        .diff_raw_until(&f2, |_| ControlFlow::Continue(()))
+        .unwrap();
+    assert_eq!(done, None);
+    let panicked = f.diff_raw_until(&f2, |_| -> ControlFlow<()> { panic!("stop") });
+    assert!(panicked.is_err());
+
+    let mut base = MMFile::from_bytes(b"a\nb\nc\n");
+    let mut ours = MMFile::from_bytes(b"A\nb\nc\n");
+    let mut theirs = MMFile::from_bytes(b"a\nb\nC\n");
+    let mut merged = Vec::new();
+    let complete = MMFile::merge3_raw_until(
+        &mut base,
+        &mut ours,
+        &mut theirs,
+        |buf| {
+            merged.extend_from_slice(buf);
+            ControlFlow::<usize>::Continue(())
+        },
+        |_| ControlFlow::Break(0),
+    )
+    .unwrap();
+    assert_eq!(complete, None);
+    assert_eq!(merged, b"A\nb\nC\n");
+
+    let stopped = MMFile::merge3_raw_until(
+        &mut base,
+        &mut ours,
+        &mut theirs,
+        |buf| ControlFlow::Break(buf.len()),
+        |_| ControlFlow::Continue(()),
+    )
+    .unwrap();
+    assert!(stopped.is_some());
+}
+
+#[test]
+fn diff_max_hunks() {
+    let old: Vec<u8> = (0..100)
+        .flat_map(|i| format!("{}\n", i).into_bytes())
+        .collect();
+    let new: Vec<u8> = (0..100)
+        .flat_map(|i| match i % 20 {
+            0 => format!("changed {}\n", i).into_bytes(),
+            _ => format!("{}\n", i).into_bytes(),
+        })
+        .collect();
+    let f = MMFile::from_bytes(&old);
+    let f2 = MMFile::from_bytes(&new);
+
+    let (all, truncated) = f.diff_hunks_limited(&f2, &DiffOptions::new()).unwrap();
+    assert_eq!(all.len(), 5);
+    assert!(!truncated);
+
+    let opts = DiffOptions::new().max_hunks(2);
+    let (first, truncated) = f.diff_hunks_limited(&f2, &opts).unwrap();
+    assert!(truncated);
+    assert_eq!(first, all[..2]);
+    assert_eq!(f.diff_hunks(&f2, &opts).unwrap(), all[..2]);
+
+    let (same, truncated) = f
+        .diff_hunks_limited(&f2, &DiffOptions::new().max_hunks(5))
+        .unwrap();
+    assert!(!truncated);
+    assert_eq!(same, all);
+
+    let text = f
+        .diff_unified(&f2, &DiffOptions::new().max_hunks(1), NonUtf8::Lossy)
+        .unwrap();
+    assert_eq!(text.matches("@@ -").count(), 1);
+    assert!(f.differs(&f2, &DiffOptions::new().max_hunks(0)).unwrap());
+}
+
+#[test]
+fn patch_recontext() {
+    let old: Vec<u8> = (0..30)
+        .flat_map(|i| format!("{}\n", i).into_bytes())
+        .collect();
+    let new: Vec<u8> = (0..30)
+        .flat_map(|i| match i {
+            5 => b"five\n".to_vec(),
+            12 => Vec::new(),
+            29 => b"last".to_vec(),
+            _ => format!("{}\n", i).into_bytes(),
+        })
+        .collect();
+    let mut base = MMFile::from_bytes(&old);
+    let mut target = MMFile::from_bytes(&new);
+    let with_context = |base: &mut MMFile, target: &mut MMFile, n| {
+        base.compute_patch_with(target, &DiffOptions::new().context_len(n))
+            .unwrap()
+    };
+
+    // the first two changes share a hunk with 4 lines of context
+    let patch = with_context(&mut base, &mut target, 4);
+    assert_eq!(patch.hunk_count(), 2);
+    for n in [0, 1, 3, 4] {
+        let reduced = patch.reduce_context(n).unwrap();
+        assert_eq!(reduced, with_context(&mut base, &mut target, n));
+        assert_eq!(base.apply_patch(&mut reduced.clone()).unwrap(), target);
+    }
+
+    let small = with_context(&mut base, &mut target, 0);
+    let expanded = small.recontext(&mut base, 5).unwrap();
+    assert_eq!(expanded, with_context(&mut base, &mut target, 5));
+
+    let mut labeled = b"--- a/file\n+++ b/file\n".to_vec();
+    labeled.extend_from_slice(small.as_slice());
+    let labeled = Patch::from_bytes(&labeled).unwrap();
+    let expanded = labeled.recontext(&mut base, 2).unwrap();
+    assert!(expanded
+        .as_slice()
+        .starts_with(b"--- a/file\n+++ b/file\n@@"));
+    assert!(labeled
+        .recontext(&mut MMFile::from_bytes(b"unrelated\n"), 2)
+        .is_err());
+}
+
+#[test]
+fn patch_recount() {
+    let old: Vec<u8> = (0..20)
+        .flat_map(|i| format!("{}\n", i).into_bytes())
+        .collect();
+    let mut base = MMFile::from_bytes(&old);
+    let edited = concat!(
+        "--- a/f\n",
+        "+++ b/f\n",
+        "@@ -2,3 +2,3 @@\n",
+        " 1\n",
+        "-2\n",
+        "+two\n",
+        "+two and a half\n",
+        " 3\n",
+        "@@ -15,3 +15,3 @@\n",
+        " 14\n",
+        "-15\n",
+        " 16\n",
+    );
+    assert!(Patch::from_bytes(edited.as_bytes()).is_err());
+    let patch = Patch::recount(edited.as_bytes()).unwrap();
+    assert_eq!(
+        patch.as_slice(),
+        concat!(
+            "--- a/f\n",
+            "+++ b/f\n",
+            "@@ -2,3 +2,4 @@\n",
+            " 1\n",
+            "-2\n",
+            "+two\n",
+            "+two and a half\n",
+            " 3\n",
+            "@@ -15,3 +16,2 @@\n",
+            " 14\n",
+            "-15\n",
+            " 16\n",
+        )
+        .as_bytes()
+    );
+    let patched = base.apply_patch(&mut patch.clone()).unwrap();
+    assert!(patched
+        .as_slice()
+        .starts_with(b"0\n1\ntwo\ntwo and a half\n3\n"));
+
+    // drop the first of two hunks and renumber the second
+    let target = MMFile::from_bytes(&[b"x\n".as_slice(), &old[..old.len() - 3], b"y\n"].concat());
+    let hunks = base.diff_hunks(&target, &DiffOptions::new()).unwrap();
+    assert_eq!(hunks.len(), 2);
+    let second_only = Patch::from_hunks(&hunks[1..]).unwrap();
+    let patched = base.apply_patch(&mut second_only.clone()).unwrap();
+    assert_eq!(patched.as_slice(), [&old[..old.len() - 3], b"y\n"].concat());
+
+    assert!(Patch::recount(b"@@ -1,1 +1,1 @@\nbogus\n").is_err());
+}
+
+#[test]
+fn apply_patch_ignore_whitespace() {
+    let mut base = MMFile::from_bytes(b"fn f() {\n    a();\n    b();\n    c();\n}\n");
+    let target = MMFile::from_bytes(b"fn f() {\n    a();\n    B();\n    c();\n}\n");
+    let mut patch = base.compute_patch(&target).unwrap();
+
+    let mut reformatted = MMFile::from_bytes(b"fn f() {\n\ta();\n\tb();\n\tc();\n}\n");
+    assert!(reformatted.apply_patch(&mut patch).is_err());
+    let opts = ApplyOptions::new().ignore_whitespace(true);
+    let patched = reformatted.apply_patch_with(&mut patch, &opts).unwrap();
+    assert!(patched.as_slice().windows(4).any(|w| w == b"B();"));
+    assert!(!patched.as_slice().windows(2).any(|w| w == b"b("));
+
+    let patched = base
+        .apply_patch_with(&mut patch, &ApplyOptions::new())
+        .unwrap();
+    assert_eq!(patched, target);
+}
+
+#[test]
+fn apply_patch_preserve_line_endings() {
+    let mut base = MMFile::from_bytes(b"a\nb\nc\n");
+    let target = MMFile::from_bytes(b"a\nB\nnew\nc\n");
+    let mut patch = base.compute_patch(&target).unwrap();
+
+    let mut windows = MMFile::from_bytes(b"a\r\nb\r\nc\r\n");
+    assert!(windows.apply_patch(&mut patch).is_err());
+    let opts = ApplyOptions::new().preserve_line_endings(true);
+    let patched = windows.apply_patch_with(&mut patch, &opts).unwrap();
+    assert_eq!(patched.as_slice(), b"a\r\nB\r\nnew\r\nc\r\n");
+
+    // unchanged lines keep their own endings in a mixed file
+    let mut mixed = MMFile::from_bytes(b"a\nb\r\nc\r\n");
+    let patched = mixed.apply_patch_with(&mut patch, &opts).unwrap();
+    assert_eq!(patched.as_slice(), b"a\nB\r\nnew\r\nc\r\n");
+
+    // a CRLF patch applies to an LF file, keeping LF
+    let mut crlf_patch = Patch::from_bytes(&LineEnding::CrLf.apply(patch.as_slice())).unwrap();
+    let patched = base.apply_patch_with(&mut crlf_patch, &opts).unwrap();
+    assert_eq!(patched, target);
+}
+
+#[test]
+fn error_kinds() {
+    let oom = Error::Os {
+        op: "diff",
+        code: -1,
+        errno: libc::ENOMEM,
+    };
+    assert_eq!(oom.kind(), ErrorKind::OutOfMemory);
+    assert_eq!(oom.errno(), Some(libc::ENOMEM));
+    assert!(oom.to_string().starts_with("diff failed with err: -1"));
+    let invalid = Error::Os {
+        op: "bpatch",
+        code: -1,
+        errno: libc::EINVAL,
+    };
+    assert_eq!(invalid.kind(), ErrorKind::InvalidInput);
+    assert_eq!(
+        Error::Failed {
+            op: "diff",
+            code: -1
+        }
+        .kind(),
+        ErrorKind::Other
+    );
+    assert_eq!(
+        Error::Failed {
+            op: "diff",
+            code: -1
+        }
+        .errno(),
+        None
+    );
+    assert_eq!(
+        Error::TooExpensive { cost: 2, limit: 1 }.kind(),
+        ErrorKind::TooExpensive
+    );
+
+    // a failure reported by a callback doesn't pick up a stale errno
+    let f = MMFile::from_bytes(b"a\n");
+    let f2 = MMFile::from_bytes(b"b\n");
+    let opts = DiffOptions::new().ignore_bom(true);
+    let err = unsafe { f.diff_records(&f2, &opts, |_| -1) }.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Other);
+}
+
+#[test]
+fn emit_failure_position() {
+    let old: Vec<u8> = (0..40)
+        .flat_map(|i| format!("{}\n", i).into_bytes())
+        .collect();
+    let new: Vec<u8> = (0..40)
+        .flat_map(|i| match i {
+            5 | 30 => format!("changed {}\n", i).into_bytes(),
+            _ => format!("{}\n", i).into_bytes(),
+        })
+        .collect();
+    let f = MMFile::from_bytes(&old);
+    let f2 = MMFile::from_bytes(&new);
+    let opts = DiffOptions::new();
+
+    let mut headers = 0;
+    let err = unsafe {
+        f.diff_records(&f2, &opts, |record| {
+            if record.len() == 1 {
+                headers += 1;
+            }
+            if headers == 2 && record.first() == Some(&&b"-"[..]) {
+                -1
+            } else {
+                0
+            }
+        })
+    }
+    .unwrap_err();
+    // the second hunk has three lines of context before the removed line
+    assert_eq!(
+        err,
+        Error::Callback {
+            op: "diff",
+            hunk: 1,
+            line: Some(3),
+            panic: None
+        }
+    );
+    assert_eq!(err.to_string(), "diff callback failed on line 3 of hunk 1");
+
+    let err = unsafe { f.diff_records(&f2, &opts, |_| -1) }.unwrap_err();
+    assert_eq!(err.to_string(), "diff callback failed on header of hunk 0");
+
+    let mut calls = 0;
+    let err = f
+        .diff_raw(&f2, |_| {
+            calls += 1;
+            if calls == 2 {
+                panic!("sink closed");
+            }
+        })
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "diff callback panicked on line 0 of hunk 0: sink closed"
+    );
+}
+
+#[test]
+fn concurrent_init() {
+    let threads: Vec<_> = (0..16)
+        .map(|i| {
+            std::thread::spawn(move || {
+                for j in 0..50 {
+                    let data = format!("thread {} iteration {}\n", i, j);
+                    let mut f = MMFile::from_bytes(data.as_bytes());
+                    assert_eq!(f.size(), data.len());
+                    let mut blocks = MMBlocks::new();
+                    assert_eq!(blocks.write_buf(data.as_bytes()), 0);
+                    assert_eq!(blocks.to_mmfile(), f);
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
+
+#[test]
+fn diff_across_threads() {
+    let pairs: Vec<(MMFile, MMFile)> = (0..8)
+        .map(|i| {
+            (
+                MMFile::from_bytes(format!("a\n{}\nc\n", i).as_bytes()),
+                MMFile::from_bytes(format!("a\n{}\nC\n", i * 2).as_bytes()),
+            )
+        })
+        .collect();
+    let handles: Vec<_> = pairs
+        .into_iter()
+        .map(|(old, new)| {
+            std::thread::spawn(move || {
+                let patch = old.compute_patch(&new).unwrap();
+                (old, patch)
+            })
+        })
+        .collect();
+    for (i, handle) in handles.into_iter().enumerate() {
+        let (mut old, mut patch) = handle.join().unwrap();
+        let patched = old.apply_patch(&mut patch).unwrap();
+        assert_eq!(patched.as_slice(), format!("a\n{}\nC\n", i * 2).as_bytes());
+    }
+    let blocks = MMBlocks::from_bytes(b"moved");
+    let moved = std::thread::spawn(move || blocks.to_mmfile())
+        .join()
+        .unwrap();
+    assert_eq!(moved.as_slice(), b"moved");
+}
+
+#[test]
+fn raw_escape_hatches() {
+    let mut f = MMFile::from_bytes(b"raw bytes\n");
+    let size = unsafe { libxdiff_sys::xdl_mmfile_size(f.as_raw()) };
+    assert_eq!(size, 10);
+
+    let raw = f.into_raw();
+    let mut f = unsafe { MMFile::from_raw(raw) };
+    assert_eq!(f.as_slice(), b"raw bytes\n");
+    assert_eq!(f.size(), 10);
+
+    let blocks = MMBlocks::from_chunks(&[b"a", b"b"]);
+    let mut blocks = unsafe { MMBlocks::from_raw(blocks.into_raw()) };
+    assert_eq!(blocks.size(), 2);
+    assert_eq!(
+        unsafe { libxdiff_sys::xdl_mmfile_iscompact(blocks.as_raw()) },
+        0
+    );
+}
+
+#[cfg(feature = "pure-rust")]
+#[test]
+fn pure_rust_engine() {
+    use crate::pure;
+
+    let old = b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    let new = b"a\nB\nc\nd\ne\nf\ng\nh\nj\nk";
+    let hunks = pure::diff_hunks(old, new, 1);
+    let f = MMFile::from_bytes(old);
+    let f2 = MMFile::from_bytes(new);
+    let opts = DiffOptions::new().context_len(1);
+    assert_eq!(hunks, f.diff_hunks(&f2, &opts).unwrap());
+
+    let patch = pure::diff(old, new, 3);
+    assert_eq!(
+        patch,
+        f.compute_patch(&f2).unwrap().as_slice(),
+        "{}",
+        String::from_utf8_lossy(&patch)
+    );
+    assert_eq!(pure::apply(old, &patch).unwrap(), new);
+    assert!(pure::apply(b"x\n", &patch).is_err());
+    assert!(pure::diff(old, old, 3).is_empty());
+    assert_eq!(pure::apply(b"", &pure::diff(b"", new, 3)).unwrap(), new);
+
+    let base = b"1\n2\n3\n4\n5\n6\n";
+    let ours = b"one\n2\n3\n4\n5\n6\n";
+    let theirs = b"1\n2\n3\n4\n5\nsix\n";
+    let (merged, conflicts) = pure::merge3(base, ours, theirs);
+    assert_eq!(merged, b"one\n2\n3\n4\n5\nsix\n");
+    assert!(conflicts.is_empty());
+
+    let theirs = b"uno\n2\n3\n4\n5\nsix\n";
+    let (merged, conflicts) = pure::merge3(base, ours, theirs);
+    assert_eq!(merged, b"one\n2\n3\n4\n5\nsix\n");
+    assert_eq!(conflicts, vec![0..1]);
+}
+
+#[test]
+fn memory_usage() {
+    let usage = MMFile::builder()
+        .capacity(1024)
+        .content("a\nb")
+        .build()
+        .memory_usage();
+    assert_eq!(usage.stored, 3);
+    assert_eq!(usage.blocks, 1);
+    assert!(usage.allocated >= 1024);
+    assert!(usage.overhead() >= 1021);
+
+    let mut blocks = MMBlocks::from_chunks(&[b"one\n", b"two\n", b"three\n"]);
+    let usage = blocks.memory_usage();
+    assert_eq!(usage.stored, 14);
+    assert!(usage.blocks >= 1);
+    blocks.to_compact();
+    assert_eq!(blocks.memory_usage().blocks, 1);
+    assert_eq!(MMBlocks::new().memory_usage(), MemoryUsage::default());
+}
+
+#[test]
+fn line_count() {
+    assert_eq!(MMFile::new().line_count(), 0);
+    assert_eq!(MMFile::from_bytes(b"a\nb\n").line_count(), 2);
+    assert_eq!(MMFile::from_bytes(b"a\nb").line_count(), 2);
+    assert_eq!(MMFile::from_bytes(b"\n\n\n").line_count(), 3);
+
+    let blocks = MMBlocks::from_chunks(&[b"one\ntw", b"o\nthree", b""]);
+    assert_eq!(blocks.line_count(), 3);
+    assert_eq!(MMBlocks::from_chunks(&[b"x\n", b""]).line_count(), 1);
+}
+
+#[test]
+fn diff_hunks_in_ranges() {
+    let lines: Vec<String> = (1..=40).map(|i| format!("{}\n", i)).collect();
+    let old = MMFile::from_bytes(lines.concat().as_bytes());
+    let mut changed = lines.clone();
+    changed[4] = "five\n".to_owned();
+    changed[29] = "thirty\n".to_owned();
+    let new = MMFile::from_bytes(changed.concat().as_bytes());
+    let opts = DiffOptions::new();
+
+    let all = old.diff_hunks(&new, &opts).unwrap();
+    assert_eq!(all.len(), 2);
+    let hunks = old.diff_hunks_in(&new, &[28..30, 31..32], &opts).unwrap();
+    assert_eq!(hunks, all[1..]);
+    let hunks = old
+        .diff_hunks_in(&new, &[0..1, 7..8, 35..40], &opts)
+        .unwrap();
+    assert_eq!(hunks, all[..1]);
+    assert!(old.diff_hunks_in(&new, &[], &opts).unwrap().is_empty());
+}
+
+#[test]
+fn diff_indent_heuristic() {
+    let old = MMFile::from_bytes(b"fn a() {\n    x\n}\n");
+    let new = MMFile::from_bytes(b"fn a() {\n    x\n}\n\nfn b() {\n    x\n}\n");
+    let opts = DiffOptions::new().indent_heuristic(true);
+    let patch = old.compute_patch_with(&new, &opts).unwrap();
+    assert_eq!(
+        patch.as_slice(),
+        b"@@ -1,3 +1,7 @@\n fn a() {\n     x\n }\n+\n+fn b() {\n+    x\n+}\n"
+    );
+
+    let hunks = old.diff_hunks(&new, &opts.context_len(0)).unwrap();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].new_lines(), 3..7);
+    assert_eq!(hunks[0].old_lines(), 3..3);
+}
+
+#[test]
+fn diff_semantic_cleanup() {
+    let mut old = MMFile::from_bytes(b"The cat sat.\n\nIt was warm.\nThe end.\n");
+    let new = MMFile::from_bytes(b"A dog stood up.\n\nIt was cold outside.\nThe end.\n");
+    let opts = DiffOptions::new().context_len(0);
+    assert_eq!(old.diff_hunks(&new, &opts).unwrap().len(), 2);
+
+    let hunks = old
+        .diff_hunks(&new, &opts.clone().semantic_cleanup(true))
+        .unwrap();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].old_lines(), 0..3);
+    assert_eq!(hunks[0].new_lines(), 0..3);
+
+    let mut patch = old
+        .compute_patch_with(&new, &DiffOptions::new().semantic_cleanup(true))
+        .unwrap();
+    assert_eq!(old.apply_patch(&mut patch).unwrap(), new);
+}
+
+#[test]
+fn char_patch_fuzzy_apply() {
+    let old = "The quick brown fox jumps over the lazy dog.";
+    let new = "That quick brown fox jumped over a lazy dog.";
+    let patch = CharPatch::new(old, new).unwrap();
+    assert!(!patch.hunks().is_empty());
+    let (patched, applied) = patch.apply(old).unwrap();
+    assert_eq!(patched, new);
+    assert!(applied.iter().all(|a| *a));
+
+    // text moved by an earlier edit
+    let (patched, applied) = patch.apply(&format!("Intro. {}", old)).unwrap();
+    assert_eq!(patched, format!("Intro. {}", new));
+    assert!(applied.iter().all(|a| *a));
+
+    // context that no longer matches exactly
+    let (patched, applied) = patch
+        .apply("The quack brown fox jumps over the lazy dog.")
+        .unwrap();
+    assert_eq!(patched, "That quack brown fox jumped over a lazy dog.");
+    assert!(applied.iter().all(|a| *a));
+
+    // nothing close enough to patch
+    let unrelated = "Lorem ipsum dolor sit amet.";
+    let opts = CharPatchOptions::new().match_threshold(0.2);
+    let (patched, applied) = patch.apply_with(unrelated, &opts).unwrap();
+    assert_eq!(patched, unrelated);
+    assert!(applied.iter().all(|a| !*a));
+
+    let patch = CharPatch::new("line one\nline two\n", "line one\nline 2\n").unwrap();
+    let (patched, _) = patch.apply("line zero\nline one\nline two\n").unwrap();
+    assert_eq!(patched, "line zero\nline one\nline 2\n");
+
+    // a hunk far longer than the fuzzy search scores is still found when
+    // both its scored prefix and the rest differ slightly
+    let mut seed = 1u32;
+    let old: String = (0..3000)
+        .map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            char::from(b'a' + (seed >> 16) as u8 % 26)
+        })
+        .collect();
+    let new = format!("{}{}", &old[..1000], &old[2000..]);
+    let patch = CharPatch::new(&old, &new).unwrap();
+    let mut moved: Vec<char> = format!("zz{}", old).chars().collect();
+    moved[2 + 1000] = '!';
+    moved[2 + 1500] = '!';
+    let moved: String = moved.into_iter().collect();
+    let (patched, applied) = patch.apply(&moved).unwrap();
+    assert!(applied.iter().all(|a| *a));
+    assert_eq!(patched, format!("zz{}", new));
+}
+
+#[test]
+fn diff_by_tokens() {
+    let old = b"the quick brown fox";
+    let new = b"the quick red fox";
+    let changes = diff_tokens(old, new, &Words, &DiffOptions::new()).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].old, 4..5);
+    assert_eq!(changes[0].new, 4..5);
+    assert_eq!(&old[changes[0].old_bytes.clone()], b"brown");
+    assert_eq!(&new[changes[0].new_bytes.clone()], b"red");
+
+    let changes = diff_tokens(
+        b"a,b,c\n",
+        b"a,x,c\n",
+        &Separated(b','),
+        &DiffOptions::new(),
+    )
+    .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].old_bytes, 2..4);
+
+    // a tokenizer that skips whitespace ignores changes to it
+    let words = |bytes: &[u8]| {
+        let mut tokens = Vec::new();
+        let mut start = None;
+        for (i, b) in bytes.iter().enumerate() {
+            match (start, b.is_ascii_whitespace()) {
+                (None, false) => start = Some(i),
+                (Some(s), true) => {
+                    tokens.push(s..i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        tokens.extend(start.map(|s| s..bytes.len()));
+        tokens
+    };
+    let changes = diff_tokens(
+        b"one  two\nthree",
+        b"one two three",
+        &words,
+        &DiffOptions::new(),
+    )
+    .unwrap();
+    assert!(changes.is_empty());
+}
+
+#[test]
+fn diff_syntax_classes() {
+    // one record per line; lines differing only in whitespace share a class,
+    // and `#` comment lines are skipped
+    let lexer = |bytes: &[u8]| {
+        let mut records = Vec::new();
+        let mut start = 0;
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            let range = start..start + line.len();
+            start += line.len();
+            let code: Vec<u8> = line
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect();
+            if code.starts_with(b"#") {
+                continue;
+            }
+            let mut hasher = std::collections::hash_map::DefaultHasher::new();
+            std::hash::Hash::hash(&code, &mut hasher);
+            records.push(Lexeme::with_class(
+                range,
+                std::hash::Hasher::finish(&hasher),
+            ));
+        }
+        records
+    };
+    let old = b"fn f() {\n    g(1, 2);\n}\n";
+    let new = b"fn f() {\n# call g\n  g(1,2);\n    h();\n}\n";
+    let changes = diff_syntax(old, new, &lexer, &DiffOptions::new()).unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].old, 2..2);
+    assert_eq!(changes[0].new, 2..3);
+    assert_eq!(&new[changes[0].new_bytes.clone()], b"    h();\n");
+
+    let by_bytes = |bytes: &[u8]| vec![Lexeme::new(0..bytes.len())];
+    assert!(diff_syntax(old, old, &by_bytes, &DiffOptions::new())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn diff_record_key() {
+    let old = MMFile::from_bytes(b"Alpha\nbeta  \ngamma\n");
+    let new = MMFile::from_bytes(b"alpha\nbeta\nGAMMA\ndelta\n");
+    let opts =
+        DiffOptions::new().record_key(|line: &[u8]| line.trim_ascii_end().to_ascii_lowercase());
+    let hunks = old.diff_hunks(&new, &opts.clone().context_len(0)).unwrap();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].old_lines(), 3..3);
+    assert_eq!(hunks[0].new_lines(), 3..4);
+    // the original lines are emitted as context
+    let hunks = old.diff_hunks(&new, &opts).unwrap();
+    assert_eq!(hunks[0].changes[1].line, b"beta  \n");
+    assert_eq!(hunks[0].changes[3].line, b"delta\n");
+    assert!(old.differs(&new, &opts).unwrap());
+
+    let same = MMFile::from_bytes(b"ALPHA\nBeta\ngamma\n");
+    assert!(!old.differs(&same, &opts).unwrap());
+    assert_eq!(opts.clone(), opts);
+}
+
+#[test]
+fn patch_canonicalize() {
+    let text = b"--- a/file\t2020-01-01\n+++ b/file\t2020-01-02\n\
+@@ -5 +5 @@\n-e\n+E\n\
+@@ -1,2 +1,2 @@\n-a\n+A\n b\n";
+    let patch = Patch::from_bytes(text).unwrap();
+    let canonical = patch.canonicalize().unwrap();
+    assert_eq!(
+        canonical.as_slice(),
+        b"@@ -1,2 +1,2 @@\n-a\n+A\n b\n@@ -5,1 +5,1 @@\n-e\n+E\n"
+    );
+    assert_eq!(canonical.canonicalize().unwrap(), canonical);
+
+    let mut base = MMFile::from_bytes(b"a\nb\nc\nd\ne\n");
+    let mut canonical = canonical;
+    assert_eq!(
+        base.apply_patch(&mut canonical).unwrap().as_slice(),
+        b"A\nb\nc\nd\nE\n"
+    );
+}
+
+#[cfg(feature = "git-header")]
+#[test]
+fn git_diff_header() {
+    use crate::GitHeader;
+
+    let old = MMFile::from_bytes(b"hello\n");
+    let new = MMFile::from_bytes(b"hello\nworld\n");
+    let opts = DiffOptions::new().git_header(GitHeader::new("src/a.txt", "src/a.txt"));
+    let patch = old.compute_patch_with(&new, &opts).unwrap();
+    let text = patch.to_string();
+    assert!(text.starts_with(
+        "diff --git a/src/a.txt b/src/a.txt\n\
+         index ce013625030ba8dba906f756967f9e9ca394464a..\
+         94954abda49de8615a048f8d2e64b5de848e27a1 100644\n\
+         --- a/src/a.txt\n\
+         +++ b/src/a.txt\n\
+         @@ -1,1 +1,2 @@\n"
+    ));
+    assert_eq!(patch.hunk_count(), 1);
+    let unified = old.diff_unified(&new, &opts, NonUtf8::Lossy).unwrap();
+    assert_eq!(unified, text);
+
+    let empty = MMFile::new();
+    let opts = DiffOptions::new().git_header(GitHeader::created("new.txt"));
+    let text = empty.compute_patch_with(&new, &opts).unwrap().to_string();
+    assert!(text.starts_with(
+        "diff --git a/new.txt b/new.txt\nnew file mode 100644\nindex 0000000000000000000000000000000000000000..94954abda49de8615a048f8d2e64b5de848e27a1\n--- /dev/null\n+++ b/new.txt\n"
+    ));
+
+    // a mode change alone still gets a header
+    let opts =
+        DiffOptions::new().git_header(GitHeader::new("run.sh", "run.sh").modes(0o100644, 0o100755));
+    let text = old
+        .diff_unified(&old.clone(), &opts, NonUtf8::Lossy)
+        .unwrap();
+    assert_eq!(
+        text,
+        "diff --git a/run.sh b/run.sh\nold mode 100644\nnew mode 100755\n"
+    );
+}
+
+#[test]
+fn diff_file_labels() {
+    let mut old = MMFile::from_bytes(b"a\nb\n");
+    let new = MMFile::from_bytes(b"a\nc\n");
+    let opts = DiffOptions::new().labels("a/file.txt", "b/file.txt");
+    let text = old.diff_unified(&new, &opts, NonUtf8::Lossy).unwrap();
+    assert_eq!(
+        text,
+        "--- a/file.txt\n+++ b/file.txt\n@@ -1,2 +1,2 @@\n a\n-b\n+c\n"
+    );
+    let mut patch = old.compute_patch_with(&new, &opts).unwrap();
+    assert_eq!(patch.to_string(), text);
+    assert_eq!(old.apply_patch(&mut patch).unwrap(), new);
+
+    let unchanged = old
+        .diff_unified(&old.clone(), &opts, NonUtf8::Lossy)
+        .unwrap();
+    assert!(unchanged.is_empty());
+}
+
+#[test]
+fn diff_timestamps() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let old = MMFile::from_bytes(b"a\n");
+    let new = MMFile::from_bytes(b"b\n");
+    let opts = DiffOptions::new().labels("a/f", "b/f").timestamps(
+        UNIX_EPOCH + Duration::new(1014335439, 942229878),
+        UNIX_EPOCH + Duration::from_secs(951782400),
+    );
+    let patch = old.compute_patch_with(&new, &opts).unwrap();
+    assert!(patch.to_string().starts_with(
+        "--- a/f\t2002-02-21 23:50:39.942229878 +0000\n\
+         +++ b/f\t2000-02-29 00:00:00.000000000 +0000\n@@"
+    ));
+    let (old_label, new_label) = patch.file_labels().unwrap();
+    assert_eq!(old_label.name, "a/f");
+    assert_eq!(
+        old_label.timestamp.as_deref(),
+        Some("2002-02-21 23:50:39.942229878 +0000")
+    );
+    assert_eq!(new_label.name, "b/f");
+
+    let patch = Patch::from_bytes(b"--- old\n+++ new\n@@ -1 +1 @@\n-a\n+b\n").unwrap();
+    let (old_label, new_label) = patch.file_labels().unwrap();
+    assert_eq!(
+        (old_label.name.as_str(), old_label.timestamp),
+        ("old", None)
+    );
+    assert_eq!(new_label.name, "new");
+    assert!(Patch::from_bytes(b"@@ -1 +1 @@\n-a\n+b\n")
+        .unwrap()
+        .file_labels()
+        .is_none());
+}
+
+#[test]
+fn signature_sync_roundtrip() {
+    use crate::signature::{apply_delta, delta, signature, Signature};
+
+    let mut old = MMFile::from_bytes(&b"0123456789".repeat(100));
+    let mut data = old.as_slice().to_vec();
+    data.splice(500..510, b"changed".iter().copied());
+    let new = MMFile::from_bytes(&data);
+
+    let sent = signature(&mut old, 32).unwrap().to_bytes();
+    let received = Signature::from_bytes(&sent).unwrap();
+    assert_eq!(received, old.signature(32).unwrap());
+    let patch = delta(&received, &new).unwrap();
+    let patch = BinaryPatch::from_bytes(patch.as_slice()).unwrap();
+    assert_eq!(apply_delta(&mut old, &patch).unwrap(), new);
+
+    assert!(Signature::from_bytes(&sent[..sent.len() - 3]).is_err());
+    assert!(Signature::from_bytes(b"garbage").is_err());
+}
+
+#[test]
+fn diff_hunks_stream() {
+    let old: Vec<u8> = (0..200)
+        .flat_map(|i| format!("line {}\n", i).into_bytes())
+        .collect();
+    let new = String::from_utf8(old.clone())
+        .unwrap()
+        .replace("line 1", "LINE 1")
+        .into_bytes();
+    let old = MMFile::from_bytes(&old);
+    let new = MMFile::from_bytes(&new);
+    let opts = DiffOptions::new();
+    let expected = old.diff_hunks(&new, &opts).unwrap();
+    assert!(expected.len() > 2);
+
+    let streamed = old
+        .diff_hunks_stream(&new, &opts, 1, |hunks| hunks.collect::<Vec<_>>())
+        .unwrap();
+    assert_eq!(streamed.len(), expected.len());
+    for (s, e) in streamed.iter().zip(expected.iter()) {
+        assert_eq!(s.header, e.header);
+        assert_eq!(s.changes.len(), e.changes.len());
+    }
+
+    // stopping early ends the diff without an error
+    let first = old
+        .diff_hunks_stream(&new, &opts, 1, |mut hunks| hunks.next())
+        .unwrap();
+    assert_eq!(first.unwrap().header, expected[0].header);
+}
+
+#[test]
+fn diff_events() {
+    let old = MMFile::from_bytes(b"a\nb\nc");
+    let new = MMFile::from_bytes(b"a\nB\nc");
+    let mut events = Vec::new();
+    old.diff_events(&new, &DiffOptions::new(), |event| {
+        events.push(match event {
+            DiffEvent::HunkStart(h) => format!("@{},{}", h.old_start, h.old_len),
+            DiffEvent::Context(line) => format!(" {}", String::from_utf8_lossy(line)),
+            DiffEvent::Removed(line) => format!("-{}", String::from_utf8_lossy(line)),
+            DiffEvent::Added(line) => format!("+{}", String::from_utf8_lossy(line)),
+        })
+    })
+    .unwrap();
+    assert_eq!(events, vec!["@1,3", " a\n", "-b\n", "+B\n", " c"]);
+
+    let result = old.diff_events(&new, &DiffOptions::new(), |_| panic!("boom"));
+    assert!(result.unwrap_err().to_string().contains("boom"));
+}
+
+#[test]
+fn diff_raw_tagged() {
+    let old = MMFile::from_bytes(b"a\nb");
+    let new = MMFile::from_bytes(b"a\nc");
+    let mut buffers = Vec::new();
+    old.diff_raw_tagged(&new, |kind, buf| buffers.push((kind, buf.to_vec())))
+        .unwrap();
+    assert_eq!(
+        buffers[0],
+        (BufferKind::HunkHeader, b"@@ -1,2 +1,2 @@\n".to_vec())
+    );
+    assert_eq!(
+        buffers[1],
+        (BufferKind::Prefix(ChangeTag::Equal), b" ".to_vec())
+    );
+    assert_eq!(buffers[2], (BufferKind::Content, b"a\n".to_vec()));
+    assert_eq!(
+        buffers[3],
+        (BufferKind::Prefix(ChangeTag::Delete), b"-".to_vec())
+    );
+    assert_eq!(buffers[4], (BufferKind::Content, b"b".to_vec()));
+    assert_eq!(buffers[5].0, BufferKind::NoNewlineMarker);
+    assert_eq!(buffers.len(), 9);
+}
+
+#[test]
+fn patch_hunks() {
+    let old = MMFile::from_bytes(b"a\nb\nc\nd\ne\nf\ng\nh\ni\nj\n");
+    let new = MMFile::from_bytes(b"A\nb\nc\nd\ne\nf\ng\nh\ni\nJ\n");
+    let opts = DiffOptions::new().context_len(1);
+    let patch = old.compute_patch_with(&new, &opts).unwrap();
+    let hunks = patch.hunks().unwrap();
+    assert_eq!(hunks.len(), patch.hunk_count());
+    let expected = old.diff_hunks(&new, &opts).unwrap();
+    for (parsed, diffed) in hunks.iter().zip(expected.iter()) {
+        assert_eq!(parsed.header, diffed.header);
+        assert_eq!(parsed.old_lines(), diffed.old_lines());
+        let lines = |h: &Hunk| {
+            h.changes
+                .iter()
+                .map(|c| (c.tag, c.line.clone(), c.old_index))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(parsed), lines(diffed));
+    }
+    assert_eq!(hunks[1].changes[2].line, b"J\n");
+    assert_eq!(Patch::from_bytes(b"").unwrap().hunks().unwrap().len(), 0);
+}
+
+#[test]
+fn diff_whole_file_context() {
+    let mut old = MMFile::from_bytes(b"1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n");
+    let new = MMFile::from_bytes(b"one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n");
+    let opts = DiffOptions::new().whole_file(true);
+    let hunks = old.diff_hunks(&new, &opts).unwrap();
+    assert_eq!(hunks.len(), 1);
+    assert_eq!(hunks[0].old_lines(), 0..10);
+    assert_eq!(hunks[0].new_lines(), 0..10);
+    assert_eq!(hunks[0].changes.len(), 12);
+
+    let mut patch = old.compute_patch_with(&new, &opts).unwrap();
+    assert_eq!(old.apply_patch(&mut patch).unwrap(), new);
+    assert!(old.diff_hunks(&old.clone(), &opts).unwrap().is_empty());
+}
+
+#[test]
+fn patch_status() {
+    let mut old = MMFile::from_bytes(b"a\nb\nc\nd\n");
+    let mut new = MMFile::from_bytes(b"a\nB\nc\nd\n");
+    let mut patch = old.compute_patch(&new).unwrap();
+    assert_eq!(old.patch_status(&mut patch).unwrap(), PatchStatus::Applies);
+    assert_eq!(
+        new.patch_status(&mut patch).unwrap(),
+        PatchStatus::AlreadyApplied
+    );
+    let mut other = MMFile::from_bytes(b"x\ny\nz\n");
+    assert_eq!(
+        other.patch_status(&mut patch).unwrap(),
+        PatchStatus::Conflicts
+    );
+}
+
+#[test]
+fn prepared_patch_many_targets() {
+    let old = MMFile::from_bytes(b"a\nb\nc\nd\ne\n");
+    let new = MMFile::from_bytes(b"a\nB\nc\nd\ne\nf\n");
+    let prepared = PreparedPatch::new(old.compute_patch(&new).unwrap()).unwrap();
+    for _ in 0..3 {
+        assert_eq!(prepared.apply(&mut old.clone()).unwrap(), new);
+    }
+
+    // a target where the hunk has moved falls back to libxdiff
+    let mut shifted = MMFile::from_bytes(b"header\na\nb\nc\nd\ne\n");
+    assert_eq!(
+        prepared.apply(&mut shifted).unwrap().as_slice(),
+        b"header\na\nB\nc\nd\ne\nf\n"
+    );
+    assert!(prepared.apply(&mut MMFile::from_bytes(b"x\n")).is_err());
+}
+
+#[test]
+fn blocks_extend_from_reader() {
+    let data: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+    let mut blocks = MMBlocks::from_bytes(b"prefix");
+    let read = blocks.extend_from_reader(&data[..], 4096).unwrap();
+    assert_eq!(read, data.len());
+    assert_eq!(blocks.size(), data.len() + 6);
+    let file = blocks.to_mmfile();
+    assert_eq!(&file.as_slice()[..6], b"prefix");
+    assert_eq!(&file.as_slice()[6..], &data[..]);
+
+    let mut blocks = MMBlocks::new();
+    assert_eq!(blocks.extend_from_reader(&b""[..], 16).unwrap(), 0);
+    assert_eq!(blocks.size(), 0);
+    // the block reserved for a read that found nothing is freed
+    assert_eq!(blocks.block_count(), 0);
+    assert!(blocks.extend_from_reader(&b"x"[..], 0).is_err());
+
+    struct Overreporting;
+    impl std::io::Read for Overreporting {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            Ok(buf.len() + 1)
+        }
+    }
+    let mut blocks = MMBlocks::from_bytes(b"kept");
+    assert!(blocks.extend_from_reader(Overreporting, 8).is_err());
+    assert_eq!(blocks.to_mmfile().as_slice(), b"kept");
+}
+
+#[test]
+fn mmfile_into_blocks() {
+    let file = MMFile::from_bytes(b"hello ");
+    let ptr = file.as_slice().as_ptr();
+    let mut blocks = file.into_blocks();
+    assert_eq!(blocks.block_count(), 1);
+    assert_eq!(
+        crate::mmblocks::block_slices(&blocks.inner)
+            .next()
+            .unwrap()
+            .as_ptr(),
+        ptr
+    );
+    assert_eq!(blocks.write_buf(b"world"), 0);
+    assert_eq!(blocks.to_mmfile().as_slice(), b"hello world");
+}
+
+#[test]
+fn mmfile_split_off() {
+    let mut file = MMFile::from_bytes(b"first chunk|second chunk");
+    let tail = file.split_off(12);
+    assert_eq!(file.as_slice(), b"first chunk|");
+    assert_eq!(tail.as_slice(), b"second chunk");
+    assert_eq!(file.size(), 12);
+
+    let all = file.split_off(0);
+    assert_eq!(file.as_slice(), b"");
+    assert_eq!(all.as_slice(), b"first chunk|");
+    assert!(file.split_off(0).as_slice().is_empty());
+}
+
+#[test]
+#[should_panic]
+fn mmfile_split_off_out_of_bounds() {
+    MMFile::from_bytes(b"abc").split_off(4);
+}
+
+#[test]
+fn mmfile_concat() {
+    let parts = [
+        MMFile::from_bytes(b"one\n"),
+        MMFile::new(),
+        MMFile::from_bytes(b"two\n"),
+    ];
+    let joined = MMFile::concat(&parts);
+    assert!(joined.is_compact());
+    assert_eq!(joined.as_slice(), b"one\ntwo\n");
+    assert!(MMFile::concat(&[]).as_slice().is_empty());
+
+    let mut blocks = MMBlocks::new();
+    for part in parts {
+        blocks.push_file(part);
+    }
+    assert_eq!(blocks.block_count(), 2);
+    blocks.write_buf(b"three\n");
+    assert_eq!(blocks.to_mmfile().as_slice(), b"one\ntwo\nthree\n");
+}
+
+#[test]
+fn mmfile_clone_copies_on_write() {
+    let mut original = MMFile::from_bytes(b"hello\n");
+    let mut copy = original.clone();
+    assert_eq!(original.as_slice().as_ptr(), copy.as_slice().as_ptr());
+
+    copy.as_slice_mut()[0] = b'j';
+    assert_eq!(original.as_slice(), b"hello\n");
+    assert_eq!(copy.as_slice(), b"jello\n");
+    assert_ne!(original.as_slice().as_ptr(), copy.as_slice().as_ptr());
+
+    let shared = original.clone();
+    drop(shared);
+    // the last remaining file mutates its buffer in place
+    let ptr = original.as_slice().as_ptr();
+    original.as_slice_mut()[0] = b'c';
+    assert_eq!(original.as_slice(), b"cello\n");
+    assert_eq!(original.as_slice().as_ptr(), ptr);
+
+    let mut split = original.clone();
+    let tail = split.split_off(1);
+    assert_eq!(
+        (split.as_slice(), tail.as_slice()),
+        (&b"c"[..], &b"ello\n"[..])
+    );
+    assert_eq!(original.as_slice(), b"cello\n");
+}
+
+#[test]
+fn shared_mmfile_concurrent_diffs() {
+    let base = SharedMMFile::new(MMFile::from_bytes(b"a\nb\nc\n"));
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let base = &base;
+                scope.spawn(move || {
+                    let new =
+                        SharedMMFile::from(MMFile::from_bytes(format!("a\n{}\nc\n", i).as_bytes()));
+                    let mut patch = base.compute_patch_with(&new, &DiffOptions::new()).unwrap();
+                    let hunks = base.diff_hunks(&new, &DiffOptions::new()).unwrap();
+                    assert_eq!(hunks.len(), 1);
+                    base.to_mmfile().apply_patch(&mut patch).unwrap()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(
+                handle.join().unwrap().as_slice(),
+                format!("a\n{}\nc\n", i).as_bytes()
+            );
+        }
+    });
+    let copy = base.clone();
+    assert_eq!(copy.as_slice().as_ptr(), base.as_slice().as_ptr());
+    let mut file = copy.to_mmfile();
+    file.as_slice_mut()[0] = b'z';
+    assert_eq!(base.as_slice(), b"a\nb\nc\n");
+}
+
+#[test]
+fn shared_mmfile_concurrent_compare() {
+    let base = SharedMMFile::new(MMFile::from_bytes(b"a\nb\nc\n"));
+    let hash = MMFile::from_bytes(b"a\nb\nc\n").content_hash();
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            let base = &base;
+            scope.spawn(move || {
+                for _ in 0..100 {
+                    let copy = base.clone();
+                    assert_eq!(base.content_hash(), hash);
+                    assert!(*base == copy);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn diff_shared_references() {
+    let files = [
+        MMFile::from_bytes(b"a\nb\nc\n"),
+        MMFile::from_bytes(b"a\nB\nc\n"),
+    ];
+    let hunks = files[0].diff_hunks(&files[1], &DiffOptions::new()).unwrap();
+    assert_eq!(hunks.len(), 1);
+    // the same file can be both sides
+    assert!(files[0].compute_patch(&files[0]).unwrap().is_empty());
+    assert!(!files[0].differs(&files[0], &DiffOptions::new()).unwrap());
+    // iteration state isn't left behind between diffs
+    let patch = files[0].compute_patch(&files[1]).unwrap();
+    assert_eq!(patch, files[0].compute_patch(&files[1]).unwrap());
+}
+
+#[test]
+fn file_contained_in() {
+    let opts = DiffOptions::new();
+    let base = MMFile::from_bytes(b"a\nb\n");
+    let extended = MMFile::from_bytes(b"a\nx\nb\ny\n");
+    assert!(base.is_contained_in(&extended, &opts).unwrap());
+    assert!(!extended.is_contained_in(&base, &opts).unwrap());
+    assert!(base.is_contained_in(&base, &opts).unwrap());
+    assert!(MMFile::new().is_contained_in(&base, &opts).unwrap());
+    let reordered = MMFile::from_bytes(b"b\na\n");
+    assert!(!base.is_contained_in(&reordered, &opts).unwrap());
+}
+
+#[test]
+fn library_version() {
+    let version = version();
+    assert_eq!(version.libxdiff, "0.23");
+    assert_eq!(version.crate_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(version.features.contains(&"std"), cfg!(feature = "std"));
+    assert!(version.to_string().starts_with(&format!(
+        "libxdiff 0.23 (crate {}; features: ",
+        version.crate_version
+    )));
 }
@@ -0,0 +1,124 @@
+use core::fmt::{self, Display, Formatter, Write};
+
+use crate::{header::file_header, DiffOptions, Error, MMFile, Patch};
+
+/// How bytes that are not valid UTF-8 are handled when converting file or
+/// patch contents to a `String`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NonUtf8 {
+    /// Replace each invalid sequence with U+FFFD
+    #[default]
+    Lossy,
+    /// Write each invalid byte as a C-style `\xNN` escape
+    Escape,
+    /// Fail the conversion
+    Error,
+}
+
+/// Number of bytes of content shown by the `Debug` impls of files
+pub(crate) const PREVIEW_LEN: usize = 64;
+
+/// Lossy preview of the first [`PREVIEW_LEN`] bytes spread over `chunks`,
+/// ending in an ellipsis if anything was cut off
+pub(crate) fn preview<'a>(chunks: impl Iterator<Item = &'a [u8]>) -> String {
+    let mut head = Vec::with_capacity(PREVIEW_LEN);
+    let mut truncated = false;
+    for chunk in chunks {
+        let take = (PREVIEW_LEN - head.len()).min(chunk.len());
+        head.extend_from_slice(&chunk[..take]);
+        if take < chunk.len() {
+            truncated = true;
+            break;
+        }
+    }
+    if truncated {
+        // don't show a character split by the cut as invalid
+        if let Err(e) = core::str::from_utf8(&head) {
+            if e.error_len().is_none() {
+                head.truncate(e.valid_up_to());
+            }
+        }
+    }
+    let mut out = String::from_utf8_lossy(&head).into_owned();
+    if truncated {
+        out.push('…');
+    }
+    out
+}
+
+/// Convert bytes to a `String` according to `policy`
+pub(crate) fn decode(mut bytes: &[u8], policy: NonUtf8) -> Result<String, Error> {
+    let mut out = String::with_capacity(bytes.len());
+    loop {
+        match core::str::from_utf8(bytes) {
+            Ok(valid) => {
+                out.push_str(valid);
+                return Ok(out);
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                // SAFETY: from_utf8 validated this prefix
+                out.push_str(unsafe { core::str::from_utf8_unchecked(valid) });
+                let invalid_len = e.error_len().unwrap_or(rest.len());
+                match policy {
+                    NonUtf8::Lossy => out.push(char::REPLACEMENT_CHARACTER),
+                    NonUtf8::Escape => {
+                        for b in &rest[..invalid_len] {
+                            let _ = write!(out, "\\x{:02x}", b);
+                        }
+                    }
+                    // everything before the first invalid byte was copied as-is
+                    NonUtf8::Error => {
+                        return Err(Error::Invalid(format!(
+                            "invalid UTF-8 at byte {}",
+                            out.len()
+                        )))
+                    }
+                }
+                bytes = &rest[invalid_len..];
+            }
+        }
+    }
+}
+
+impl MMFile {
+    /// Get the contents as a `String`, handling invalid UTF-8 per `policy`
+    pub fn to_string_with(&self, policy: NonUtf8) -> Result<String, Error> {
+        decode(self.as_slice(), policy)
+    }
+
+    /// Compute the unified diff to turn self into other as a `String`,
+    /// handling invalid UTF-8 per `policy`
+    pub fn diff_unified(
+        &self,
+        other: &MMFile,
+        opts: &DiffOptions,
+        policy: NonUtf8,
+    ) -> Result<String, Error> {
+        let mut out = Vec::new();
+        // SAFETY: the callback only pushes to a Vec and doesn't panic
+        unsafe {
+            self.diff_records(other, opts, |record| {
+                record.iter().for_each(|buf| out.extend_from_slice(buf));
+                0
+            })
+        }?;
+        let mut text = file_header(opts, self.as_slice(), other.as_slice(), !out.is_empty());
+        text.extend_from_slice(&out);
+        decode(&text, policy)
+    }
+}
+
+impl Patch {
+    /// Get the patch text as a `String`, handling invalid UTF-8 per `policy`
+    pub fn to_string_with(&self, policy: NonUtf8) -> Result<String, Error> {
+        decode(self.as_slice(), policy)
+    }
+}
+
+impl Display for Patch {
+    /// Writes the patch text, replacing invalid UTF-8 as [`NonUtf8::Lossy`]
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(self.as_slice()))
+    }
+}
@@ -0,0 +1,152 @@
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+
+use crate::{options::RecordKey, DiffOptions, Error, LineChange, MMFile};
+
+/// Splits bytes into the tokens a diff compares, such as words, sentences or
+/// CSV fields
+pub trait Tokenizer {
+    /// The byte range of each token in `bytes`, in order and not
+    /// overlapping. Bytes outside every token are ignored when comparing.
+    fn tokenize(&self, bytes: &[u8]) -> Vec<Range<usize>>;
+}
+
+impl<F: Fn(&[u8]) -> Vec<Range<usize>>> Tokenizer for F {
+    fn tokenize(&self, bytes: &[u8]) -> Vec<Range<usize>> {
+        self(bytes)
+    }
+}
+
+/// Splits text into words, runs of whitespace, and single punctuation bytes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Words;
+
+impl Tokenizer for Words {
+    fn tokenize(&self, bytes: &[u8]) -> Vec<Range<usize>> {
+        // bytes of the same class join into one token, except punctuation
+        let class = |b: u8| {
+            if b.is_ascii_whitespace() {
+                0
+            } else if b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii() {
+                1
+            } else {
+                2
+            }
+        };
+        let mut tokens: Vec<Range<usize>> = Vec::new();
+        for (i, b) in bytes.iter().enumerate() {
+            match tokens.last_mut() {
+                Some(last) if class(bytes[last.start]) == class(*b) && class(*b) != 2 => {
+                    last.end = i + 1
+                }
+                _ => tokens.push(i..i + 1),
+            }
+        }
+        tokens
+    }
+}
+
+/// Splits bytes into fields ending at each separator, such as `b','` for
+/// CSV. Each field includes its separator.
+#[derive(Debug, Clone, Copy)]
+pub struct Separated(pub u8);
+
+impl Tokenizer for Separated {
+    fn tokenize(&self, bytes: &[u8]) -> Vec<Range<usize>> {
+        let mut tokens = Vec::new();
+        let mut start = 0;
+        for field in bytes.split_inclusive(|b| *b == self.0) {
+            tokens.push(start..start + field.len());
+            start += field.len();
+        }
+        tokens
+    }
+}
+
+/// Tokens changed by one contiguous edit, as 0-based token ranges and the
+/// byte ranges they cover. An empty range marks where tokens were inserted
+/// or deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenChange {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+    pub old_bytes: Range<usize>,
+    pub new_bytes: Range<usize>,
+}
+
+/// Compute the tokens changed between `old` and `new`, as split by
+/// `tokenizer`, with libxdiff. Context settings in `opts` are ignored.
+pub fn diff_tokens<T: Tokenizer + ?Sized>(
+    old: &[u8],
+    new: &[u8],
+    tokenizer: &T,
+    opts: &DiffOptions,
+) -> Result<Vec<TokenChange>, Error> {
+    let old_tokens = tokenizer.tokenize(old);
+    let new_tokens = tokenizer.tokenize(new);
+    let mut ids = BTreeMap::new();
+    let old_file = interned_file(old_tokens.iter().map(|t| &old[t.clone()]), &mut ids);
+    let new_file = interned_file(new_tokens.iter().map(|t| &new[t.clone()]), &mut ids);
+    let changes = old_file.changed_lines(&new_file, opts)?;
+    Ok(changes
+        .into_iter()
+        .map(|c| TokenChange {
+            old_bytes: byte_range(&old_tokens, &c.old, old.len()),
+            new_bytes: byte_range(&new_tokens, &c.new, new.len()),
+            old: c.old,
+            new: c.new,
+        })
+        .collect())
+}
+
+/// Compute the lines changed between `old` and `new`, comparing each line
+/// by its key
+pub(crate) fn keyed_changes(
+    old: &[u8],
+    new: &[u8],
+    key: &RecordKey,
+    opts: &DiffOptions,
+) -> Result<Vec<LineChange>, Error> {
+    let lines = |bytes| {
+        <[u8]>::split_inclusive(bytes, |b| *b == b'\n')
+            .map(|line| (key.0)(line))
+            .collect::<Vec<_>>()
+    };
+    let (old_keys, new_keys) = (lines(old), lines(new));
+    let mut ids = BTreeMap::new();
+    let old_file = interned_file(old_keys.iter(), &mut ids);
+    let new_file = interned_file(new_keys.iter(), &mut ids);
+    old_file.changed_lines(&new_file, opts)
+}
+
+/// A file holding one line per key, each line the id the key was interned
+/// as, so libxdiff compares exactly the key sequences
+pub(crate) fn interned_file<K, I>(keys: I, ids: &mut BTreeMap<K, usize>) -> MMFile
+where
+    K: Ord,
+    I: Iterator<Item = K>,
+{
+    let mut data = Vec::new();
+    for key in keys {
+        let next = ids.len();
+        let id = *ids.entry(key).or_insert(next);
+        data.extend_from_slice(id.to_string().as_bytes());
+        data.push(b'\n');
+    }
+    MMFile::from_bytes(&data)
+}
+
+/// The bytes covered by a range of tokens. An empty range covers nothing,
+/// at the start of the token it precedes.
+pub(crate) fn byte_range(
+    tokens: &[Range<usize>],
+    range: &Range<usize>,
+    len: usize,
+) -> Range<usize> {
+    let at = |i: usize| tokens.get(i).map_or(len, |t| t.start);
+    if range.is_empty() {
+        at(range.start)..at(range.start)
+    } else {
+        tokens[range.start].start..tokens[range.end - 1].end
+    }
+}
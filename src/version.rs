@@ -0,0 +1,58 @@
+use core::fmt::{self, Display, Formatter};
+
+/// Version of the libxdiff sources libxdiff-sys builds, which don't export
+/// their version
+const LIBXDIFF_VERSION: &str = "0.23";
+
+/// Every cargo feature of this crate and whether it was enabled
+const FEATURES: &[(&str, bool)] = &[
+    ("std", cfg!(feature = "std")),
+    ("ffi", cfg!(feature = "ffi")),
+    ("alloc-stats", cfg!(feature = "alloc-stats")),
+    ("arbitrary", cfg!(feature = "arbitrary")),
+    ("diffy", cfg!(feature = "diffy")),
+    ("encoding", cfg!(feature = "encoding")),
+    ("gix", cfg!(feature = "gix")),
+    ("pure-rust", cfg!(feature = "pure-rust")),
+    ("similar", cfg!(feature = "similar")),
+    ("tracing", cfg!(feature = "tracing")),
+    ("xxhash", cfg!(feature = "xxhash")),
+];
+
+/// The diff engine and build configuration in use, as returned by
+/// [`version`]. Displays as `libxdiff 0.23 (crate 0.2.0; features: std, ffi)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    /// Version of the underlying libxdiff C library
+    pub libxdiff: &'static str,
+    /// Version of this crate
+    pub crate_version: &'static str,
+    /// Cargo features this crate was built with
+    pub features: Vec<&'static str>,
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "libxdiff {} (crate {}; features: {})",
+            self.libxdiff,
+            self.crate_version,
+            self.features.join(", ")
+        )
+    }
+}
+
+/// Report the libxdiff version and the features this crate was built with,
+/// for logging or for gating behavior on the build
+pub fn version() -> Version {
+    Version {
+        libxdiff: LIBXDIFF_VERSION,
+        crate_version: env!("CARGO_PKG_VERSION"),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+    }
+}